@@ -0,0 +1,97 @@
+//! Compare serving stored metadata bytes against decoding and re-encoding them.
+//!
+//! Run with `cargo bench --bench get_key`.
+
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use actix_service::Service;
+use actix_web::{http::StatusCode, test, web, App, HttpResponse};
+use prost::Message;
+
+use keyserver::{
+    crypto::Address,
+    db::{AsyncDB, Database, KeyDB},
+    models::address_metadata::{AddressMetadata, Payload},
+    net::{errors::ServerError, get_key},
+};
+
+const REQUESTS: u32 = 20_000;
+
+// GET handler which decodes and re-encodes the stored metadata, for comparison
+async fn get_key_decoded<D: Database>(
+    addr_str: web::Path<String>,
+    db_data: web::Data<AsyncDB<D>>,
+) -> Result<HttpResponse, ServerError> {
+    let addr = Address::decode(&addr_str)?;
+    let metadata = db_data.get(addr).await?.ok_or(ServerError::NotFound)?;
+    let mut raw_metadata = Vec::with_capacity(metadata.encoded_len());
+    metadata.encode(&mut raw_metadata).unwrap();
+    Ok(HttpResponse::Ok().body(raw_metadata))
+}
+
+fn metadata() -> AddressMetadata {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+    let payload = Payload {
+        timestamp,
+        ttl: 3000,
+        entries: vec![],
+    };
+    let mut serialized_payload = Vec::with_capacity(payload.encoded_len());
+    payload.encode(&mut serialized_payload).unwrap();
+    AddressMetadata {
+        pub_key: vec![],
+        serialized_payload,
+        signature: vec![],
+        scheme: 1,
+    }
+}
+
+fn main() {
+    actix_rt::System::new("bench_get_key").block_on(async {
+        // Init routes
+        let _ = std::fs::remove_dir_all("./test_db/bench_get");
+        let key_db = AsyncDB::new(KeyDB::try_new("./test_db/bench_get").unwrap(), 16);
+        let mut app = test::init_service(
+            App::new()
+                .data(key_db.clone())
+                .route("/raw/{addr}", web::get().to(get_key::<KeyDB>))
+                .route("/decoded/{addr}", web::get().to(get_key_decoded::<KeyDB>)),
+        )
+        .await;
+
+        // Put metadata
+        let addr = Address {
+            body: vec![1; 20],
+            ..Default::default()
+        };
+        let address_base58 = addr.encode().unwrap();
+        let metadata = metadata();
+        let mut metadata_raw = Vec::with_capacity(metadata.encoded_len());
+        metadata.encode(&mut metadata_raw).unwrap();
+        key_db.inner().put(&addr, &metadata).unwrap();
+
+        for path in &["raw", "decoded"] {
+            let start = Instant::now();
+            for _ in 0..REQUESTS {
+                let req = test::TestRequest::get()
+                    .uri(&format!("/{}/{}", path, address_base58))
+                    .to_request();
+                let resp = app.call(req).await.unwrap();
+                assert_eq!(resp.status(), StatusCode::OK);
+                let body = test::read_body(resp).await;
+                assert_eq!(&body[..], &metadata_raw[..]);
+            }
+            let elapsed = start.elapsed();
+            println!(
+                "GET /keys/{{addr}} ({}): {:.0} req/s",
+                path,
+                f64::from(REQUESTS) / elapsed.as_secs_f64()
+            );
+        }
+
+        let _ = std::fs::remove_dir_all("./test_db/bench_get");
+    });
+}
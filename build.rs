@@ -1,10 +1,3 @@
 fn main() {
-    prost_build::compile_protos(
-        &[
-            "src/proto/addressmetadata.proto",
-            "src/proto/paymentrequest.proto",
-        ],
-        &["src/"],
-    )
-    .unwrap();
+    prost_build::compile_protos(&["src/proto/paymentrequest.proto"], &["src/"]).unwrap();
 }
@@ -1 +1 @@
-protoc -I ../src/proto --python_out=. ../src/proto/*
\ No newline at end of file
+protoc -I ../src/proto -I ../keyserver-core/proto --python_out=. ../src/proto/paymentrequest.proto ../keyserver-core/proto/addressmetadata.proto
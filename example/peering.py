@@ -14,7 +14,7 @@ from time import time, sleep
 
 
 # Generate protobuf code
-# protoc -I=../src/proto --python_out=. ../src/proto/*.proto
+# protoc -I=../src/proto -I=../keyserver-core/proto --python_out=. ../src/proto/*.proto ../keyserver-core/proto/*.proto
 
 # Run bitcoind in regtest mode
 # bitcoind -daemon -regtest -zmqpubrawtx=tcp://127.0.0.1:28332 -rpcallowip=0.0.0.0/0 -server  \
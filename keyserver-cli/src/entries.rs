@@ -0,0 +1,131 @@
+use std::{
+    fs::{self, File},
+    io,
+    path::Path,
+};
+
+use serde_derive::Deserialize;
+
+use keyserver_client::models::address_metadata::{Entry, Header};
+
+use super::to_io_error;
+
+#[derive(Deserialize)]
+struct HeaderSpec {
+    name: String,
+    value: String,
+}
+
+#[derive(Deserialize)]
+struct EntrySpec {
+    kind: String,
+    #[serde(default)]
+    headers: Vec<HeaderSpec>,
+    data: Option<String>,
+    path: Option<String>,
+}
+
+/// Parse a header given as `NAME=VALUE`.
+pub fn parse_header(raw: &str) -> io::Result<Header> {
+    let mut parts = raw.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(value)) if !name.is_empty() => Ok(Header {
+            name: name.to_string(),
+            value: value.to_string(),
+        }),
+        _ => Err(to_io_error(format!("header {} is not NAME=VALUE", raw))),
+    }
+}
+
+/// Read entries from a JSON file, resolving data paths relative to it.
+pub fn from_json(path: &Path) -> io::Result<Vec<Entry>> {
+    let specs: Vec<EntrySpec> = serde_json::from_reader(File::open(path)?)?;
+    let base = path.parent().unwrap_or_else(|| Path::new("."));
+    specs
+        .into_iter()
+        .map(|spec| {
+            let entry_data = match (spec.data, spec.path) {
+                (Some(data), None) => data.into_bytes(),
+                (None, Some(data_path)) => fs::read(base.join(data_path))?,
+                _ => {
+                    return Err(to_io_error(format!(
+                        "entry of kind {} must have either data or a path",
+                        spec.kind
+                    )))
+                }
+            };
+            let headers = spec
+                .headers
+                .into_iter()
+                .map(|header| Header {
+                    name: header.name,
+                    value: header.value,
+                })
+                .collect();
+            Ok(Entry {
+                kind: spec.kind,
+                headers,
+                entry_data,
+            })
+        })
+        .collect()
+}
+
+/// Build the entry given by flags, if any data was given.
+pub fn from_flags(
+    kind: &str,
+    headers: &[&str],
+    data: Option<&str>,
+    file: Option<&str>,
+) -> io::Result<Option<Entry>> {
+    let entry_data = match (data, file) {
+        (Some(data), _) => data.as_bytes().to_vec(),
+        (None, Some(file)) => fs::read(file)?,
+        (None, None) => return Ok(None),
+    };
+    let headers = headers
+        .iter()
+        .map(|header| parse_header(header))
+        .collect::<io::Result<_>>()?;
+    Ok(Some(Entry {
+        kind: kind.to_string(),
+        headers,
+        entry_data,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_header() {
+        let header = parse_header("Type=Ego=Boost").unwrap();
+        assert_eq!(header.name, "Type");
+        assert_eq!(header.value, "Ego=Boost");
+        assert!(parse_header("Type").is_err());
+        assert!(parse_header("=Boost").is_err());
+    }
+
+    #[test]
+    fn test_from_json() {
+        let dir = Path::new("./test_db/cli_entries");
+        fs::create_dir_all(dir).unwrap();
+        fs::write(dir.join("avatar.png"), [1, 2, 3]).unwrap();
+        let entries_path = dir.join("entries.json");
+        fs::write(
+            &entries_path,
+            r#"[
+                {"kind": "text_utf8", "headers": [{"name": "Type", "value": "Bio"}], "data": "Hi"},
+                {"kind": "image", "path": "avatar.png"}
+            ]"#,
+        )
+        .unwrap();
+
+        let entries = from_json(&entries_path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].headers[0].value, "Bio");
+        assert_eq!(entries[0].entry_data, b"Hi".to_vec());
+        assert_eq!(entries[1].entry_data, vec![1, 2, 3]);
+    }
+}
@@ -0,0 +1,209 @@
+#[macro_use]
+extern crate clap;
+
+mod entries;
+mod network;
+mod rpc;
+mod wallet;
+
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{
+    fs,
+    io::{self, Write},
+    path::Path,
+};
+
+use clap::ArgMatches;
+use keyserver_client::{
+    metadata::{build_payload, key_address, sign_payload},
+    models::address_metadata::{AddressMetadata, Payload},
+    KeyserverClient,
+};
+use keyserver_core::{
+    authentication::validate,
+    ecdsa::{Secp256k1, Secp256k1PublicKey},
+    Address, Addressable, PublicKey,
+};
+use prost::Message;
+use secp256k1::{rand, SecretKey};
+
+use network::Network;
+use rpc::NodeClient;
+
+fn to_io_error<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err.to_string())
+}
+
+fn parse_network(matches: &ArgMatches) -> Network {
+    match matches.value_of("network") {
+        Some("testnet") => Network::Testnet,
+        Some("regnet") => Network::Regnet,
+        _ => Network::Mainnet,
+    }
+}
+
+fn decode_address(addr_str: &str) -> io::Result<Address> {
+    Address::decode(addr_str)
+        .map_err(|(cash_err, base58_err)| to_io_error(format!("{}, {}", cash_err, base58_err)))
+}
+
+fn read_metadata(path: &str) -> io::Result<AddressMetadata> {
+    AddressMetadata::decode(&fs::read(path)?[..]).map_err(to_io_error)
+}
+
+fn print_summary(metadata: &AddressMetadata) -> io::Result<()> {
+    let payload = Payload::decode(&metadata.serialized_payload[..]).map_err(to_io_error)?;
+    println!("public key: {}", hex::encode(&metadata.pub_key));
+    println!("timestamp: {}", payload.timestamp);
+    println!("ttl: {}", payload.ttl);
+    for entry in payload.entries {
+        println!("entry: {} ({} bytes)", entry.kind, entry.entry_data.len());
+        for header in entry.headers {
+            println!("    {}: {}", header.name, header.value);
+        }
+    }
+    Ok(())
+}
+
+// Write a secret to a new file only readable by its owner
+fn write_secret(path: &str, secret: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    options.open(path)?.write_all(secret.as_bytes())
+}
+
+/// Generate a key and print its address
+fn keygen(network: Network, matches: &ArgMatches) -> io::Result<()> {
+    let secp = secp256k1::Secp256k1::new();
+    let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+    let secret_hex = hex::encode(&secret_key[..]);
+    match matches.value_of("output") {
+        Some(path) => write_secret(path, &secret_hex)?,
+        None => println!("secret key: {}", secret_hex),
+    }
+    let addr = key_address(&public_key, network.into());
+    println!("public key: {}", hex::encode(&public_key.serialize()[..]));
+    println!("address: {}", addr.encode().map_err(to_io_error)?);
+    Ok(())
+}
+
+/// Build a payload from entries and sign it
+fn sign(network: Network, matches: &ArgMatches) -> io::Result<()> {
+    // Read secret key
+    let secret_hex = fs::read_to_string(matches.value_of("key").unwrap())?;
+    let secret_raw = hex::decode(secret_hex.trim()).map_err(to_io_error)?;
+    let secret_key = SecretKey::from_slice(&secret_raw).map_err(to_io_error)?;
+
+    // Collect entries from files then flags
+    let mut entries = Vec::new();
+    for path in matches.values_of("entries").into_iter().flatten() {
+        entries.extend(entries::from_json(Path::new(path))?);
+    }
+    let headers: Vec<&str> = matches.values_of("header").into_iter().flatten().collect();
+    if let Some(entry) = entries::from_flags(
+        matches.value_of("kind").unwrap(),
+        &headers,
+        matches.value_of("data"),
+        matches.value_of("file"),
+    )? {
+        entries.push(entry);
+    }
+
+    // Sign
+    let ttl = value_t!(matches, "ttl", i64).map_err(to_io_error)?;
+    let metadata = sign_payload(&build_payload(entries, ttl), &secret_key);
+    let mut raw_metadata = Vec::with_capacity(metadata.encoded_len());
+    metadata.encode(&mut raw_metadata).unwrap();
+    fs::write(matches.value_of("output").unwrap(), raw_metadata)?;
+
+    let public_key = secp256k1::PublicKey::from_slice(&metadata.pub_key).map_err(to_io_error)?;
+    let addr = key_address(&public_key, network.into());
+    println!("signed for {}", addr.encode().map_err(to_io_error)?);
+    Ok(())
+}
+
+/// Validate signed metadata against an address
+fn verify(matches: &ArgMatches) -> io::Result<()> {
+    let addr = decode_address(matches.value_of("address").unwrap())?;
+    let metadata = read_metadata(matches.value_of("input").unwrap())?;
+
+    // TODO: Support Schnorr
+    match metadata.scheme {
+        1 => validate::<Secp256k1>(&addr, &metadata).map_err(to_io_error)?,
+        scheme => {
+            return Err(to_io_error(format!(
+                "unsupported signature scheme {}",
+                scheme
+            )))
+        }
+    }
+    println!("valid");
+    print_summary(&metadata)
+}
+
+/// Fetch and verify the metadata for an address
+async fn get(keyserver_url: &str, matches: &ArgMatches<'_>) -> io::Result<()> {
+    let addr = decode_address(matches.value_of("address").unwrap())?;
+    let metadata = KeyserverClient::new()
+        .get_metadata(keyserver_url, &addr)
+        .await
+        .map_err(to_io_error)?;
+    match matches.value_of("output") {
+        Some(path) => {
+            let mut raw_metadata = Vec::with_capacity(metadata.encoded_len());
+            metadata.encode(&mut raw_metadata).unwrap();
+            fs::write(path, raw_metadata)
+        }
+        None => print_summary(&metadata),
+    }
+}
+
+/// Upload signed metadata, paying with the node's wallet
+async fn put(keyserver_url: &str, network: Network, matches: &ArgMatches<'_>) -> io::Result<()> {
+    let metadata = read_metadata(matches.value_of("input").unwrap())?;
+    let public_key = Secp256k1PublicKey::deserialize(&metadata.pub_key).map_err(to_io_error)?;
+    let addr = Address {
+        body: public_key.to_raw_address(),
+        network: network.into(),
+        ..Default::default()
+    };
+
+    let max_amount = match matches.value_of("max-amount") {
+        Some(max_amount) => max_amount.parse().map_err(to_io_error)?,
+        None => wallet::DEFAULT_MAX_AMOUNT,
+    };
+
+    let node_client = NodeClient::new(
+        matches.value_of("rpc-url").unwrap().to_string(),
+        matches.value_of("rpc-username").unwrap().to_string(),
+        matches.value_of("rpc-password").unwrap().to_string(),
+    );
+    KeyserverClient::new()
+        .put_metadata(keyserver_url, &addr, &metadata, |outputs| {
+            wallet::pay(&node_client, outputs, network, max_amount)
+        })
+        .await
+        .map_err(to_io_error)?;
+    println!("uploaded {}", addr.encode().map_err(to_io_error)?);
+    Ok(())
+}
+
+#[actix_rt::main]
+async fn main() -> io::Result<()> {
+    let yaml = load_yaml!("cli.yml");
+    let matches = clap::App::from_yaml(yaml).get_matches();
+    let keyserver_url = matches.value_of("keyserver").unwrap();
+    let network = parse_network(&matches);
+
+    match matches.subcommand() {
+        ("keygen", Some(sub_matches)) => keygen(network, sub_matches),
+        ("sign", Some(sub_matches)) => sign(network, sub_matches),
+        ("verify", Some(sub_matches)) => verify(sub_matches),
+        ("get", Some(sub_matches)) => get(keyserver_url, sub_matches).await,
+        ("put", Some(sub_matches)) => put(keyserver_url, network, sub_matches).await,
+        _ => unreachable!(),
+    }
+}
@@ -0,0 +1,29 @@
+use keyserver_core::Network as AddrNetwork;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Network {
+    Mainnet,
+    Testnet,
+    Regnet,
+}
+
+impl From<Network> for AddrNetwork {
+    fn from(network: Network) -> AddrNetwork {
+        match network {
+            Network::Mainnet => AddrNetwork::Main,
+            Network::Testnet => AddrNetwork::Test,
+            Network::Regnet => AddrNetwork::Regtest,
+        }
+    }
+}
+
+impl Network {
+    /// Chain name reported by `getblockchaininfo`.
+    pub fn chain_name(self) -> &'static str {
+        match self {
+            Network::Mainnet => "main",
+            Network::Testnet => "test",
+            Network::Regnet => "regtest",
+        }
+    }
+}
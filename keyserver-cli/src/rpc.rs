@@ -0,0 +1,92 @@
+use json_rpc::{clients::http::HttpConnector, prelude::*};
+use serde::de::DeserializeOwned;
+use serde_derive::Deserialize;
+use serde_json::Value;
+
+/// Wallet calls against a bitcoind node.
+pub struct NodeClient(HttpClient<HttpConnector>);
+
+#[derive(Debug)]
+pub enum NodeError {
+    Http(HttpError),
+    Rpc(RpcError),
+    Json(JsonError),
+    EmptyResponse,
+}
+
+/// Subset of the `getblockchaininfo` response.
+#[derive(Debug, Deserialize)]
+pub struct BlockchainInfo {
+    pub chain: String,
+}
+
+/// Subset of the `fundrawtransaction` response.
+#[derive(Debug, Deserialize)]
+pub struct FundedTx {
+    pub hex: String,
+}
+
+/// Subset of the `signrawtransactionwithwallet` response.
+#[derive(Debug, Deserialize)]
+pub struct SignedTx {
+    pub hex: String,
+    pub complete: bool,
+}
+
+impl NodeClient {
+    pub fn new(endpoint: String, username: String, password: String) -> Self {
+        NodeClient(HttpClient::new(endpoint, Some(username), Some(password)))
+    }
+
+    // Call a method and parse its result
+    async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Vec<Value>,
+    ) -> Result<T, NodeError> {
+        let request = self
+            .0
+            .build_request()
+            .method(method)
+            .params(params)
+            .finish()
+            .unwrap();
+        let response = self.0.send(request).await.map_err(NodeError::Http)?;
+        if response.is_error() {
+            return Err(NodeError::Rpc(response.error().unwrap()));
+        }
+        response
+            .into_result()
+            .ok_or(NodeError::EmptyResponse)?
+            .map_err(NodeError::Json)
+    }
+
+    pub async fn get_blockchain_info(&self) -> Result<BlockchainInfo, NodeError> {
+        self.call("getblockchaininfo", vec![]).await
+    }
+
+    /// Create an unsigned transaction without inputs paying the outputs, given as
+    /// `createrawtransaction` expects.
+    pub async fn create_raw_tx(&self, outputs: Value) -> Result<String, NodeError> {
+        self.call("createrawtransaction", vec![Value::Array(vec![]), outputs])
+            .await
+    }
+
+    /// Add inputs and change from the node's wallet.
+    pub async fn fund_raw_tx(&self, raw_tx: &str) -> Result<FundedTx, NodeError> {
+        self.call(
+            "fundrawtransaction",
+            vec![Value::String(raw_tx.to_string())],
+        )
+        .await
+    }
+
+    /// Sign the inputs with the node's wallet.
+    pub async fn sign_raw_tx(&self, raw_tx: &str) -> Result<SignedTx, NodeError> {
+        self.call(
+            "signrawtransactionwithwallet",
+            vec![Value::String(raw_tx.to_string())],
+        )
+        .await
+    }
+}
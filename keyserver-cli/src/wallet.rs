@@ -0,0 +1,171 @@
+use serde_json::{json, Map, Value};
+
+use keyserver_client::models::bip70::Output;
+use keyserver_core::{Address, HashType};
+
+use crate::{network::Network, rpc::NodeClient};
+
+/// Satoshis paid when no maximum is given, the price charged by keyservers.
+pub const DEFAULT_MAX_AMOUNT: u64 = 5;
+
+const OP_RETURN: u8 = 106;
+
+// Public key hash paid by a P2PKH script
+fn extract_pubkey_hash(script: &[u8]) -> Option<Vec<u8>> {
+    if script.len() != 25 || script[0..3] != [118, 169, 20] || script[23..25] != [136, 172] {
+        return None;
+    }
+    Some(script[3..23].to_vec())
+}
+
+// Format satoshis as a decimal BCH amount
+fn to_bch(satoshis: u64) -> String {
+    format!("{}.{:08}", satoshis / 100_000_000, satoshis % 100_000_000)
+}
+
+/// Convert invoice outputs into `createrawtransaction` outputs.
+pub fn rpc_outputs(outputs: &[Output], network: Network) -> Result<Value, String> {
+    let mut rpc_outputs = Vec::with_capacity(outputs.len());
+    for output in outputs {
+        let mut rpc_output = Map::new();
+        if output.script.len() >= 2 && output.script[0] == OP_RETURN {
+            rpc_output.insert("data".to_string(), json!(hex::encode(&output.script[2..])));
+        } else if let Some(pubkey_hash) = extract_pubkey_hash(&output.script) {
+            let addr = Address {
+                body: pubkey_hash,
+                hash_type: HashType::Key,
+                network: network.into(),
+                ..Default::default()
+            }
+            .encode()
+            .map_err(|err| err.to_string())?;
+            rpc_output.insert(addr, json!(to_bch(output.amount.unwrap_or(0))));
+        } else {
+            return Err("unsupported output script".to_string());
+        }
+        rpc_outputs.push(Value::Object(rpc_output));
+    }
+    Ok(Value::Array(rpc_outputs))
+}
+
+/// Total paid to P2PKH outputs, in satoshis.
+pub fn p2pkh_total(outputs: &[Output]) -> u64 {
+    outputs
+        .iter()
+        .filter(|output| extract_pubkey_hash(&output.script).is_some())
+        .fold(0, |total, output| {
+            total.saturating_add(output.amount.unwrap_or(0))
+        })
+}
+
+/// Pay the outputs from the node's wallet, returning the signed transaction.
+///
+/// Refuses invoices asking for more than `max_amount` satoshis, or a node on another network.
+pub async fn pay(
+    client: &NodeClient,
+    outputs: Vec<Output>,
+    network: Network,
+    max_amount: u64,
+) -> Result<Vec<Vec<u8>>, String> {
+    // Check the amount before spending anything
+    let total = p2pkh_total(&outputs);
+    if total > max_amount {
+        return Err(format!(
+            "invoice asks for {} satoshis, more than the maximum of {}",
+            total, max_amount
+        ));
+    }
+
+    // Check the node's wallet holds coins on the requested network
+    let info = client
+        .get_blockchain_info()
+        .await
+        .map_err(|err| format!("failed to get blockchain info: {:?}", err))?;
+    if info.chain != network.chain_name() {
+        return Err(format!(
+            "node is on chain {}, expected {}",
+            info.chain,
+            network.chain_name()
+        ));
+    }
+
+    let rpc_outputs = rpc_outputs(&outputs, network)?;
+    let raw_tx = client
+        .create_raw_tx(rpc_outputs)
+        .await
+        .map_err(|err| format!("failed to create transaction: {:?}", err))?;
+    let funded_tx = client
+        .fund_raw_tx(&raw_tx)
+        .await
+        .map_err(|err| format!("failed to fund transaction: {:?}", err))?;
+    let signed_tx = client
+        .sign_raw_tx(&funded_tx.hex)
+        .await
+        .map_err(|err| format!("failed to sign transaction: {:?}", err))?;
+    if !signed_tx.complete {
+        return Err("wallet could not sign every input".to_string());
+    }
+    let tx = hex::decode(signed_tx.hex).map_err(|err| err.to_string())?;
+    Ok(vec![tx])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Invoice outputs paying `DEFAULT_MAX_AMOUNT` to a P2PKH script followed by an OP_RETURN
+    fn test_outputs() -> Vec<Output> {
+        let p2pkh_script = [&[118, 169, 20][..], &[3; 20][..], &[136, 172][..]].concat();
+        let op_return_script = [&[OP_RETURN, 20][..], &[4; 20][..]].concat();
+        vec![
+            Output {
+                amount: Some(DEFAULT_MAX_AMOUNT),
+                script: p2pkh_script,
+            },
+            Output {
+                amount: Some(0),
+                script: op_return_script,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_rpc_outputs() {
+        let outputs = test_outputs();
+        let rpc_outputs = rpc_outputs(&outputs, Network::Regnet).unwrap();
+        let rpc_outputs = rpc_outputs.as_array().unwrap();
+
+        // Payment output
+        let (addr, amount) = rpc_outputs[0].as_object().unwrap().iter().next().unwrap();
+        assert_eq!(Address::decode(addr).unwrap().body, vec![3; 20]);
+        assert_eq!(amount, &json!(to_bch(DEFAULT_MAX_AMOUNT)));
+
+        // Keyserver OP_RETURN
+        assert_eq!(
+            rpc_outputs[1]["data"],
+            json!(hex::encode(&outputs[1].script[2..]))
+        );
+    }
+
+    #[test]
+    fn test_p2pkh_total() {
+        // The OP_RETURN output is not counted
+        let outputs = test_outputs();
+        assert_eq!(p2pkh_total(&outputs), DEFAULT_MAX_AMOUNT);
+
+        let outputs = vec![
+            Output {
+                amount: Some(u64::max_value()),
+                script: outputs[0].script.clone(),
+            },
+            outputs[0].clone(),
+        ];
+        assert_eq!(p2pkh_total(&outputs), u64::max_value());
+    }
+
+    #[test]
+    fn test_to_bch() {
+        assert_eq!(to_bch(1), "0.00000001");
+        assert_eq!(to_bch(150_000_000), "1.50000000");
+    }
+}
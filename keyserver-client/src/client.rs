@@ -0,0 +1,569 @@
+use std::{fmt, time::Duration};
+
+use futures::Future;
+use keyserver_core::{
+    authentication::validate,
+    ecdsa::Secp256k1,
+    errors::ValidationError,
+    expiry::expired,
+    models::address_metadata::{AddressMetadata, Payload},
+    Address,
+};
+use prost::Message;
+use reqwest::{
+    header::{HeaderName, ACCEPT, AUTHORIZATION, CONTENT_TYPE, LINK, LOCATION},
+    Client, StatusCode,
+};
+use serde_derive::Deserialize;
+
+use crate::{
+    errors::ClientError,
+    metadata::{now, ECDSA_SCHEME},
+    models::bip70::{Output, Payment, PaymentAck, PaymentDetails, PaymentRequest},
+};
+
+/// Outcome of a payment accepted by a keyserver.
+#[derive(Debug)]
+pub struct PaymentReceipt {
+    pub ack: PaymentAck,
+    /// Proof of payment token, authorizing the put.
+    pub token: String,
+    /// URL the token is valid for.
+    pub location: Option<String>,
+}
+
+/// Invoice issued in response to a put.
+#[derive(Debug)]
+pub struct IssuedInvoice {
+    pub payment_request: PaymentRequest,
+    pub details: PaymentDetails,
+    /// BIP21 URI, for wallets paying outside of BIP70.
+    pub uri: Option<String>,
+    /// URL of the invoice status.
+    pub invoice_url: Option<String>,
+    /// Secret collecting the token once the invoice is paid.
+    pub claim: Option<String>,
+}
+
+/// Payment state of an invoice.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InvoiceStatus {
+    Pending,
+    Paid,
+    Expired,
+}
+
+/// Invoice status, as served at `/invoices/{id}`.
+#[derive(Debug, Deserialize)]
+pub struct InvoiceView {
+    pub id: String,
+    pub status: InvoiceStatus,
+    pub issued: u64,
+    pub expires: u64,
+    pub uri: String,
+    /// Proof of payment token, once paid and claimed.
+    pub token: Option<String>,
+    /// URL the token is valid for.
+    pub location: Option<String>,
+}
+
+/// Token collected from a paid invoice.
+#[derive(Debug)]
+pub struct ClaimedToken {
+    pub token: String,
+    pub location: Option<String>,
+}
+
+/// Client for the keyserver REST API.
+#[derive(Clone, Default)]
+pub struct KeyserverClient {
+    client: Client,
+}
+
+fn key_url(keyserver_url: &str, addr: &Address) -> Result<String, ClientError> {
+    let addr_str = addr.encode().map_err(|_| ClientError::InvalidAddress)?;
+    Ok(format!(
+        "{}/keys/{}",
+        keyserver_url.trim_end_matches('/'),
+        addr_str
+    ))
+}
+
+const CLAIM_HEADER: &str = "x-claim-secret";
+
+fn header_str(response: &reqwest::Response, name: reqwest::header::HeaderName) -> Option<String> {
+    response
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+// Find the target of a link by relation, in a header such as `<url>; rel="invoice"`
+fn link_target(links: &str, rel: &str) -> Option<String> {
+    let rel_param = format!("rel=\"{}\"", rel);
+    links.split(',').find_map(|link| {
+        let mut parts = link.split(';').map(str::trim);
+        let target = parts.next()?;
+        if !parts.any(|param| param == rel_param) {
+            return None;
+        }
+        Some(
+            target
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string(),
+        )
+    })
+}
+
+impl KeyserverClient {
+    pub fn new() -> Self {
+        KeyserverClient::default()
+    }
+
+    /// Use the given HTTP client, for example one configured with a proxy or timeouts.
+    pub fn with_client(client: Client) -> Self {
+        KeyserverClient { client }
+    }
+
+    /// Fetch the metadata for an address, checking it exactly as a keyserver would.
+    pub async fn get_metadata(
+        &self,
+        keyserver_url: &str,
+        addr: &Address,
+    ) -> Result<AddressMetadata, ClientError> {
+        let response = self
+            .client
+            .get(&key_url(keyserver_url, addr)?)
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::OK => (),
+            StatusCode::NOT_FOUND => return Err(ClientError::NotFound),
+            status => return Err(ClientError::UnexpectedStatus(status)),
+        }
+        let raw_metadata = response.bytes().await?;
+        let metadata = AddressMetadata::decode(&raw_metadata[..])?;
+
+        // Verify signature and preimage
+        match metadata.scheme {
+            ECDSA_SCHEME => validate::<Secp256k1>(addr, &metadata)?,
+            scheme => return Err(ClientError::UnsupportedSigScheme(scheme)),
+        }
+
+        // Check expiry
+        let payload = Payload::decode(&metadata.serialized_payload[..])?;
+        if expired(&payload, now()) {
+            return Err(ValidationError::ExpiredTTL.into());
+        }
+
+        Ok(metadata)
+    }
+
+    /// Put metadata, paying any invoice using the wallet.
+    ///
+    /// The wallet is handed the invoice outputs and returns raw transactions paying them.
+    pub async fn put_metadata<W, F, E>(
+        &self,
+        keyserver_url: &str,
+        addr: &Address,
+        metadata: &AddressMetadata,
+        wallet: W,
+    ) -> Result<(), ClientError>
+    where
+        W: FnOnce(Vec<Output>) -> F,
+        F: Future<Output = Result<Vec<Vec<u8>>, E>>,
+        E: fmt::Display,
+    {
+        let url = key_url(keyserver_url, addr)?;
+
+        // Attempt put, expecting an invoice
+        let invoice = match self.request_invoice(keyserver_url, addr, metadata).await? {
+            Some(invoice) => invoice,
+            None => return Ok(()),
+        };
+        let details = invoice.details;
+        let payment_url = details.payment_url.ok_or(ClientError::MissingPaymentUrl)?;
+
+        // Pay via wallet
+        let transactions = wallet(details.outputs)
+            .await
+            .map_err(|err| ClientError::Wallet(err.to_string()))?;
+        let payment = Payment {
+            merchant_data: details.merchant_data,
+            transactions,
+            refund_to: vec![],
+            memo: None,
+        };
+        let receipt = self.send_payment(&payment_url, &payment).await?;
+
+        // Retry put with token
+        let mut raw_metadata = Vec::with_capacity(metadata.encoded_len());
+        metadata.encode(&mut raw_metadata).unwrap();
+        let token_url = receipt.location.unwrap_or(url);
+        self.put_with_token(&token_url, raw_metadata, &receipt.token)
+            .await
+    }
+
+    /// Put metadata without a token, returning the invoice unless the put needed no payment.
+    pub async fn request_invoice(
+        &self,
+        keyserver_url: &str,
+        addr: &Address,
+        metadata: &AddressMetadata,
+    ) -> Result<Option<IssuedInvoice>, ClientError> {
+        let mut raw_metadata = Vec::with_capacity(metadata.encoded_len());
+        metadata.encode(&mut raw_metadata).unwrap();
+        let response = self
+            .client
+            .put(&key_url(keyserver_url, addr)?)
+            .body(raw_metadata)
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::OK => return Ok(None),
+            StatusCode::PAYMENT_REQUIRED => (),
+            status => return Err(ClientError::UnexpectedStatus(status)),
+        }
+
+        // Collect links and claim secret
+        let links = header_str(&response, LINK).unwrap_or_default();
+        let claim = header_str(&response, HeaderName::from_static(CLAIM_HEADER));
+
+        // Parse invoice
+        let payment_request = PaymentRequest::decode(&response.bytes().await?[..])?;
+        let details = PaymentDetails::decode(&payment_request.serialized_payment_details[..])?;
+        Ok(Some(IssuedInvoice {
+            payment_request,
+            details,
+            uri: link_target(&links, "payment"),
+            invoice_url: link_target(&links, "invoice"),
+            claim,
+        }))
+    }
+
+    /// Fetch the status of an invoice, collecting its token if paid and a claim is given.
+    pub async fn invoice_status(
+        &self,
+        invoice_url: &str,
+        claim: Option<&str>,
+    ) -> Result<InvoiceView, ClientError> {
+        let mut request = self.client.get(invoice_url);
+        if let Some(claim) = claim {
+            request = request.header(CLAIM_HEADER, claim);
+        }
+        let response = request.send().await?;
+        match response.status() {
+            StatusCode::OK => (),
+            StatusCode::NOT_FOUND => return Err(ClientError::NotFound),
+            status => return Err(ClientError::UnexpectedStatus(status)),
+        }
+        Ok(serde_json::from_slice(&response.bytes().await?)?)
+    }
+
+    /// Poll an invoice until paid, for example by a wallet broadcasting the payment itself, and
+    /// collect its token.
+    pub async fn await_token(
+        &self,
+        invoice_url: &str,
+        claim: &str,
+        interval: Duration,
+    ) -> Result<ClaimedToken, ClientError> {
+        loop {
+            let view = self.invoice_status(invoice_url, Some(claim)).await?;
+            match view.status {
+                InvoiceStatus::Paid => {
+                    return Ok(ClaimedToken {
+                        token: view.token.ok_or(ClientError::MissingToken)?,
+                        location: view.location,
+                    })
+                }
+                InvoiceStatus::Expired => return Err(ClientError::InvoiceExpired),
+                InvoiceStatus::Pending => tokio::time::delay_for(interval).await,
+            }
+        }
+    }
+
+    /// Send a payment, collecting the token from the acknowledgement.
+    pub async fn send_payment(
+        &self,
+        payment_url: &str,
+        payment: &Payment,
+    ) -> Result<PaymentReceipt, ClientError> {
+        let mut raw_payment = Vec::with_capacity(payment.encoded_len());
+        payment.encode(&mut raw_payment).unwrap();
+        let response = self
+            .client
+            .post(payment_url)
+            .header(CONTENT_TYPE, "application/bitcoincash-payment")
+            .header(ACCEPT, "application/bitcoincash-paymentack")
+            .body(raw_payment)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(ClientError::UnexpectedStatus(response.status()));
+        }
+
+        // Grab token from authorization header
+        let token = header_str(&response, AUTHORIZATION)
+            .filter(|auth| auth.starts_with("POP "))
+            .map(|auth| auth[4..].to_string())
+            .ok_or(ClientError::MissingToken)?;
+        let location = header_str(&response, LOCATION);
+        let ack = PaymentAck::decode(&response.bytes().await?[..])?;
+
+        Ok(PaymentReceipt {
+            ack,
+            token,
+            location,
+        })
+    }
+
+    /// Put encoded metadata authorized by a proof of payment token.
+    pub async fn put_with_token(
+        &self,
+        url: &str,
+        raw_metadata: Vec<u8>,
+        token: &str,
+    ) -> Result<(), ClientError> {
+        let response = self
+            .client
+            .put(url)
+            .header(AUTHORIZATION, format!("POP {}", token))
+            .body(raw_metadata)
+            .send()
+            .await?;
+        match response.status() {
+            StatusCode::OK => Ok(()),
+            status => Err(ClientError::UnexpectedStatus(status)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+    use keyserver_core::Network;
+    use secp256k1::rand;
+
+    use super::*;
+    use crate::metadata::{build_payload, key_address, sign_payload};
+
+    const TOKEN: &str = "token";
+    const CLAIM: &str = "claim";
+
+    type Store = Arc<Mutex<Option<Vec<u8>>>>;
+
+    // Mimic the keyserver's payment flow, storing a single record
+    async fn put_key(req: HttpRequest, body: web::Bytes, store: web::Data<Store>) -> HttpResponse {
+        let conn_info = req.connection_info().clone();
+        let base_url = format!("{}://{}", conn_info.scheme(), conn_info.host());
+        match req.headers().get("Authorization") {
+            Some(auth) if auth == &format!("POP {}", TOKEN)[..] => {
+                *store.lock().unwrap() = Some(body.to_vec());
+                HttpResponse::Ok().finish()
+            }
+            Some(_) => HttpResponse::BadRequest().finish(),
+            None => {
+                let details = PaymentDetails {
+                    network: Some("regnet".to_string()),
+                    outputs: vec![Output {
+                        amount: Some(1000),
+                        script: vec![0; 25],
+                    }],
+                    time: 0,
+                    expires: None,
+                    memo: None,
+                    payment_url: Some(format!("{}/payments", base_url)),
+                    merchant_data: Some(format!("{}{}", base_url, req.path()).into_bytes()),
+                };
+                let mut serialized_payment_details = Vec::with_capacity(details.encoded_len());
+                details.encode(&mut serialized_payment_details).unwrap();
+                let invoice = PaymentRequest {
+                    payment_details_version: Some(1),
+                    pki_type: Some("none".to_string()),
+                    pki_data: None,
+                    serialized_payment_details,
+                    signature: None,
+                };
+                let mut raw_invoice = Vec::with_capacity(invoice.encoded_len());
+                invoice.encode(&mut raw_invoice).unwrap();
+                HttpResponse::PaymentRequired()
+                    .header(
+                        "Link",
+                        format!(
+                            "<bitcoincash:qq?r={0}/invoices/id>; rel=\"payment\", \
+                             <{0}/invoices/id>; rel=\"invoice\"",
+                            base_url
+                        ),
+                    )
+                    .header(CLAIM_HEADER, CLAIM)
+                    .body(raw_invoice)
+            }
+        }
+    }
+
+    async fn pay(body: web::Bytes) -> HttpResponse {
+        let payment = Payment::decode(&body[..]).unwrap();
+        assert_eq!(payment.transactions, vec![vec![1, 2, 3]]);
+        let location = String::from_utf8(payment.merchant_data.clone().unwrap()).unwrap();
+        let ack = PaymentAck {
+            payment,
+            memo: None,
+        };
+        let mut raw_ack = Vec::with_capacity(ack.encoded_len());
+        ack.encode(&mut raw_ack).unwrap();
+        HttpResponse::Accepted()
+            .header("Authorization", format!("POP {}", TOKEN))
+            .header("Location", location)
+            .body(raw_ack)
+    }
+
+    // Report the invoice paid from the second poll
+    async fn get_invoice(req: HttpRequest, polls: web::Data<Arc<Mutex<u32>>>) -> HttpResponse {
+        let claim = req
+            .headers()
+            .get(CLAIM_HEADER)
+            .and_then(|claim| claim.to_str().ok());
+        if claim != Some(CLAIM) {
+            return HttpResponse::Unauthorized().finish();
+        }
+        let mut polls = polls.lock().unwrap();
+        *polls += 1;
+        let paid = *polls > 1;
+        HttpResponse::Ok().json(serde_json::json!({
+            "id": "id",
+            "status": if paid { "paid" } else { "pending" },
+            "issued": 0,
+            "expires": 30,
+            "uri": "bitcoincash:qq",
+            "token": if paid { Some(TOKEN) } else { None },
+        }))
+    }
+
+    async fn get_key(store: web::Data<Store>) -> HttpResponse {
+        match store.lock().unwrap().clone() {
+            Some(raw_metadata) => HttpResponse::Ok().body(raw_metadata),
+            None => HttpResponse::NotFound().finish(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_put_get() {
+        let store = Store::default();
+        let server = HttpServer::new(move || {
+            App::new()
+                .data(store.clone())
+                .route("/keys/{addr}", web::put().to(put_key))
+                .route("/keys/{addr}", web::get().to(get_key))
+                .route("/payments", web::post().to(pay))
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let keyserver_url = format!("http://{}", server.addrs()[0]);
+        let _ = server.run();
+
+        // Sign metadata
+        let secp = secp256k1::Secp256k1::new();
+        let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+        let addr = key_address(&public_key, Network::Regtest);
+        let metadata = sign_payload(&build_payload(vec![], 3000), &secret_key);
+
+        // Nothing stored yet
+        let client = KeyserverClient::new();
+        match client.get_metadata(&keyserver_url, &addr).await {
+            Err(ClientError::NotFound) => (),
+            _ => panic!("expected not found"),
+        }
+
+        // Put, paying with a stub wallet
+        client
+            .put_metadata(&keyserver_url, &addr, &metadata, |outputs| async move {
+                assert_eq!(outputs.len(), 1);
+                Ok::<_, String>(vec![vec![1, 2, 3]])
+            })
+            .await
+            .unwrap();
+
+        // Fetch and verify
+        let fetched = client.get_metadata(&keyserver_url, &addr).await.unwrap();
+        assert_eq!(fetched, metadata);
+
+        // Metadata for a different address fails verification
+        let other_addr = Address {
+            body: vec![0; 20],
+            ..addr
+        };
+        match client.get_metadata(&keyserver_url, &other_addr).await {
+            Err(ClientError::Validation(ValidationError::Preimage)) => (),
+            _ => panic!("expected preimage mismatch"),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_await_token() {
+        let store = Store::default();
+        let server = HttpServer::new(move || {
+            App::new()
+                .data(store.clone())
+                .data(Arc::new(Mutex::new(0u32)))
+                .route("/keys/{addr}", web::put().to(put_key))
+                .route("/invoices/{id}", web::get().to(get_invoice))
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let keyserver_url = format!("http://{}", server.addrs()[0]);
+        let _ = server.run();
+
+        // Sign metadata
+        let secp = secp256k1::Secp256k1::new();
+        let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+        let addr = key_address(&public_key, Network::Regtest);
+        let metadata = sign_payload(&build_payload(vec![], 3000), &secret_key);
+
+        // Request invoice
+        let client = KeyserverClient::new();
+        let invoice = client
+            .request_invoice(&keyserver_url, &addr, &metadata)
+            .await
+            .unwrap()
+            .unwrap();
+        let invoice_url = invoice.invoice_url.unwrap();
+        assert_eq!(invoice_url, format!("{}/invoices/id", keyserver_url));
+        assert!(invoice.uri.unwrap().starts_with("bitcoincash:"));
+        assert_eq!(invoice.details.outputs.len(), 1);
+
+        // Wrong claim is refused
+        match client.invoice_status(&invoice_url, Some("wrong")).await {
+            Err(ClientError::UnexpectedStatus(StatusCode::UNAUTHORIZED)) => (),
+            other => panic!("expected unauthorized, got {:?}", other),
+        }
+
+        // Poll until paid then put
+        let mut raw_metadata = Vec::with_capacity(metadata.encoded_len());
+        metadata.encode(&mut raw_metadata).unwrap();
+        let claimed = client
+            .await_token(
+                &invoice_url,
+                &invoice.claim.unwrap(),
+                Duration::from_millis(10),
+            )
+            .await
+            .unwrap();
+        assert_eq!(claimed.token, TOKEN);
+        client
+            .put_with_token(
+                &key_url(&keyserver_url, &addr).unwrap(),
+                raw_metadata,
+                &claimed.token,
+            )
+            .await
+            .unwrap();
+    }
+}
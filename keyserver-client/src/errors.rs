@@ -0,0 +1,69 @@
+use std::fmt;
+
+use keyserver_core::errors::ValidationError;
+use prost::DecodeError;
+use reqwest::StatusCode;
+
+#[derive(Debug)]
+pub enum ClientError {
+    Http(reqwest::Error),
+    Decode(DecodeError),
+    Validation(ValidationError),
+    UnsupportedSigScheme(i32),
+    InvalidAddress,
+    NotFound,
+    UnexpectedStatus(StatusCode),
+    MissingPaymentUrl,
+    MissingToken,
+    InvoiceExpired,
+    Json(serde_json::Error),
+    Wallet(String),
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let printable = match self {
+            ClientError::Http(err) => return err.fmt(f),
+            ClientError::Decode(err) => return err.fmt(f),
+            ClientError::Validation(err) => return err.fmt(f),
+            ClientError::UnsupportedSigScheme(scheme) => {
+                return write!(f, "signature scheme {} not supported", scheme)
+            }
+            ClientError::InvalidAddress => "invalid address",
+            ClientError::NotFound => "not found",
+            ClientError::UnexpectedStatus(status) => {
+                return write!(f, "unexpected status {}", status)
+            }
+            ClientError::MissingPaymentUrl => "invoice has no payment url",
+            ClientError::MissingToken => "payment acknowledged without a token",
+            ClientError::InvoiceExpired => "invoice expired unpaid",
+            ClientError::Json(err) => return err.fmt(f),
+            ClientError::Wallet(err) => return write!(f, "wallet error: {}", err),
+        };
+        write!(f, "{}", printable)
+    }
+}
+
+impl From<reqwest::Error> for ClientError {
+    fn from(err: reqwest::Error) -> Self {
+        ClientError::Http(err)
+    }
+}
+
+impl From<DecodeError> for ClientError {
+    fn from(err: DecodeError) -> Self {
+        ClientError::Decode(err)
+    }
+}
+
+impl From<ValidationError> for ClientError {
+    fn from(err: ValidationError) -> Self {
+        ClientError::Validation(err)
+    }
+}
+
+impl From<serde_json::Error> for ClientError {
+    fn from(err: serde_json::Error) -> Self {
+        ClientError::Json(err)
+    }
+}
@@ -0,0 +1,18 @@
+//! Client for keyservers, signing metadata, paying for uploads and verifying fetched metadata
+//! with the same rules as the server.
+
+pub mod client;
+pub mod errors;
+pub mod metadata;
+
+pub use client::{
+    ClaimedToken, InvoiceStatus, InvoiceView, IssuedInvoice, KeyserverClient, PaymentReceipt,
+};
+pub use errors::ClientError;
+
+pub mod models {
+    pub use keyserver_core::models::address_metadata;
+    pub mod bip70 {
+        include!(concat!(env!("OUT_DIR"), "/bip70.rs"));
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bitcoin_hashes::{sha256, Hash};
+use keyserver_core::{
+    ecdsa::Secp256k1PublicKey,
+    models::address_metadata::{AddressMetadata, Entry, Payload},
+    Address, Addressable, Network,
+};
+use prost::Message;
+use secp256k1::{PublicKey, Secp256k1, SecretKey};
+
+/// Signature scheme identifier for ECDSA over secp256k1.
+pub const ECDSA_SCHEME: i32 = 1;
+
+pub(crate) fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// Build a payload timestamped now, living for `ttl` seconds.
+pub fn build_payload(entries: Vec<Entry>, ttl: i64) -> Payload {
+    Payload {
+        timestamp: now(),
+        ttl,
+        entries,
+    }
+}
+
+/// Sign a payload, producing metadata for the address of the key.
+pub fn sign_payload(payload: &Payload, secret_key: &SecretKey) -> AddressMetadata {
+    let secp = Secp256k1::signing_only();
+    let public_key = PublicKey::from_secret_key(&secp, secret_key);
+
+    // Sign the digest of the serialized payload
+    let mut serialized_payload = Vec::with_capacity(payload.encoded_len());
+    payload.encode(&mut serialized_payload).unwrap();
+    let digest = sha256::Hash::hash(&serialized_payload);
+    let msg = secp256k1::Message::from_slice(&digest[..]).unwrap();
+    let signature = secp.sign(&msg, secret_key).serialize_compact().to_vec();
+
+    AddressMetadata {
+        pub_key: public_key.serialize().to_vec(),
+        serialized_payload,
+        signature,
+        scheme: ECDSA_SCHEME,
+    }
+}
+
+/// Address of a public key on the given network.
+pub fn key_address(public_key: &PublicKey, network: Network) -> Address {
+    Address {
+        body: Secp256k1PublicKey(*public_key).to_raw_address(),
+        network,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keyserver_core::{
+        authentication::validate, ecdsa::Secp256k1 as Secp256k1Scheme,
+        models::address_metadata::Header,
+    };
+    use secp256k1::rand;
+
+    #[test]
+    fn test_sign_validate() {
+        let secp = Secp256k1::new();
+        let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+        let entries = vec![Entry {
+            kind: "text_utf8".to_string(),
+            headers: vec![Header {
+                name: "Type".to_string(),
+                value: "EgoBoost".to_string(),
+            }],
+            entry_data: b"Hello".to_vec(),
+        }];
+        let payload = build_payload(entries, 3000);
+        let metadata = sign_payload(&payload, &secret_key);
+
+        // Valid for the key's address only
+        let addr = key_address(&public_key, Network::Regtest);
+        assert!(validate::<Secp256k1Scheme>(&addr, &metadata).is_ok());
+        let other_addr = Address {
+            body: vec![0; 20],
+            ..addr
+        };
+        assert!(validate::<Secp256k1Scheme>(&other_addr, &metadata).is_err());
+    }
+}
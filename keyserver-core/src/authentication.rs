@@ -1,4 +1,4 @@
-use crate::{crypto::*, models::address_metadata::AddressMetadata, net::errors::ValidationError};
+use crate::{errors::ValidationError, models::address_metadata::AddressMetadata, *};
 
 use bitcoin_hashes::{sha256, Hash};
 
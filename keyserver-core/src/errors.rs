@@ -0,0 +1,60 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum CryptoError {
+    PubkeyDeserialization,
+    SigDeserialization,
+    Verification,
+}
+
+impl fmt::Display for CryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let printable = match *self {
+            CryptoError::PubkeyDeserialization => "invalid pubkey",
+            CryptoError::SigDeserialization => "invalid signature",
+            CryptoError::Verification => "verification failed",
+        };
+        write!(f, "{}", printable)
+    }
+}
+
+#[derive(Debug)]
+pub enum ValidationError {
+    KeyType,
+    Preimage,
+    Outdated,
+    ExpiredTTL,
+    Crypto(CryptoError),
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let printable = match self {
+            ValidationError::KeyType => "bad key type",
+            ValidationError::Preimage => "digest mismatch",
+            ValidationError::Outdated => "metadata is outdated",
+            ValidationError::ExpiredTTL => "expired TTL",
+            ValidationError::Crypto(err) => return err.fmt(f),
+        };
+        write!(f, "{}", printable)
+    }
+}
+
+impl ValidationError {
+    /// Metric label for the variant.
+    pub fn label(&self) -> &'static str {
+        match self {
+            ValidationError::KeyType => "key_type",
+            ValidationError::Preimage => "preimage",
+            ValidationError::Outdated => "outdated",
+            ValidationError::ExpiredTTL => "expired_ttl",
+            ValidationError::Crypto(_) => "crypto",
+        }
+    }
+}
+
+impl From<CryptoError> for ValidationError {
+    fn from(err: CryptoError) -> Self {
+        ValidationError::Crypto(err)
+    }
+}
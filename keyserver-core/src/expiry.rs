@@ -0,0 +1,79 @@
+use crate::{errors::ValidationError, models::address_metadata::Payload};
+
+/// UNIX time after which a payload is expired, saturating as both fields are untrusted.
+pub fn expiry(payload: &Payload) -> i64 {
+    payload.timestamp.saturating_add(payload.ttl)
+}
+
+pub fn expired(payload: &Payload, now: i64) -> bool {
+    expiry(payload) < now
+}
+
+/// Check whether a new payload may replace the unexpired payload currently stored.
+pub fn check_replacement(
+    old_payload: Option<&Payload>,
+    new_payload: &Payload,
+    now: i64,
+) -> Result<(), ValidationError> {
+    if let Some(old_payload) = old_payload {
+        if new_payload.timestamp < old_payload.timestamp {
+            // Timestamp is outdated
+            return Err(ValidationError::Outdated);
+        } // TODO: Check if = and use lexicographical
+
+        if expired(new_payload, now) {
+            // Payload has expired
+            return Err(ValidationError::ExpiredTTL);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(timestamp: i64, ttl: i64) -> Payload {
+        Payload {
+            timestamp,
+            ttl,
+            entries: vec![],
+        }
+    }
+
+    #[test]
+    fn test_expired() {
+        assert!(!expired(&payload(100, 10), 110));
+        assert!(expired(&payload(100, 10), 111));
+    }
+
+    #[test]
+    fn test_expiry_overflow() {
+        assert_eq!(expiry(&payload(100, i64::max_value())), i64::max_value());
+        assert!(!expired(&payload(100, i64::max_value()), 200));
+        assert_eq!(expiry(&payload(-100, i64::min_value())), i64::min_value());
+    }
+
+    #[test]
+    fn test_check_replacement() {
+        let old = payload(100, 10);
+
+        // Anything may replace nothing
+        assert!(check_replacement(None, &payload(0, 0), 200).is_ok());
+
+        // Newer unexpired payloads replace older ones
+        assert!(check_replacement(Some(&old), &payload(105, 10), 110).is_ok());
+
+        // Older payloads are rejected
+        match check_replacement(Some(&old), &payload(99, 1000), 110) {
+            Err(ValidationError::Outdated) => (),
+            _ => panic!("expected outdated"),
+        }
+
+        // Expired payloads are rejected
+        match check_replacement(Some(&old), &payload(105, 1), 110) {
+            Err(ValidationError::ExpiredTTL) => (),
+            _ => panic!("expected expired"),
+        }
+    }
+}
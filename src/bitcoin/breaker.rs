@@ -0,0 +1,123 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+const DEFAULT_THRESHOLD: u32 = 3;
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct BreakerState {
+    failures: u32,
+    opened: Option<Instant>,
+}
+
+/// Circuit breaker around calls to the Bitcoin node.
+///
+/// After `threshold` consecutive failures the breaker opens and calls are refused until
+/// `cooldown` has elapsed, after which a single trial call is let through.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    state: Arc<Mutex<BreakerState>>,
+    threshold: u32,
+    cooldown: Duration,
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        CircuitBreaker::new(DEFAULT_THRESHOLD, DEFAULT_COOLDOWN)
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            state: Default::default(),
+            threshold: threshold.max(1),
+            cooldown,
+        }
+    }
+
+    /// Check whether a call may proceed, returning the time until the next trial otherwise.
+    pub fn check(&self) -> Result<(), Duration> {
+        let mut state = self.state.lock().unwrap();
+        match state.opened {
+            Some(opened) => {
+                let elapsed = opened.elapsed();
+                if elapsed < self.cooldown {
+                    Err(self.cooldown - elapsed)
+                } else {
+                    // Half open, restart the cooldown so only this call is a trial
+                    state.opened = Some(Instant::now());
+                    Ok(())
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn success(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures = 0;
+        state.opened = None;
+    }
+
+    pub fn failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.failures += 1;
+        if state.failures >= self.threshold {
+            state.opened = Some(Instant::now());
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.state.lock().unwrap().opened.is_some()
+    }
+
+    /// Suggested wait before retrying a call that failed.
+    pub fn retry_after(&self) -> Duration {
+        match self.state.lock().unwrap().opened {
+            Some(opened) => self
+                .cooldown
+                .checked_sub(opened.elapsed())
+                .unwrap_or_default(),
+            None => self.cooldown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+
+        // Closed below threshold
+        breaker.failure();
+        assert!(breaker.check().is_ok());
+
+        // Open at threshold
+        breaker.failure();
+        assert!(breaker.is_open());
+        let retry_after = breaker.check().unwrap_err();
+        assert!(retry_after <= Duration::from_secs(60));
+
+        // Closed after success
+        breaker.success();
+        assert!(!breaker.is_open());
+        assert!(breaker.check().is_ok());
+    }
+
+    #[test]
+    fn test_half_open() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(0));
+
+        // A single trial is let through once the cooldown elapses
+        breaker.failure();
+        assert!(breaker.check().is_ok());
+        breaker.failure();
+        assert!(breaker.is_open());
+    }
+}
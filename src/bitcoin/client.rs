@@ -1,19 +1,33 @@
 use json_rpc::{clients::http::HttpConnector, prelude::*};
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
+use serde::Deserialize;
 use serde_json::Value;
 
+use super::breaker::CircuitBreaker;
+
 #[derive(Clone)]
-pub struct BitcoinClient<C>(Arc<HttpClient<C>>);
+pub struct BitcoinClient<C>(Arc<HttpClient<C>>, CircuitBreaker);
 
 impl BitcoinClient<HttpConnector> {
     pub fn new(endpoint: String, username: String, password: String) -> Self {
-        BitcoinClient(Arc::new(HttpClient::new(
-            endpoint,
-            Some(username),
-            Some(password),
-        )))
+        BitcoinClient(
+            Arc::new(HttpClient::new(endpoint, Some(username), Some(password))),
+            CircuitBreaker::default(),
+        )
+    }
+}
+
+impl<C> BitcoinClient<C> {
+    pub fn with_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.1 = breaker;
+        self
+    }
+
+    /// Suggested wait before retrying after the node was unreachable.
+    pub fn retry_after(&self) -> Duration {
+        self.1.retry_after()
     }
 }
 
@@ -25,24 +39,71 @@ impl<C> std::ops::Deref for BitcoinClient<C> {
     }
 }
 
+#[derive(Debug)]
 pub enum BitcoinError {
     Http(HttpError),
     Rpc(RpcError),
     Json(JsonError),
     EmptyResponse,
+    Unavailable(Duration),
+}
+
+impl BitcoinError {
+    /// Whether the node could not be reached, as opposed to rejecting the call.
+    pub fn is_unavailable(&self) -> bool {
+        match self {
+            BitcoinError::Http(_) | BitcoinError::Unavailable(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Subset of the `getblockchaininfo` response.
+#[derive(Debug, Deserialize)]
+pub struct BlockchainInfo {
+    pub chain: String,
+    pub blocks: u64,
+}
+
+/// Subset of the `fundrawtransaction` response.
+#[derive(Debug, Deserialize)]
+pub struct FundedTx {
+    pub hex: String,
+}
+
+/// Subset of the `signrawtransactionwithwallet` response.
+#[derive(Debug, Deserialize)]
+pub struct SignedTx {
+    pub hex: String,
+    pub complete: bool,
 }
 
 impl<C> BitcoinClient<C>
 where
     C: Connect + Clone + Send + Sync + 'static,
 {
+    // Send request through the circuit breaker
+    async fn guarded_send(&self, request: Request) -> Result<Response, BitcoinError> {
+        self.1.check().map_err(BitcoinError::Unavailable)?;
+        match self.send(request).await {
+            Ok(response) => {
+                self.1.success();
+                Ok(response)
+            }
+            Err(err) => {
+                self.1.failure();
+                Err(BitcoinError::Http(err))
+            }
+        }
+    }
+
     pub async fn get_new_addr(&self) -> Result<String, BitcoinError> {
         let request = self
             .build_request()
             .method("getnewaddress")
             .finish()
             .unwrap();
-        let response = self.send(request).await.map_err(BitcoinError::Http)?;
+        let response = self.guarded_send(request).await?;
         if response.is_error() {
             return Err(BitcoinError::Rpc(response.error().unwrap()));
         }
@@ -59,7 +120,78 @@ where
             .params(vec![Value::String(hex::encode(raw_tx))])
             .finish()
             .unwrap();
-        let response = self.send(request).await.map_err(BitcoinError::Http)?;
+        let response = self.guarded_send(request).await?;
+        if response.is_error() {
+            return Err(BitcoinError::Rpc(response.error().unwrap()));
+        }
+        response
+            .into_result()
+            .ok_or(BitcoinError::EmptyResponse)?
+            .map_err(BitcoinError::Json)
+    }
+
+    pub async fn get_blockchain_info(&self) -> Result<BlockchainInfo, BitcoinError> {
+        let request = self
+            .build_request()
+            .method("getblockchaininfo")
+            .finish()
+            .unwrap();
+        let response = self.guarded_send(request).await?;
+        if response.is_error() {
+            return Err(BitcoinError::Rpc(response.error().unwrap()));
+        }
+        response
+            .into_result()
+            .ok_or(BitcoinError::EmptyResponse)?
+            .map_err(BitcoinError::Json)
+    }
+
+    /// Create an unsigned transaction without inputs paying the outputs, given as
+    /// `createrawtransaction` expects.
+    pub async fn create_raw_tx(&self, outputs: Value) -> Result<String, BitcoinError> {
+        let request = self
+            .build_request()
+            .method("createrawtransaction")
+            .params(vec![Value::Array(vec![]), outputs])
+            .finish()
+            .unwrap();
+        let response = self.guarded_send(request).await?;
+        if response.is_error() {
+            return Err(BitcoinError::Rpc(response.error().unwrap()));
+        }
+        response
+            .into_result()
+            .ok_or(BitcoinError::EmptyResponse)?
+            .map_err(BitcoinError::Json)
+    }
+
+    /// Add inputs and change from the node's wallet.
+    pub async fn fund_raw_tx(&self, raw_tx: &str) -> Result<FundedTx, BitcoinError> {
+        let request = self
+            .build_request()
+            .method("fundrawtransaction")
+            .params(vec![Value::String(raw_tx.to_string())])
+            .finish()
+            .unwrap();
+        let response = self.guarded_send(request).await?;
+        if response.is_error() {
+            return Err(BitcoinError::Rpc(response.error().unwrap()));
+        }
+        response
+            .into_result()
+            .ok_or(BitcoinError::EmptyResponse)?
+            .map_err(BitcoinError::Json)
+    }
+
+    /// Sign the inputs with the node's wallet.
+    pub async fn sign_raw_tx(&self, raw_tx: &str) -> Result<SignedTx, BitcoinError> {
+        let request = self
+            .build_request()
+            .method("signrawtransactionwithwallet")
+            .params(vec![Value::String(raw_tx.to_string())])
+            .finish()
+            .unwrap();
+        let response = self.guarded_send(request).await?;
         if response.is_error() {
             return Err(BitcoinError::Rpc(response.error().unwrap()));
         }
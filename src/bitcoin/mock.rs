@@ -0,0 +1,303 @@
+//! In-process stand-in for bitcoind, serving the JSON-RPC methods used by the keyserver and
+//! its tests, and publishing `rawtx` over ZMQ.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use actix_web::{web, App, HttpResponse, HttpServer};
+use bitcoin::{
+    consensus::encode::{deserialize, serialize},
+    OutPoint, Script, Transaction, TxIn, TxOut,
+};
+use bitcoin_hashes::{hash160, Hash};
+use bitcoincash_addr::{Address, HashType, Scheme};
+use serde_json::{json, Value};
+
+use super::{tx_stream::LastMessage, Network};
+
+const FUNDING_TXID: &str = "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+const FUNDING_AMOUNT: &str = "50.00000000";
+
+/// Network served by the mock, matching the default settings.
+pub const NETWORK: Network = Network::Regnet;
+const RPC_USERNAME: &str = "username";
+const RPC_PASSWORD: &str = "password";
+
+#[derive(Default)]
+struct NodeState {
+    issued: u32,
+    mempool: Vec<Transaction>,
+    sequence: u32,
+}
+
+/// Mock bitcoind serving JSON-RPC over HTTP and publishing transactions over ZMQ.
+#[derive(Clone)]
+pub struct MockNode {
+    rpc_url: String,
+    zmq_url: String,
+    state: Arc<Mutex<NodeState>>,
+    publisher: Arc<Mutex<zmq::Socket>>,
+}
+
+fn p2pkh_script(pubkey_hash: &[u8]) -> Vec<u8> {
+    [&[118, 169, 20][..], pubkey_hash, &[136, 172][..]].concat()
+}
+
+fn op_return_script(data: &[u8]) -> Vec<u8> {
+    [&[106, data.len() as u8][..], data].concat()
+}
+
+fn encode_cashaddr(pubkey_hash: Vec<u8>) -> String {
+    Address {
+        body: pubkey_hash,
+        scheme: Scheme::CashAddr,
+        hash_type: HashType::Key,
+        network: NETWORK.into(),
+    }
+    .encode()
+    .unwrap()
+}
+
+// Parse a decimal BCH amount, given as either a string or a number, into satoshis
+fn to_satoshis(amount: &Value) -> Option<u64> {
+    let amount = match amount {
+        Value::String(amount) => amount.clone(),
+        Value::Number(amount) => amount.to_string(),
+        _ => return None,
+    };
+    let mut parts = amount.splitn(2, '.');
+    let whole: u64 = parts.next()?.parse().ok()?;
+    let fraction = format!("{:0<8}", parts.next().unwrap_or(""));
+    if fraction.len() != 8 {
+        return None;
+    }
+    Some(whole * 100_000_000 + fraction.parse::<u64>().ok()?)
+}
+
+fn rpc_error(code: i32, message: &str) -> Value {
+    json!({ "code": code, "message": message })
+}
+
+impl MockNode {
+    /// Start the RPC server and ZMQ publisher on ephemeral local ports.
+    pub fn start() -> Self {
+        // Bind ZMQ publisher
+        let context = zmq::Context::new();
+        let publisher = context.socket(zmq::PUB).unwrap();
+        publisher.bind("tcp://127.0.0.1:*").unwrap();
+        let zmq_url = publisher.get_last_endpoint().unwrap().unwrap();
+
+        let mut node = MockNode {
+            rpc_url: String::new(),
+            zmq_url,
+            state: Default::default(),
+            publisher: Arc::new(Mutex::new(publisher)),
+        };
+
+        // Bind RPC server
+        let node_inner = node.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .data(node_inner.clone())
+                .route("/", web::post().to(handle_rpc))
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        node.rpc_url = format!("http://{}", server.addrs()[0]);
+        let _ = server.run(); // Runs until the system stops
+
+        node
+    }
+
+    pub fn rpc_url(&self) -> &str {
+        &self.rpc_url
+    }
+
+    pub fn zmq_url(&self) -> &str {
+        &self.zmq_url
+    }
+
+    /// Client for the mock RPC.
+    pub fn client(&self) -> super::BitcoinClient<json_rpc::clients::http::HttpConnector> {
+        super::BitcoinClient::new(
+            self.rpc_url.clone(),
+            RPC_USERNAME.to_string(),
+            RPC_PASSWORD.to_string(),
+        )
+    }
+
+    /// Transactions accepted via `sendrawtransaction`.
+    pub fn mempool(&self) -> Vec<Transaction> {
+        self.state.lock().unwrap().mempool.clone()
+    }
+
+    /// Publish a raw transaction over ZMQ, as bitcoind does on mempool acceptance.
+    pub fn publish(&self, tx: &Transaction) {
+        let sequence = {
+            let mut state = self.state.lock().unwrap();
+            state.sequence += 1;
+            state.sequence
+        };
+        self.publisher
+            .lock()
+            .unwrap()
+            .send_multipart(
+                &[
+                    &b"rawtx"[..],
+                    &serialize(tx)[..],
+                    &sequence.to_le_bytes()[..],
+                ],
+                0,
+            )
+            .unwrap();
+    }
+
+    /// Publish placeholder transactions until a subscriber has received one, avoiding lost messages
+    /// while the subscription is being established.
+    pub async fn await_subscriber(&self, last_message: &LastMessage) {
+        let ping = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Script::new(),
+                sequence: 0xffff_ffff,
+                witness: vec![],
+            }],
+            output: vec![],
+        };
+        while last_message.elapsed().is_none() {
+            self.publish(&ping);
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Send a transaction as if broadcast by a third party.
+    pub fn broadcast(&self, tx: Transaction) {
+        self.publish(&tx);
+        self.state.lock().unwrap().mempool.push(tx);
+    }
+
+    fn call(&self, method: &str, params: &[Value]) -> Result<Value, Value> {
+        match method {
+            "getblockchaininfo" => Ok(json!({
+                "chain": NETWORK.chain_name(),
+                "blocks": 0,
+            })),
+            "getnewaddress" => {
+                let mut state = self.state.lock().unwrap();
+                state.issued += 1;
+                let pubkey_hash = hash160::Hash::hash(&state.issued.to_le_bytes())[..].to_vec();
+                Ok(json!(encode_cashaddr(pubkey_hash)))
+            }
+            "listunspent" => {
+                let funding_hash = hash160::Hash::hash(b"funding")[..].to_vec();
+                Ok(json!([{
+                    "txid": FUNDING_TXID,
+                    "vout": 0,
+                    "amount": FUNDING_AMOUNT,
+                    "address": encode_cashaddr(funding_hash),
+                }]))
+            }
+            "createrawtransaction" => {
+                let invalid = || rpc_error(-8, "invalid parameter");
+
+                // Inputs
+                let inputs = params
+                    .get(0)
+                    .and_then(Value::as_array)
+                    .ok_or_else(invalid)?;
+                let mut input = Vec::with_capacity(inputs.len());
+                for item in inputs {
+                    let txid = item["txid"].as_str().ok_or_else(invalid)?;
+                    let vout = item["vout"].as_u64().ok_or_else(invalid)? as u32;
+
+                    // Txids are displayed byte reversed
+                    let mut outpoint_raw = hex::decode(txid).map_err(|_| invalid())?;
+                    outpoint_raw.reverse();
+                    outpoint_raw.extend_from_slice(&vout.to_le_bytes());
+                    input.push(TxIn {
+                        previous_output: deserialize::<OutPoint>(&outpoint_raw)
+                            .map_err(|_| invalid())?,
+                        script_sig: Script::new(),
+                        sequence: 0xffff_ffff,
+                        witness: vec![],
+                    });
+                }
+
+                // Outputs, given as an object or an array of single entry objects
+                let outputs: Vec<(String, Value)> = match params.get(1) {
+                    Some(Value::Object(map)) => map.clone().into_iter().collect(),
+                    Some(Value::Array(items)) => items
+                        .iter()
+                        .filter_map(Value::as_object)
+                        .flat_map(|map| map.clone().into_iter())
+                        .collect(),
+                    _ => return Err(invalid()),
+                };
+                let mut output = Vec::with_capacity(outputs.len());
+                for (key, value) in outputs {
+                    let tx_out = if key == "data" {
+                        let data = value
+                            .as_str()
+                            .and_then(|data| hex::decode(data).ok())
+                            .ok_or_else(invalid)?;
+                        TxOut {
+                            value: 0,
+                            script_pubkey: Script::from(op_return_script(&data)),
+                        }
+                    } else {
+                        let addr = Address::decode(&key).map_err(|_| invalid())?;
+                        TxOut {
+                            value: to_satoshis(&value).ok_or_else(invalid)?,
+                            script_pubkey: Script::from(p2pkh_script(&addr.body)),
+                        }
+                    };
+                    output.push(tx_out);
+                }
+
+                let tx = Transaction {
+                    version: 2,
+                    lock_time: 0,
+                    input,
+                    output,
+                };
+                Ok(json!(hex::encode(serialize(&tx))))
+            }
+            "signrawtransactionwithwallet" => {
+                // Signatures are never checked, so the transaction is returned as is
+                let raw_tx = params
+                    .get(0)
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| rpc_error(-8, "invalid parameter"))?;
+                Ok(json!({ "hex": raw_tx, "complete": true }))
+            }
+            "sendrawtransaction" => {
+                let tx = params
+                    .get(0)
+                    .and_then(Value::as_str)
+                    .and_then(|raw_tx| hex::decode(raw_tx).ok())
+                    .and_then(|raw_tx| deserialize::<Transaction>(&raw_tx).ok())
+                    .ok_or_else(|| rpc_error(-22, "TX decode failed"))?;
+                let txid = tx.txid().to_string();
+                self.broadcast(tx);
+                Ok(json!(txid))
+            }
+            _ => Err(rpc_error(-32601, "Method not found")),
+        }
+    }
+}
+
+async fn handle_rpc(request: web::Json<Value>, node: web::Data<MockNode>) -> HttpResponse {
+    let id = request["id"].clone();
+    let method = request["method"].as_str().unwrap_or_default();
+    let params = request["params"].as_array().cloned().unwrap_or_default();
+    let body = match node.call(method, &params) {
+        Ok(result) => json!({ "result": result, "error": null, "id": id }),
+        Err(error) => json!({ "result": null, "error": error, "id": id }),
+    };
+    HttpResponse::Ok().json(body)
+}
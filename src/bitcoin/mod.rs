@@ -1,24 +1,30 @@
+pub mod breaker;
 mod client;
+#[cfg(test)]
+pub mod mock;
 pub mod tx_stream;
 
 pub const PRICE: u64 = 5;
 
 use std::{
-    collections::HashSet,
+    collections::HashMap,
     string::ToString,
     sync::{Arc, RwLock},
 };
 
 use bitcoin::{Transaction, TxOut};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::{crypto::Address, models::bip70::Output, SETTINGS};
+use crate::{
+    crypto::Address,
+    models::bip70::{Output, PaymentDetails},
+};
 
-pub use client::BitcoinClient;
+pub use client::{BitcoinClient, BitcoinError, BlockchainInfo, FundedTx, SignedTx};
 
 const KEYSERVER_PREFIX: &[u8; 9] = b"keyserver";
 
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(rename_all = "lowercase")]
 pub enum Network {
     Mainnet = 0,
@@ -46,6 +52,17 @@ impl Into<bitcoincash_addr::Network> for Network {
     }
 }
 
+impl Network {
+    /// Chain name reported by `getblockchaininfo`.
+    pub fn chain_name(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "main",
+            Network::Testnet => "test",
+            Network::Regnet => "regtest",
+        }
+    }
+}
+
 impl ToString for Network {
     fn to_string(&self) -> String {
         match self {
@@ -56,48 +73,213 @@ impl ToString for Network {
     }
 }
 
+/// Seconds paid and expired invoices are remembered, so their status can still be polled.
+pub const INVOICE_RETENTION: u64 = 3600;
+
+/// Payment state of an invoice.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InvoiceStatus {
+    Pending,
+    Paid,
+    Expired,
+}
+
+/// Invoice issued for a put.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Invoice {
+    /// Stable identifier, served at `/invoices/{id}`.
+    pub id: String,
+    pub details: PaymentDetails,
+    /// Encoded, and possibly signed, BIP70 payment request.
+    pub payment_request: Vec<u8>,
+    /// BIP21 URI for wallets unable to parse the 402 body.
+    pub uri: String,
+    pub paid: bool,
+}
+
+impl Invoice {
+    pub fn issued(&self) -> u64 {
+        self.details.time
+    }
+
+    pub fn expires(&self) -> u64 {
+        self.details.expires.unwrap_or_else(|| self.issued())
+    }
+
+    /// URL of the put the invoice pays for.
+    pub fn merchant_data(&self) -> &[u8] {
+        self.details.merchant_data.as_deref().unwrap_or_default()
+    }
+
+    /// Satoshis the transaction pays to the invoice's payment address.
+    pub fn value_paid(&self, tx: &Transaction) -> u64 {
+        let payment_scripts: Vec<&[u8]> = self
+            .details
+            .outputs
+            .iter()
+            .map(|output| &output.script[..])
+            .filter(|script| extract_pubkey_hash(script).is_some())
+            .collect();
+        tx.output
+            .iter()
+            .filter(|output| payment_scripts.contains(&&output.script_pubkey[..]))
+            .map(|output| output.value)
+            .sum()
+    }
+
+    pub fn status(&self, now: u64) -> InvoiceStatus {
+        if self.paid {
+            InvoiceStatus::Paid
+        } else if now > self.expires() {
+            InvoiceStatus::Expired
+        } else {
+            InvoiceStatus::Pending
+        }
+    }
+}
+
+#[derive(Default)]
+struct Invoices {
+    by_addr: HashMap<Vec<u8>, Invoice>,
+    /// Payment address of each invoice, by ID.
+    addrs_by_id: HashMap<String, Vec<u8>>,
+}
+
+impl Invoices {
+    fn insert(&mut self, addr: Vec<u8>, invoice: Invoice) {
+        let id = invoice.id.clone();
+        if let Some(replaced) = self.by_addr.insert(addr.clone(), invoice) {
+            self.addrs_by_id.remove(&replaced.id);
+        }
+        self.addrs_by_id.insert(id, addr);
+    }
+
+    fn remove(&mut self, addr: &[u8]) -> Option<Invoice> {
+        let invoice = self.by_addr.remove(addr)?;
+        self.addrs_by_id.remove(&invoice.id);
+        Some(invoice)
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(&Invoice) -> bool) {
+        let addrs_by_id = &mut self.addrs_by_id;
+        self.by_addr.retain(|_, invoice| {
+            let kept = keep(invoice);
+            if !kept {
+                addrs_by_id.remove(&invoice.id);
+            }
+            kept
+        });
+    }
+
+    fn get_by_id(&self, id: &str) -> Option<&Invoice> {
+        self.addrs_by_id
+            .get(id)
+            .and_then(|addr| self.by_addr.get(addr))
+    }
+}
+
+/// Issued invoices, keyed by the pubkey hash of each payment address.
 #[derive(Default, Clone)]
-pub struct WalletState(Arc<RwLock<HashSet<Vec<u8>>>>);
+pub struct WalletState(Arc<RwLock<Invoices>>);
+
+// Pubkey hash of the output, if it pays at least the price to a p2pkh address
+fn paid_pubkey_hash(output: &TxOut) -> Option<Vec<u8>> {
+    if output.value < PRICE {
+        return None;
+    }
+    extract_pubkey_hash(&output.script_pubkey[..])
+}
 
 impl WalletState {
-    pub fn add(&self, addr: Vec<u8>) {
-        self.0.write().unwrap().insert(addr);
+    pub fn add(&self, addr: Vec<u8>, invoice: Invoice) {
+        let mut invoices = self.0.write().unwrap();
+
+        // Forget paid and expired invoices past retention
+        let cutoff = invoice.issued().saturating_sub(INVOICE_RETENTION);
+        invoices.retain(|invoice| {
+            if invoice.paid {
+                invoice.issued() >= cutoff
+            } else {
+                invoice.expires() >= cutoff
+            }
+        });
+
+        invoices.insert(addr, invoice);
     }
 
     pub fn remove(&self, addr: Vec<u8>) {
         self.0.write().unwrap().remove(&addr);
     }
 
-    pub fn check_p2pkh(&self, output: &TxOut) -> bool {
-        // Check first output
-        let value = output.value;
-        if value != PRICE {
-            return false;
+    /// Cancel a pending invoice, returning whether it existed.
+    pub fn cancel(&self, addr: &[u8]) -> bool {
+        let mut invoices = self.0.write().unwrap();
+        match invoices.by_addr.get(addr) {
+            Some(invoice) if !invoice.paid => invoices.remove(addr).is_some(),
+            _ => false,
         }
+    }
 
-        // Check p2pkh addr
-        let script = &output.script_pubkey[..];
-        if let Some(pubkey_hash) = extract_pubkey_hash(script) {
-            // Check if wallet contains that address
-            if self.0.read().unwrap().contains(&pubkey_hash) {
-                // Flush address
-                self.0.write().unwrap().remove(&pubkey_hash);
-                true
-            } else {
-                false
-            }
-        } else {
-            false
-        }
+    /// Look up an invoice by ID.
+    pub fn get(&self, id: &str) -> Option<Invoice> {
+        self.0.read().unwrap().get_by_id(id).cloned()
+    }
+
+    /// List invoices still pending at `now` by payment address and issue time.
+    pub fn pending(&self, now: u64) -> Vec<(Vec<u8>, u64)> {
+        let mut pending: Vec<(Vec<u8>, u64)> = self
+            .0
+            .read()
+            .unwrap()
+            .by_addr
+            .iter()
+            .filter(|(_, invoice)| invoice.status(now) == InvoiceStatus::Pending)
+            .map(|(addr, invoice)| (addr.clone(), invoice.issued()))
+            .collect();
+        pending.sort_by_key(|(_, issued)| *issued);
+        pending
+    }
+
+    /// Find the invoice pending at `now` paid by a transaction, leaving it pending.
+    pub fn match_outputs(&self, tx: &Transaction, now: u64) -> Option<Invoice> {
+        // TODO: Enforce op_return outputs
+        let invoices = self.0.read().unwrap();
+        tx.output
+            .iter()
+            .filter_map(paid_pubkey_hash)
+            .filter_map(|pubkey_hash| invoices.by_addr.get(&pubkey_hash))
+            .find(|invoice| invoice.status(now) == InvoiceStatus::Pending)
+            .cloned()
     }
 
-    pub fn check_outputs(&self, tx: Transaction) -> bool {
+    /// Mark the invoice pending at `now` paid by a transaction as paid, returning it.
+    pub fn settle_outputs(&self, tx: &Transaction, now: u64) -> Option<Invoice> {
         // TODO: Enforce op_return outputs
-        tx.output.iter().any(|output| self.check_p2pkh(output))
+        let mut invoices = self.0.write().unwrap();
+        let pubkey_hash = tx
+            .output
+            .iter()
+            .filter_map(paid_pubkey_hash)
+            .find(|pubkey_hash| {
+                invoices
+                    .by_addr
+                    .get(pubkey_hash)
+                    .map(|invoice| invoice.status(now) == InvoiceStatus::Pending)
+                    .unwrap_or(false)
+            })?;
+        let invoice = invoices.by_addr.get_mut(&pubkey_hash)?;
+        invoice.paid = true;
+        Some(invoice.clone())
     }
 }
 
-pub fn extract_op_return(script: &[u8]) -> Option<(String, Address)> {
+/// Extract the peer URL and address advertised by a keyserver OP_RETURN, ignoring our own URL.
+pub fn extract_op_return(
+    script: &[u8],
+    network: &Network,
+    own_url: &str,
+) -> Option<(String, Address)> {
     // OP_RETURN || LEN || keyserver || bitcoin pk hash || peer host
     if script.len() <= 2 + 9 + 20 {
         // Too short
@@ -128,7 +310,7 @@ pub fn extract_op_return(script: &[u8]) -> Option<(String, Address)> {
 
     // Don't get from ourselves
     // TODO: This is super crude
-    if url == format!("http://{}", SETTINGS.bind) {
+    if url == own_url {
         return None;
     }
 
@@ -136,13 +318,13 @@ pub fn extract_op_return(script: &[u8]) -> Option<(String, Address)> {
     let bitcoin_addr_raw = script[11..31].to_vec();
     let bitcoin_addr = Address {
         body: bitcoin_addr_raw,
-        network: SETTINGS.network.clone().into(),
+        network: network.clone().into(),
         ..Default::default()
     };
     Some((url, bitcoin_addr))
 }
 
-fn extract_pubkey_hash(raw_script: &[u8]) -> Option<Vec<u8>> {
+pub fn extract_pubkey_hash(raw_script: &[u8]) -> Option<Vec<u8>> {
     if raw_script.len() != 25 {
         return None;
     }
@@ -196,4 +378,163 @@ mod tests {
         let extracted_pkh = extract_pubkey_hash(&outputs.get(0).unwrap().script[..]);
         assert_eq!(pk_hash, extracted_pkh.unwrap());
     }
+
+    #[test]
+    fn test_match_settle_outputs() {
+        let pk_hash = [3; 20].to_vec();
+        let wallet_state = WalletState::default();
+        let outputs = generate_outputs(pk_hash.clone(), "", pk_hash.clone());
+        let invoice = Invoice {
+            id: "id".to_string(),
+            details: PaymentDetails {
+                network: Some("regnet".to_string()),
+                outputs: outputs.clone(),
+                time: 1,
+                expires: Some(31),
+                memo: None,
+                payment_url: None,
+                merchant_data: Some(b"http://localhost/keys/addr".to_vec()),
+            },
+            payment_request: vec![],
+            uri: String::new(),
+            paid: false,
+        };
+        wallet_state.add(pk_hash, invoice.clone());
+        assert_eq!(invoice.status(31), InvoiceStatus::Pending);
+        assert_eq!(invoice.status(32), InvoiceStatus::Expired);
+
+        let tx = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: PRICE,
+                script_pubkey: outputs[0].script.clone().into(),
+            }],
+        };
+
+        // Matching leaves the invoice pending, settling marks it paid
+        assert_eq!(wallet_state.match_outputs(&tx, 31), Some(invoice.clone()));
+        let paid = wallet_state.settle_outputs(&tx, 31).unwrap();
+        assert_eq!(paid.status(32), InvoiceStatus::Paid);
+        assert_eq!(paid.value_paid(&tx), PRICE);
+        assert_eq!(wallet_state.get("id"), Some(paid));
+        assert!(wallet_state.pending(31).is_empty());
+
+        // Paid invoices can't be paid again
+        assert_eq!(wallet_state.match_outputs(&tx, 31), None);
+        assert_eq!(wallet_state.settle_outputs(&tx, 31), None);
+
+        // Overpaying settles an invoice too
+        let overpaid = Invoice {
+            id: "overpaid".to_string(),
+            ..invoice.clone()
+        };
+        wallet_state.add([5; 20].to_vec(), overpaid.clone());
+        let overpaying_tx = Transaction {
+            output: vec![TxOut {
+                value: PRICE + 1,
+                script_pubkey: generate_outputs([5; 20].to_vec(), "", vec![])[0]
+                    .script
+                    .clone()
+                    .into(),
+            }],
+            ..tx.clone()
+        };
+        assert_eq!(
+            wallet_state.match_outputs(&overpaying_tx, 31),
+            Some(overpaid)
+        );
+        let settled = wallet_state.settle_outputs(&overpaying_tx, 31).unwrap();
+        assert!(settled.paid);
+
+        // Paid invoices are forgotten after retention
+        wallet_state.add(
+            [4; 20].to_vec(),
+            Invoice {
+                id: "other".to_string(),
+                details: PaymentDetails {
+                    time: 2 + INVOICE_RETENTION,
+                    ..invoice.details.clone()
+                },
+                ..invoice
+            },
+        );
+        assert_eq!(wallet_state.get("id"), None);
+    }
+
+    #[test]
+    fn test_expired_unpayable() {
+        let pk_hash = [3; 20].to_vec();
+        let wallet_state = WalletState::default();
+        let outputs = generate_outputs(pk_hash.clone(), "", pk_hash.clone());
+        let invoice = Invoice {
+            id: "id".to_string(),
+            details: PaymentDetails {
+                network: Some("regnet".to_string()),
+                outputs: outputs.clone(),
+                time: 1,
+                expires: Some(31),
+                memo: None,
+                payment_url: None,
+                merchant_data: None,
+            },
+            payment_request: vec![],
+            uri: String::new(),
+            paid: false,
+        };
+        wallet_state.add(pk_hash, invoice);
+        let tx = Transaction {
+            version: 1,
+            lock_time: 0,
+            input: vec![],
+            output: vec![TxOut {
+                value: PRICE,
+                script_pubkey: outputs[0].script.clone().into(),
+            }],
+        };
+
+        // Payments arriving after expiry neither match nor settle the invoice
+        assert_eq!(wallet_state.match_outputs(&tx, 32), None);
+        assert_eq!(wallet_state.settle_outputs(&tx, 32), None);
+        assert!(!wallet_state.get("id").unwrap().paid);
+    }
+
+    #[test]
+    fn test_prune_expired() {
+        let wallet_state = WalletState::default();
+        let invoice = |id: &str, time: u64| Invoice {
+            id: id.to_string(),
+            details: PaymentDetails {
+                network: Some("regnet".to_string()),
+                outputs: vec![],
+                time,
+                expires: Some(time + 30),
+                memo: None,
+                payment_url: None,
+                merchant_data: None,
+            },
+            payment_request: vec![],
+            uri: String::new(),
+            paid: false,
+        };
+
+        // Expired invoices are kept for retention
+        wallet_state.add([1; 20].to_vec(), invoice("first", 1));
+        wallet_state.add([2; 20].to_vec(), invoice("second", 31 + INVOICE_RETENTION));
+        assert!(wallet_state.get("first").is_some());
+
+        // Then forgotten, along with their ID
+        wallet_state.add([3; 20].to_vec(), invoice("third", 32 + INVOICE_RETENTION));
+        assert_eq!(wallet_state.get("first"), None);
+        assert_eq!(wallet_state.pending(31 + INVOICE_RETENTION).len(), 2);
+
+        // Expired invoices are no longer pending
+        assert_eq!(wallet_state.pending(62 + INVOICE_RETENTION).len(), 1);
+
+        // Replacing an invoice forgets the old ID
+        wallet_state.add([3; 20].to_vec(), invoice("fourth", 32 + INVOICE_RETENTION));
+        assert_eq!(wallet_state.get("third"), None);
+        assert!(wallet_state.get("fourth").is_some());
+    }
 }
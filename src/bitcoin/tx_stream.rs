@@ -1,13 +1,28 @@
+use std::{
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
 use bitcoin::{consensus::encode, util::psbt::serialize::Deserialize, Transaction};
 use bitcoin_zmq::{
     errors::{SubscriptionError, ZMQError},
     ZMQListener,
 };
-use futures::prelude::*;
+use futures::{prelude::*, stream};
+
+use crate::{
+    clock::Clock,
+    crypto::Address,
+    metrics::{INVOICES_SETTLED, KEYSERVER_OP_RETURNS, SATOSHIS_EARNED, ZMQ_TRANSACTIONS},
+};
 
-use crate::crypto::Address;
+use super::{extract_op_return, Network, WalletState};
 
-use super::extract_op_return;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
 
 #[derive(Debug)]
 pub enum StreamError {
@@ -21,12 +36,62 @@ impl From<SubscriptionError> for StreamError {
     }
 }
 
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+fn since(time: i64) -> Option<Duration> {
+    match time {
+        0 => None,
+        time => Some(Duration::from_secs((now() - time).max(0) as u64)),
+    }
+}
+
+/// Times of the last ZMQ subscription and of the last message received over it.
+#[derive(Clone, Default)]
+pub struct LastMessage {
+    message: Arc<AtomicI64>,
+    subscribed: Arc<AtomicI64>,
+}
+
+impl LastMessage {
+    pub fn touch(&self) {
+        self.message.store(now(), Ordering::Relaxed);
+    }
+
+    pub fn subscribe(&self) {
+        self.subscribed.store(now(), Ordering::Relaxed);
+    }
+
+    /// Time since the last message, if one has been received.
+    pub fn elapsed(&self) -> Option<Duration> {
+        since(self.message.load(Ordering::Relaxed))
+    }
+
+    /// Time since the last message or, if none has arrived since, the last subscription.
+    pub fn idle(&self) -> Option<Duration> {
+        let message = self.message.load(Ordering::Relaxed);
+        let subscribed = self.subscribed.load(Ordering::Relaxed);
+        if subscribed == 0 {
+            return None;
+        }
+        since(message.max(subscribed))
+    }
+}
+
 pub async fn get_tx_stream(
     node_addr: &str,
+    last_message: LastMessage,
 ) -> Result<impl Stream<Item = Result<Transaction, StreamError>>, ZMQError> {
     let stream = ZMQListener::bind(node_addr).await?.stream();
+    last_message.subscribe();
     let stream = stream
+        .inspect(move |_| last_message.touch())
         .map_err(StreamError::Subscription)
+        .inspect_ok(|_| ZMQ_TRANSACTIONS.inc())
         .and_then(move |raw_tx| {
             async move { Transaction::deserialize(&raw_tx).map_err(StreamError::Deserialization) }
         });
@@ -34,17 +99,74 @@ pub async fn get_tx_stream(
     Ok(stream)
 }
 
+/// Transaction stream which reconnects to ZMQ in the background, backing off exponentially
+/// while the node is unreachable.
+pub fn reconnecting_tx_stream(
+    node_addr: String,
+    last_message: LastMessage,
+) -> impl Stream<Item = Result<Transaction, StreamError>> {
+    stream::unfold(None, move |backoff: Option<Duration>| {
+        let node_addr = node_addr.clone();
+        let last_message = last_message.clone();
+        async move {
+            let mut backoff = backoff;
+            loop {
+                // Wait before every attempt but the first
+                if let Some(delay) = backoff {
+                    tokio::time::delay_for(delay).await;
+                }
+                match get_tx_stream(&node_addr, last_message.clone()).await {
+                    Ok(tx_stream) => {
+                        info!("connected to ZMQ @ {}", node_addr);
+                        return Some((tx_stream, Some(MIN_BACKOFF)));
+                    }
+                    Err(err) => {
+                        let delay =
+                            backoff.map_or(MIN_BACKOFF, |delay| (delay * 2).min(MAX_BACKOFF));
+                        warn!(
+                            "could not connect to ZMQ, retrying in {:?}: {:?}",
+                            delay, err
+                        );
+                        backoff = Some(delay);
+                    }
+                }
+            }
+        }
+    })
+    .flatten()
+}
+
+/// Mark invoices paid by transactions in the stream, for wallets broadcasting payments themselves.
+/// Invoices expired by the clock's time are not settled.
+pub fn settle_invoices(
+    stream: impl Stream<Item = Result<Transaction, StreamError>>,
+    wallet_state: WalletState,
+    clock: Clock,
+) -> impl Stream<Item = Result<Transaction, StreamError>> {
+    stream.inspect_ok(move |tx| {
+        if let Some(invoice) = wallet_state.settle_outputs(tx, clock.now() as u64) {
+            info!("invoice {} paid over ZMQ", invoice.id);
+            INVOICES_SETTLED.with_label_values(&["zmq"]).inc();
+            SATOSHIS_EARNED.inc_by(invoice.value_paid(tx) as i64);
+        }
+    })
+}
+
 // Extract peer address, bitcoin address and metadata digest from tx stream
 pub fn extract_details(
     stream: impl Stream<Item = Result<Transaction, StreamError>>,
+    network: Network,
+    own_url: String,
 ) -> impl Stream<Item = Result<(String, Address), StreamError>> {
-    stream.try_filter_map(|tx| {
+    stream.try_filter_map(move |tx| {
+        let details = tx.output.iter().find_map(|output| {
+            extract_op_return(output.script_pubkey.as_bytes(), &network, &own_url)
+        });
         async move {
-            Ok(tx
-                .output
-                .iter()
-                .map(|output| output.script_pubkey.as_bytes())
-                .find_map(extract_op_return))
+            if details.is_some() {
+                KEYSERVER_OP_RETURNS.inc();
+            }
+            Ok(details)
         }
     })
 }
@@ -0,0 +1,116 @@
+use std::{
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const FROZEN_POLL: Duration = Duration::from_millis(10);
+
+fn system_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+/// Source of the current UNIX time, in seconds.
+///
+/// The system clock may be shifted by an offset, for example to simulate expiry against a copy
+/// of production data, and a frozen clock only moves when advanced, letting tests fast-forward.
+#[derive(Clone, Debug, Default)]
+pub struct Clock {
+    frozen: Option<i64>,
+    offset: Arc<AtomicI64>,
+}
+
+impl Clock {
+    pub fn system() -> Self {
+        Clock::default()
+    }
+
+    /// Clock stopped at the current time.
+    pub fn frozen() -> Self {
+        Clock {
+            frozen: Some(system_now()),
+            ..Default::default()
+        }
+    }
+
+    /// Shift the clock by a number of seconds. The shifted clock has its own offset, leaving
+    /// clones of the original untouched.
+    pub fn with_offset(self, offset: i64) -> Self {
+        let offset = self.offset.load(Ordering::Relaxed) + offset;
+        Clock {
+            offset: Arc::new(AtomicI64::new(offset)),
+            ..self
+        }
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen.is_some()
+    }
+
+    pub fn now(&self) -> i64 {
+        self.frozen.unwrap_or_else(system_now) + self.offset.load(Ordering::Relaxed)
+    }
+
+    /// Move the clock forward, affecting every clone.
+    pub fn advance(&self, duration: Duration) {
+        self.offset
+            .fetch_add(duration.as_secs() as i64, Ordering::Relaxed);
+    }
+
+    /// Wait until the clock has moved forward by at least `duration`.
+    pub async fn sleep(&self, duration: Duration) {
+        if !self.is_frozen() {
+            tokio::time::delay_for(duration).await;
+            return;
+        }
+
+        // Round up to whole seconds and wait for the clock to be advanced
+        let secs = duration.as_secs() + if duration.subsec_nanos() > 0 { 1 } else { 0 };
+        let deadline = self.now() + secs as i64;
+        while self.now() < deadline {
+            tokio::time::delay_for(FROZEN_POLL).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+
+    #[test]
+    fn test_offset() {
+        let before = system_now();
+        let clock = Clock::system().with_offset(3600);
+        assert!(clock.now() >= before + 3600);
+
+        // Shifting leaves clones of the original untouched
+        let frozen = Clock::frozen();
+        let shifted = frozen.clone().with_offset(60);
+        assert_eq!(shifted.now(), frozen.now() + 60);
+        shifted.advance(Duration::from_secs(30));
+        assert_eq!(shifted.now(), frozen.now() + 90);
+    }
+
+    #[actix_rt::test]
+    async fn test_frozen() {
+        let clock = Clock::frozen();
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+
+        // Advancing is seen by clones
+        let clone = clock.clone();
+        clone.advance(Duration::from_secs(30));
+        assert_eq!(clock.now(), start + 30);
+
+        // Sleeps wait for the clock to be advanced
+        let sleep = clock.sleep(Duration::from_secs(60));
+        let advance = async { clock.advance(Duration::from_secs(60)) };
+        future::join(sleep, advance).await;
+    }
+}
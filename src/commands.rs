@@ -0,0 +1,161 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+};
+
+use clap::ArgMatches;
+use prost::Message;
+use serde_json::json;
+
+use crate::{
+    clock::Clock,
+    crypto::{authentication::validate, ecdsa::Secp256k1, Address},
+    db::{backup::BackupRecord, Database, KeyDB},
+    models::address_metadata::{AddressMetadata, Payload},
+    settings::Settings,
+};
+
+fn to_io_error<E: ToString>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, err.to_string())
+}
+
+fn open_db(settings: &Settings) -> io::Result<KeyDB> {
+    // Keep the search index in step with any writes
+    KeyDB::try_new(&settings.db_path)
+        .and_then(|key_db| key_db.with_search_index(settings.search_index))
+        .map_err(to_io_error)
+}
+
+// Ask the running server for a checkpoint, as it holds the database lock. The name is resolved
+// by the server within its snapshot directory.
+async fn live_snapshot(settings: &Settings, name: &str) -> io::Result<()> {
+    let admin_key = settings
+        .admin_key
+        .as_ref()
+        .ok_or_else(|| to_io_error("live snapshots require the admin key"))?;
+    let url = format!("http://{}/admin/db/snapshot", settings.bind);
+    reqwest::Client::new()
+        .post(&url)
+        .bearer_auth(admin_key)
+        .json(&json!({ "path": name }))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(to_io_error)?;
+    info!(
+        "created snapshot {} in the running server's snapshot directory",
+        name
+    );
+    Ok(())
+}
+
+/// Database maintenance subcommands
+pub async fn db(settings: &Settings, matches: &ArgMatches) -> io::Result<()> {
+    if let ("snapshot", Some(snapshot_matches)) = matches.subcommand() {
+        if snapshot_matches.is_present("live") {
+            let name = snapshot_matches.value_of("path").unwrap();
+            return live_snapshot(settings, name).await;
+        }
+    }
+    let key_db = open_db(settings)?;
+
+    match matches.subcommand() {
+        ("scrub", Some(_)) => {
+            let report = key_db.scrub().map_err(to_io_error)?;
+            info!(
+                "checked {} records, quarantined {} ({} undecodable, {} invalid)",
+                report.checked,
+                report.quarantined(),
+                report.undecodable,
+                report.invalid
+            );
+        }
+        ("sweep", Some(sweep_matches)) => {
+            // Judge expiry against a shifted clock, to simulate sweeping in the future or past
+            let offset = match sweep_matches.value_of("offset") {
+                Some(offset) => offset.parse().map_err(to_io_error)?,
+                None => 0,
+            };
+            let key_db = key_db.with_clock(Clock::system().with_offset(offset));
+            let removed = key_db.remove_expired().map_err(to_io_error)?;
+            info!("removed {} expired records", removed);
+        }
+        ("snapshot", Some(snapshot_matches)) => {
+            let path = snapshot_matches.value_of("path").unwrap();
+            key_db.checkpoint(path).map_err(to_io_error)?;
+            info!("created snapshot at {}", path);
+        }
+        _ => unreachable!(),
+    }
+    Ok(())
+}
+
+/// Export every live record as JSON lines
+pub fn export(settings: &Settings, matches: &ArgMatches) -> io::Result<()> {
+    let key_db = open_db(settings)?;
+    let path = matches.value_of("output").unwrap();
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    let mut exported = 0;
+    for (addr_raw, raw_metadata) in key_db.live_records() {
+        let addr = Address {
+            body: addr_raw.to_vec(),
+            network: settings.network.clone().into(),
+            ..Default::default()
+        };
+        let record = BackupRecord {
+            address: addr.encode().map_err(to_io_error)?,
+            metadata: base64::encode(&raw_metadata),
+        };
+        serde_json::to_writer(&mut writer, &record)?;
+        writeln!(writer)?;
+        exported += 1;
+    }
+    writer.flush()?;
+
+    info!("exported {} records to {}", exported, path);
+    Ok(())
+}
+
+// Validate a record and insert it if it is newer than what is stored
+fn import_record(key_db: &KeyDB, line: &str) -> Result<(), String> {
+    let record: BackupRecord = serde_json::from_str(line).map_err(|err| err.to_string())?;
+    let addr = Address::decode(&record.address)
+        .map_err(|(cash_err, base58_err)| format!("{}, {}", cash_err, base58_err))?;
+    let raw_metadata = base64::decode(&record.metadata).map_err(|err| err.to_string())?;
+    let metadata = AddressMetadata::decode(&raw_metadata[..]).map_err(|err| err.to_string())?;
+
+    // TODO: Support Schnorr
+    match metadata.scheme {
+        1 => validate::<Secp256k1>(&addr, &metadata).map_err(|err| err.to_string())?,
+        scheme => return Err(format!("unsupported signature scheme {}", scheme)),
+    }
+
+    let payload =
+        Payload::decode(&metadata.serialized_payload[..]).map_err(|err| err.to_string())?;
+    key_db
+        .put_checked(&addr, &metadata, &payload)
+        .map_err(|err| err.to_string())?
+        .map_err(|err| err.to_string())
+}
+
+/// Validate and import records from JSON lines
+pub fn import(settings: &Settings, matches: &ArgMatches) -> io::Result<()> {
+    let key_db = open_db(settings)?;
+    let path = matches.value_of("input").unwrap();
+    let reader = BufReader::new(File::open(path)?);
+
+    let (mut imported, mut skipped) = (0, 0);
+    for (index, line) in reader.lines().enumerate() {
+        match import_record(&key_db, &line?) {
+            Ok(()) => imported += 1,
+            Err(err) => {
+                warn!("skipping line {}: {}", index + 1, err);
+                skipped += 1;
+            }
+        }
+    }
+
+    info!("imported {} records, skipped {}", imported, skipped);
+    Ok(())
+}
@@ -0,0 +1,335 @@
+//! BIP70 `x509+sha256` signing of payment requests.
+
+use std::{fmt, fs, io};
+
+use openssl::{
+    error::ErrorStack,
+    hash::MessageDigest,
+    pkey::{PKey, Private},
+    sign::{Signer, Verifier},
+    stack::Stack,
+    x509::{store::X509Store, X509StoreContext, X509},
+};
+use prost::Message;
+
+use crate::models::bip70::{PaymentRequest, X509Certificates};
+
+pub const PKI_TYPE: &str = "x509+sha256";
+
+#[derive(Debug)]
+pub enum PkiError {
+    Io(io::Error),
+    OpenSsl(ErrorStack),
+    EmptyChain,
+    KeyMismatch,
+    UnsupportedPkiType,
+    Decode,
+    InvalidSignature,
+    UntrustedChain(String),
+}
+
+impl fmt::Display for PkiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let printable = match self {
+            PkiError::Io(err) => return err.fmt(f),
+            PkiError::OpenSsl(err) => return err.fmt(f),
+            PkiError::EmptyChain => "certificate chain is empty",
+            PkiError::KeyMismatch => "private key does not match the leaf certificate",
+            PkiError::UnsupportedPkiType => "unsupported pki type",
+            PkiError::Decode => "failed to decode certificates",
+            PkiError::InvalidSignature => "invalid signature",
+            PkiError::UntrustedChain(reason) => {
+                return write!(f, "untrusted certificate chain: {}", reason)
+            }
+        };
+        write!(f, "{}", printable)
+    }
+}
+
+impl From<io::Error> for PkiError {
+    fn from(err: io::Error) -> Self {
+        PkiError::Io(err)
+    }
+}
+
+impl From<ErrorStack> for PkiError {
+    fn from(err: ErrorStack) -> Self {
+        PkiError::OpenSsl(err)
+    }
+}
+
+// Serialize the request with an empty signature, as is signed
+fn signing_bytes(payment_request: &PaymentRequest) -> Vec<u8> {
+    let unsigned = PaymentRequest {
+        signature: Some(vec![]),
+        ..payment_request.clone()
+    };
+    let mut raw = Vec::with_capacity(unsigned.encoded_len());
+    unsigned.encode(&mut raw).unwrap();
+    raw
+}
+
+/// Merchant certificate chain and key signing payment requests.
+pub struct PaymentSigner {
+    pki_data: Vec<u8>,
+    key: PKey<Private>,
+}
+
+impl PaymentSigner {
+    /// Create from a PEM certificate chain, leaf first, and the PEM private key of the leaf.
+    pub fn from_pem(chain_pem: &[u8], key_pem: &[u8]) -> Result<Self, PkiError> {
+        let chain = X509::stack_from_pem(chain_pem)?;
+        if chain.is_empty() {
+            return Err(PkiError::EmptyChain);
+        }
+
+        // The key must be the one certified by the leaf
+        let key = PKey::private_key_from_pem(key_pem)?;
+        if !chain[0].public_key()?.public_eq(&key) {
+            return Err(PkiError::KeyMismatch);
+        }
+
+        let certificates = X509Certificates {
+            certificate: chain
+                .iter()
+                .map(|certificate| certificate.to_der())
+                .collect::<Result<_, _>>()?,
+        };
+        let mut pki_data = Vec::with_capacity(certificates.encoded_len());
+        certificates.encode(&mut pki_data).unwrap();
+
+        Ok(PaymentSigner { pki_data, key })
+    }
+
+    pub fn from_pem_files(chain_path: &str, key_path: &str) -> Result<Self, PkiError> {
+        Self::from_pem(&fs::read(chain_path)?, &fs::read(key_path)?)
+    }
+
+    /// Attach the certificate chain and sign.
+    pub fn sign(&self, payment_request: &mut PaymentRequest) -> Result<(), PkiError> {
+        payment_request.pki_type = Some(PKI_TYPE.to_string());
+        payment_request.pki_data = Some(self.pki_data.clone());
+
+        let mut signer = Signer::new(MessageDigest::sha256(), &self.key)?;
+        signer.update(&signing_bytes(payment_request))?;
+        payment_request.signature = Some(signer.sign_to_vec()?);
+        Ok(())
+    }
+}
+
+/// Verify the signature of a payment request and that its chain leads to a trusted root.
+pub fn verify(payment_request: &PaymentRequest, trusted: &X509Store) -> Result<(), PkiError> {
+    if payment_request.pki_type.as_deref() != Some(PKI_TYPE) {
+        return Err(PkiError::UnsupportedPkiType);
+    }
+
+    // Decode chain
+    let raw_certificates = payment_request
+        .pki_data
+        .as_ref()
+        .ok_or(PkiError::EmptyChain)?;
+    let certificates =
+        X509Certificates::decode(&raw_certificates[..]).map_err(|_| PkiError::Decode)?;
+    let mut chain = certificates
+        .certificate
+        .iter()
+        .map(|der| X509::from_der(der))
+        .collect::<Result<Vec<_>, _>>()?;
+    if chain.is_empty() {
+        return Err(PkiError::EmptyChain);
+    }
+    let leaf = chain.remove(0);
+
+    // Check signature
+    let signature = payment_request
+        .signature
+        .as_ref()
+        .ok_or(PkiError::InvalidSignature)?;
+    let public_key = leaf.public_key()?;
+    let mut verifier = Verifier::new(MessageDigest::sha256(), &public_key)?;
+    verifier.update(&signing_bytes(payment_request))?;
+    if !verifier.verify(signature)? {
+        return Err(PkiError::InvalidSignature);
+    }
+
+    // Check chain
+    let mut intermediates = Stack::new()?;
+    for certificate in chain {
+        intermediates.push(certificate)?;
+    }
+    let mut context = X509StoreContext::new()?;
+    let reason = context.init(trusted, &leaf, &intermediates, |context| {
+        Ok(if context.verify_cert()? {
+            None
+        } else {
+            Some(context.error().to_string())
+        })
+    })?;
+    match reason {
+        Some(reason) => Err(PkiError::UntrustedChain(reason)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use openssl::{
+        asn1::Asn1Time,
+        bn::BigNum,
+        rsa::Rsa,
+        x509::{
+            extension::BasicConstraints, store::X509StoreBuilder, X509Builder, X509NameBuilder,
+        },
+    };
+
+    use super::*;
+
+    pub struct TestCa {
+        pub root: X509,
+        pub chain_pem: Vec<u8>,
+        pub key_pem: Vec<u8>,
+    }
+
+    fn generate_key() -> PKey<Private> {
+        PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap()
+    }
+
+    fn build_certificate(
+        common_name: &str,
+        serial: u32,
+        key: &PKey<Private>,
+        issuer: Option<(&X509, &PKey<Private>)>,
+        is_ca: bool,
+    ) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = BigNum::from_u32(serial).unwrap().to_asn1_integer().unwrap();
+        builder.set_serial_number(&serial).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        match issuer {
+            Some((issuer_cert, _)) => builder.set_issuer_name(issuer_cert.subject_name()),
+            None => builder.set_issuer_name(&name),
+        }
+        .unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        if is_ca {
+            let constraints = BasicConstraints::new().critical().ca().build().unwrap();
+            builder.append_extension(constraints).unwrap();
+        }
+        let signing_key = issuer.map(|(_, issuer_key)| issuer_key).unwrap_or(key);
+        builder.sign(signing_key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    /// Root, intermediate and leaf certificates, with the chain and leaf key as PEM.
+    pub fn test_ca() -> TestCa {
+        let root_key = generate_key();
+        let root = build_certificate("Test Root", 1, &root_key, None, true);
+        let intermediate_key = generate_key();
+        let intermediate = build_certificate(
+            "Test Intermediate",
+            2,
+            &intermediate_key,
+            Some((&root, &root_key)),
+            true,
+        );
+        let leaf_key = generate_key();
+        let leaf = build_certificate(
+            "keyserver.test",
+            3,
+            &leaf_key,
+            Some((&intermediate, &intermediate_key)),
+            false,
+        );
+
+        TestCa {
+            root,
+            chain_pem: [leaf.to_pem().unwrap(), intermediate.to_pem().unwrap()].concat(),
+            key_pem: leaf_key.private_key_to_pem_pkcs8().unwrap(),
+        }
+    }
+
+    pub fn trust(root: X509) -> X509Store {
+        let mut store = X509StoreBuilder::new().unwrap();
+        store.add_cert(root).unwrap();
+        store.build()
+    }
+
+    fn payment_request() -> PaymentRequest {
+        PaymentRequest {
+            payment_details_version: Some(1),
+            pki_type: Some("none".to_string()),
+            pki_data: None,
+            serialized_payment_details: vec![1, 2, 3],
+            signature: None,
+        }
+    }
+
+    #[test]
+    fn test_sign_verify() {
+        let ca = test_ca();
+        let signer = PaymentSigner::from_pem(&ca.chain_pem, &ca.key_pem).unwrap();
+        let mut request = payment_request();
+        signer.sign(&mut request).unwrap();
+        assert_eq!(request.pki_type.as_deref(), Some(PKI_TYPE));
+
+        // Chain is leaf first
+        let certificates =
+            X509Certificates::decode(&request.pki_data.clone().unwrap()[..]).unwrap();
+        assert_eq!(certificates.certificate.len(), 2);
+
+        verify(&request, &trust(ca.root)).unwrap();
+    }
+
+    #[test]
+    fn test_verify_tampered() {
+        let ca = test_ca();
+        let signer = PaymentSigner::from_pem(&ca.chain_pem, &ca.key_pem).unwrap();
+        let mut request = payment_request();
+        signer.sign(&mut request).unwrap();
+
+        // Swap the payment details
+        request.serialized_payment_details = vec![4, 5, 6];
+        match verify(&request, &trust(ca.root)) {
+            Err(PkiError::InvalidSignature) => (),
+            other => panic!("expected invalid signature, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_verify_untrusted() {
+        let ca = test_ca();
+        let signer = PaymentSigner::from_pem(&ca.chain_pem, &ca.key_pem).unwrap();
+        let mut request = payment_request();
+        signer.sign(&mut request).unwrap();
+
+        // Trust a different root
+        let other_key = generate_key();
+        let other_root = build_certificate("Other Root", 4, &other_key, None, true);
+        match verify(&request, &trust(other_root)) {
+            Err(PkiError::UntrustedChain(_)) => (),
+            other => panic!("expected untrusted chain, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_key_mismatch() {
+        let ca = test_ca();
+        let other_key = generate_key().private_key_to_pem_pkcs8().unwrap();
+        match PaymentSigner::from_pem(&ca.chain_pem, &other_key) {
+            Err(PkiError::KeyMismatch) => (),
+            Err(err) => panic!("expected key mismatch, got {:?}", err),
+            Ok(_) => panic!("expected key mismatch"),
+        }
+    }
+}
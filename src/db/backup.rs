@@ -0,0 +1,79 @@
+use std::path::Path;
+
+use rocksdb::{checkpoint::Checkpoint, IteratorMode};
+use serde_derive::{Deserialize, Serialize};
+
+use super::{decode_record, errors::DBError, expiry, KeyDB};
+
+/// A single line of an export file.
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct BackupRecord {
+    /// Address encoded for the configured network
+    pub address: String,
+    /// Base64 encoded `AddressMetadata`
+    pub metadata: String,
+}
+
+impl KeyDB {
+    /// Iterate over every unexpired record. RocksDB iterators read from an implicit snapshot, so
+    /// the records are consistent even if the database is written to during iteration.
+    pub fn live_records(&self) -> impl Iterator<Item = (Box<[u8]>, Box<[u8]>)> + '_ {
+        let now = self.clock.now();
+        self.db
+            .iterator(IteratorMode::Start)
+            .filter(move |(_, value)| match decode_record(value) {
+                Ok((_, payload)) => expiry(&payload) >= now,
+                Err(_) => false,
+            })
+    }
+
+    /// Create a consistent copy of the database at the given path, hard-linking where possible.
+    pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<(), DBError> {
+        Checkpoint::new(&self.db)?.create_checkpoint(path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        crypto::Address,
+        db::{tests::metadata_with_entries, Database},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_live_records_and_checkpoint() {
+        let _ = std::fs::remove_dir_all("./test_db/backup");
+        let _ = std::fs::remove_dir_all("./test_db/backup_checkpoint");
+        let key_db = KeyDB::try_new("./test_db/backup").unwrap();
+
+        let live_addr = Address {
+            body: vec![1; 20],
+            ..Default::default()
+        };
+        let expired_addr = Address {
+            body: vec![2; 20],
+            ..Default::default()
+        };
+        let now = key_db.clock().now();
+        key_db
+            .put(&live_addr, &metadata_with_entries(now, 3000, vec![]))
+            .unwrap();
+        key_db
+            .put(&expired_addr, &metadata_with_entries(now - 20, 10, vec![]))
+            .unwrap();
+
+        // Only the live record is yielded
+        let records: Vec<_> = key_db.live_records().collect();
+        assert_eq!(records.len(), 1);
+        assert_eq!(&records[0].0[..], live_addr.as_body());
+
+        // Checkpoint contains the same records
+        key_db.checkpoint("./test_db/backup_checkpoint").unwrap();
+        key_db.close();
+        let restored = KeyDB::try_new("./test_db/backup_checkpoint").unwrap();
+        assert!(restored.get(&live_addr).unwrap().is_some());
+    }
+}
@@ -0,0 +1,43 @@
+use std::fmt;
+
+use prost::DecodeError;
+use rocksdb::Error as RocksError;
+
+#[derive(Debug)]
+pub enum DBError {
+    Rocks(RocksError),
+    Corrupted(DecodeError),
+    MissingColumnFamily(&'static str),
+    Canceled,
+    UnsupportedSchema(u32),
+    SearchDisabled,
+}
+
+impl fmt::Display for DBError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DBError::Rocks(err) => err.fmt(f),
+            DBError::Corrupted(err) => write!(f, "corrupted record: {}", err),
+            DBError::MissingColumnFamily(name) => write!(f, "missing column family {}", name),
+            DBError::Canceled => write!(f, "operation canceled"),
+            DBError::UnsupportedSchema(version) => write!(
+                f,
+                "database schema version {} is newer than this binary supports",
+                version
+            ),
+            DBError::SearchDisabled => write!(f, "search index is disabled"),
+        }
+    }
+}
+
+impl From<RocksError> for DBError {
+    fn from(err: RocksError) -> Self {
+        DBError::Rocks(err)
+    }
+}
+
+impl From<DecodeError> for DBError {
+    fn from(err: DecodeError) -> Self {
+        DBError::Corrupted(err)
+    }
+}
@@ -0,0 +1,108 @@
+use rocksdb::{Direction, IteratorMode};
+
+use crate::models::address_metadata::Payload;
+
+use super::{decode_record, errors::DBError, expiry, KeyDB};
+
+/// Summary of a live record, as returned when listing the keyspace.
+#[derive(Debug, PartialEq)]
+pub struct KeySummary {
+    pub addr_raw: Vec<u8>,
+    pub timestamp: i64,
+    pub expiry: i64,
+}
+
+impl KeySummary {
+    pub(super) fn new(addr_raw: Vec<u8>, payload: &Payload) -> Self {
+        KeySummary {
+            addr_raw,
+            timestamp: payload.timestamp,
+            expiry: expiry(payload),
+        }
+    }
+}
+
+impl KeyDB {
+    /// Iterate the keyspace in order, summarising live records.
+    pub(super) fn summaries(
+        &self,
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<KeySummary>, DBError> {
+        let now = self.clock.now();
+        let mode = match after {
+            Some(after) => IteratorMode::From(after, Direction::Forward),
+            None => IteratorMode::Start,
+        };
+
+        let mut summaries = Vec::new();
+        for (key, value) in self.db.iterator(mode) {
+            if summaries.len() == limit {
+                break;
+            }
+            if Some(&key[..]) == after {
+                continue;
+            }
+
+            // Undecodable records are left for the scrubber
+            if let Ok((_, payload)) = decode_record(&value) {
+                if expiry(&payload) >= now {
+                    summaries.push(KeySummary::new(key.to_vec(), &payload));
+                }
+            }
+        }
+        Ok(summaries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        crypto::Address,
+        db::{tests::metadata_with_entries, Database},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_list() {
+        // Open DB
+        let _ = std::fs::remove_dir_all("./test_db/list");
+        let key_db = KeyDB::try_new("./test_db/list").unwrap();
+        let now = key_db.clock().now();
+
+        // Put live records either side of an expired one
+        for (i, ttl) in [(1, 3000), (2, 10), (3, 3000), (4, 3000)].iter() {
+            let addr = Address {
+                body: vec![*i; 20],
+                ..Default::default()
+            };
+            key_db
+                .put(&addr, &metadata_with_entries(now - 20, *ttl, vec![]))
+                .unwrap();
+        }
+
+        // First page
+        let page = key_db.list(None, 2).unwrap();
+        assert_eq!(
+            page,
+            vec![
+                KeySummary {
+                    addr_raw: vec![1; 20],
+                    timestamp: now - 20,
+                    expiry: now + 2980,
+                },
+                KeySummary {
+                    addr_raw: vec![3; 20],
+                    timestamp: now - 20,
+                    expiry: now + 2980,
+                },
+            ]
+        );
+
+        // Second page
+        let page = key_db.list(Some(&page[1].addr_raw), 2).unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].addr_raw, vec![4; 20]);
+    }
+}
@@ -0,0 +1,40 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+const STRIPES: usize = 256;
+
+/// Striped per-address locks, serializing read-modify-write cycles on a single address.
+#[derive(Clone)]
+pub struct AddressLocks(Arc<Vec<Mutex<()>>>);
+
+impl Default for AddressLocks {
+    fn default() -> Self {
+        AddressLocks(Arc::new((0..STRIPES).map(|_| Mutex::new(())).collect()))
+    }
+}
+
+fn stripe(addr_raw: &[u8]) -> usize {
+    let mut hasher = DefaultHasher::new();
+    addr_raw.hash(&mut hasher);
+    hasher.finish() as usize % STRIPES
+}
+
+impl AddressLocks {
+    pub fn lock(&self, addr_raw: &[u8]) -> MutexGuard<()> {
+        self.0[stripe(addr_raw)].lock().unwrap()
+    }
+
+    /// Lock many addresses at once. Stripes are acquired in order to avoid deadlocks.
+    pub fn lock_all<'a>(&self, addrs_raw: impl Iterator<Item = &'a [u8]>) -> Vec<MutexGuard<()>> {
+        let mut stripes: Vec<usize> = addrs_raw.map(stripe).collect();
+        stripes.sort_unstable();
+        stripes.dedup();
+        stripes
+            .into_iter()
+            .map(|stripe| self.0[stripe].lock().unwrap())
+            .collect()
+    }
+}
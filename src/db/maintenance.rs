@@ -0,0 +1,91 @@
+use std::collections::BTreeMap;
+
+use serde_derive::Serialize;
+
+use super::{
+    errors::DBError,
+    rocks::{COLUMN_FAMILIES, EXPIRY_CF},
+    KeyDB,
+};
+
+/// RocksDB properties reported in the database statistics.
+const PROPERTIES: [&str; 6] = [
+    "rocksdb.estimate-num-keys",
+    "rocksdb.estimate-live-data-size",
+    "rocksdb.total-sst-files-size",
+    "rocksdb.cur-size-all-mem-tables",
+    "rocksdb.num-running-compactions",
+    "rocksdb.background-errors",
+];
+
+#[derive(Debug, Serialize)]
+pub struct DBStats {
+    /// Estimated number of stored addresses, including those awaiting the sweeper.
+    pub estimated_keys: u64,
+    /// Total size of the SST files across column families, in bytes.
+    pub size: u64,
+    pub properties: BTreeMap<String, String>,
+}
+
+impl KeyDB {
+    /// Compact the keyspace and every column family.
+    pub fn compact(&self) -> Result<(), DBError> {
+        self.db.compact_range(None::<&[u8]>, None::<&[u8]>);
+        for &name in COLUMN_FAMILIES.iter() {
+            self.db
+                .compact_range_cf(self.cf(name)?, None::<&[u8]>, None::<&[u8]>);
+        }
+        Ok(())
+    }
+
+    fn property(&self, name: &str) -> Result<Option<String>, DBError> {
+        Ok(self.db.property_value(name)?)
+    }
+
+    fn property_cf(&self, cf_name: &'static str, name: &str) -> Result<Option<String>, DBError> {
+        Ok(self.db.property_value_cf(self.cf(cf_name)?, name)?)
+    }
+
+    pub fn stats(&self) -> Result<DBStats, DBError> {
+        let parse = |value: Option<String>| value.and_then(|value| value.parse::<u64>().ok());
+
+        // Every stored address has exactly one expiry entry
+        let estimated_keys =
+            parse(self.property_cf(EXPIRY_CF, "rocksdb.estimate-num-keys")?).unwrap_or_default();
+
+        let mut size = parse(self.property("rocksdb.total-sst-files-size")?).unwrap_or_default();
+        for &name in COLUMN_FAMILIES.iter() {
+            size +=
+                parse(self.property_cf(name, "rocksdb.total-sst-files-size")?).unwrap_or_default();
+        }
+
+        let mut properties = BTreeMap::new();
+        for name in PROPERTIES.iter() {
+            if let Some(value) = self.property(name)? {
+                properties.insert(name.to_string(), value);
+            }
+        }
+
+        Ok(DBStats {
+            estimated_keys,
+            size,
+            properties,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_and_compact() {
+        let _ = std::fs::remove_dir_all("./test_db/stats");
+        let key_db = KeyDB::try_new("./test_db/stats").unwrap();
+
+        key_db.compact().unwrap();
+        let stats = key_db.stats().unwrap();
+        assert_eq!(stats.estimated_keys, 0);
+        assert!(stats.properties.contains_key("rocksdb.estimate-num-keys"));
+    }
+}
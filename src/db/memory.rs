@@ -0,0 +1,233 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+use bytes::Bytes;
+use prost::Message;
+
+use crate::{
+    clock::Clock,
+    crypto::Address,
+    models::address_metadata::{AddressMetadata, Payload},
+    net::errors::ValidationError,
+};
+
+use super::{
+    check_replacement, decode_record,
+    errors::DBError,
+    expiry,
+    listing::KeySummary,
+    pubkeys::referenced_keys,
+    search::{matches, Found, Term},
+    Database,
+};
+
+/// Encoded metadata alongside its expiry time.
+struct Entry {
+    expiry: i64,
+    raw_metadata: Bytes,
+}
+
+/// Pure in-memory storage backend, useful for tests and embedding.
+#[derive(Clone, Default)]
+pub struct MemoryDB(Arc<RwLock<HashMap<Vec<u8>, Entry>>>, Clock);
+
+impl MemoryDB {
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.1 = clock;
+        self
+    }
+
+    fn entry(metadata: &AddressMetadata, payload: &Payload) -> Entry {
+        let mut raw_metadata = Vec::with_capacity(metadata.encoded_len());
+        metadata.encode(&mut raw_metadata).unwrap();
+        Entry {
+            expiry: expiry(payload),
+            raw_metadata: raw_metadata.into(),
+        }
+    }
+}
+
+impl Database for MemoryDB {
+    fn clock(&self) -> &Clock {
+        &self.1
+    }
+
+    fn put(&self, addr: &Address, metadata: &AddressMetadata) -> Result<(), DBError> {
+        let payload = Payload::decode(&metadata.serialized_payload[..])?;
+        self.0
+            .write()
+            .unwrap()
+            .insert(addr.as_body().to_vec(), Self::entry(metadata, &payload));
+        Ok(())
+    }
+
+    fn get(&self, addr: &Address) -> Result<Option<AddressMetadata>, DBError> {
+        match self.get_raw(addr)? {
+            Some(raw_metadata) => Ok(Some(AddressMetadata::decode(raw_metadata)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn get_raw(&self, addr: &Address) -> Result<Option<Bytes>, DBError> {
+        // Expired entries are left for the sweeper
+        let now = self.1.now();
+        Ok(self
+            .0
+            .read()
+            .unwrap()
+            .get(addr.as_body())
+            .filter(|entry| entry.expiry >= now)
+            .map(|entry| entry.raw_metadata.clone()))
+    }
+
+    fn put_checked(
+        &self,
+        addr: &Address,
+        metadata: &AddressMetadata,
+        new_payload: &Payload,
+    ) -> Result<Result<(), ValidationError>, DBError> {
+        // Hold the write lock across the check and the insert
+        let now = self.1.now();
+        let mut store = self.0.write().unwrap();
+
+        let old_payload = match store.get(addr.as_body()) {
+            Some(old_entry) if old_entry.expiry >= now => {
+                Some(decode_record(&old_entry.raw_metadata)?.1)
+            }
+            _ => None,
+        };
+        if let Err(err) = check_replacement(old_payload.as_ref(), new_payload, now) {
+            return Ok(Err(err));
+        }
+
+        store.insert(addr.as_body().to_vec(), Self::entry(metadata, new_payload));
+        Ok(Ok(()))
+    }
+
+    fn remove(&self, addr: &Address) -> Result<bool, DBError> {
+        Ok(self.0.write().unwrap().remove(addr.as_body()).is_some())
+    }
+
+    fn remove_expired(&self) -> Result<usize, DBError> {
+        let now = self.1.now();
+        let mut store = self.0.write().unwrap();
+        let before = store.len();
+        store.retain(|_, entry| entry.expiry >= now);
+        Ok(before - store.len())
+    }
+
+    fn search(&self, terms: &[Term], after: Option<&[u8]>, limit: usize) -> Result<Found, DBError> {
+        let now = self.1.now();
+        let store = self.0.read().unwrap();
+        let mut found: Vec<Vec<u8>> = store
+            .iter()
+            .filter(|(addr_raw, entry)| {
+                entry.expiry >= now && after.map_or(true, |after| &addr_raw[..] > after)
+            })
+            .filter(|(_, entry)| match decode_record(&entry.raw_metadata) {
+                Ok((_, payload)) => !terms.is_empty() && matches(&payload, terms),
+                Err(_) => false,
+            })
+            .map(|(addr_raw, _)| addr_raw.clone())
+            .collect();
+        found.sort();
+        found.truncate(limit);
+        Ok(Found::page(found, limit))
+    }
+
+    fn lookup_pubkey(
+        &self,
+        pub_key: &[u8],
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<Vec<u8>>, DBError> {
+        let now = self.1.now();
+        let store = self.0.read().unwrap();
+        let mut found: Vec<Vec<u8>> = store
+            .iter()
+            .filter(|(addr_raw, entry)| {
+                entry.expiry >= now && after.map_or(true, |after| &addr_raw[..] > after)
+            })
+            .filter(|(_, entry)| match decode_record(&entry.raw_metadata) {
+                Ok((metadata, payload)) => referenced_keys(&metadata, &payload).contains(pub_key),
+                Err(_) => false,
+            })
+            .map(|(addr_raw, _)| addr_raw.clone())
+            .collect();
+        found.sort();
+        found.truncate(limit);
+        Ok(found)
+    }
+
+    fn list(&self, after: Option<&[u8]>, limit: usize) -> Result<Vec<KeySummary>, DBError> {
+        let now = self.1.now();
+        let store = self.0.read().unwrap();
+        let mut summaries: Vec<KeySummary> = store
+            .iter()
+            .filter(|(addr_raw, entry)| {
+                entry.expiry >= now && after.map_or(true, |after| &addr_raw[..] > after)
+            })
+            .filter_map(|(addr_raw, entry)| {
+                let (_, payload) = decode_record(&entry.raw_metadata).ok()?;
+                Some(KeySummary::new(addr_raw.clone(), &payload))
+            })
+            .collect();
+        summaries.sort_by(|a, b| a.addr_raw.cmp(&b.addr_raw));
+        summaries.truncate(limit);
+        Ok(summaries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use crate::db::tests::metadata_with_entries;
+
+    use super::*;
+
+    #[test]
+    fn test_put_get() {
+        let db = MemoryDB::default();
+        let addr = Address {
+            body: vec![1; 20],
+            ..Default::default()
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let metadata = metadata_with_entries(now, 3000, vec![]);
+
+        assert!(db.get(&addr).unwrap().is_none());
+        db.put(&addr, &metadata).unwrap();
+        assert_eq!(db.get(&addr).unwrap(), Some(metadata));
+    }
+
+    #[test]
+    fn test_remove_expired() {
+        let db = MemoryDB::default();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let live_addr = Address {
+            body: vec![1; 20],
+            ..Default::default()
+        };
+        let expired_addr = Address {
+            body: vec![2; 20],
+            ..Default::default()
+        };
+        db.put(&live_addr, &metadata_with_entries(now, 3000, vec![]))
+            .unwrap();
+        db.put(&expired_addr, &metadata_with_entries(now - 20, 10, vec![]))
+            .unwrap();
+
+        assert_eq!(db.remove_expired().unwrap(), 1);
+        assert!(db.get(&live_addr).unwrap().is_some());
+        assert!(db.get(&expired_addr).unwrap().is_none());
+    }
+}
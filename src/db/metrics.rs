@@ -0,0 +1,190 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use serde_derive::Serialize;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Operation {
+    Get,
+    GetRaw,
+    PutChecked,
+    RemoveExpired,
+    Search,
+    LookupPubkey,
+    List,
+    Remove,
+    Maintenance,
+}
+
+const OPERATIONS: [Operation; 9] = [
+    Operation::Get,
+    Operation::GetRaw,
+    Operation::PutChecked,
+    Operation::RemoveExpired,
+    Operation::Search,
+    Operation::LookupPubkey,
+    Operation::List,
+    Operation::Remove,
+    Operation::Maintenance,
+];
+
+impl Operation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::Get => "get",
+            Operation::GetRaw => "get_raw",
+            Operation::PutChecked => "put_checked",
+            Operation::RemoveExpired => "remove_expired",
+            Operation::Search => "search",
+            Operation::LookupPubkey => "lookup_pubkey",
+            Operation::List => "list",
+            Operation::Remove => "remove",
+            Operation::Maintenance => "maintenance",
+        }
+    }
+}
+
+#[derive(Default)]
+struct OpMetrics {
+    calls: AtomicU64,
+    queued: AtomicUsize,
+    in_flight: AtomicUsize,
+    queue_micros: AtomicU64,
+    latency_micros: AtomicU64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OpStats {
+    pub operation: &'static str,
+    pub calls: u64,
+    pub queued: usize,
+    pub in_flight: usize,
+    pub mean_queue_micros: u64,
+    pub mean_latency_micros: u64,
+}
+
+/// Per-operation queue and latency metrics for database access.
+#[derive(Clone, Default)]
+pub struct DBMetrics(Arc<[OpMetrics; 9]>);
+
+impl DBMetrics {
+    fn op(&self, op: Operation) -> &OpMetrics {
+        &self.0[op as usize]
+    }
+
+    /// Count an operation as queued until it is started, and in flight from then until it is
+    /// dropped, so that cancelled operations leave the gauges balanced.
+    pub fn track(&self, op: Operation) -> Tracked<'_> {
+        let metrics = self.op(op);
+        metrics.queued.fetch_add(1, Ordering::Relaxed);
+        Tracked {
+            metrics,
+            enqueued: Instant::now(),
+            started: None,
+        }
+    }
+
+    pub fn snapshot(&self) -> Vec<OpStats> {
+        OPERATIONS
+            .iter()
+            .map(|&op| {
+                let metrics = self.op(op);
+                let calls = metrics.calls.load(Ordering::Relaxed);
+                let mean = |total: &AtomicU64| total.load(Ordering::Relaxed) / calls.max(1);
+                OpStats {
+                    operation: op.name(),
+                    calls,
+                    queued: metrics.queued.load(Ordering::Relaxed),
+                    in_flight: metrics.in_flight.load(Ordering::Relaxed),
+                    mean_queue_micros: mean(&metrics.queue_micros),
+                    mean_latency_micros: mean(&metrics.latency_micros),
+                }
+            })
+            .collect()
+    }
+}
+
+/// A queued or in flight operation, see [`DBMetrics::track`].
+pub struct Tracked<'a> {
+    metrics: &'a OpMetrics,
+    enqueued: Instant,
+    started: Option<Instant>,
+}
+
+impl<'a> Tracked<'a> {
+    /// Move the operation from the queue to in flight.
+    pub fn start(&mut self) {
+        if self.started.is_some() {
+            return;
+        }
+        self.metrics.queued.fetch_sub(1, Ordering::Relaxed);
+        self.metrics.in_flight.fetch_add(1, Ordering::Relaxed);
+        self.metrics.queue_micros.fetch_add(
+            self.enqueued.elapsed().as_micros() as u64,
+            Ordering::Relaxed,
+        );
+        self.started = Some(Instant::now());
+    }
+
+    /// Record the completed operation, returning its latency.
+    pub fn finish(self) -> Duration {
+        let latency = self
+            .started
+            .map(|started| started.elapsed())
+            .unwrap_or_default();
+        self.metrics.calls.fetch_add(1, Ordering::Relaxed);
+        self.metrics
+            .latency_micros
+            .fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+        latency
+    }
+}
+
+impl<'a> Drop for Tracked<'a> {
+    fn drop(&mut self) {
+        let gauge = match self.started {
+            Some(_) => &self.metrics.in_flight,
+            None => &self.metrics.queued,
+        };
+        gauge.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let metrics = DBMetrics::default();
+        let queued = metrics.track(Operation::Get);
+        let mut finished = metrics.track(Operation::Get);
+        finished.start();
+        finished.finish();
+
+        let stats = metrics.snapshot();
+        let get = &stats[Operation::Get as usize];
+        assert_eq!(get.operation, "get");
+        assert_eq!(get.calls, 1);
+        assert_eq!(get.queued, 1);
+        assert_eq!(get.in_flight, 0);
+        assert_eq!(stats[Operation::PutChecked as usize].calls, 0);
+
+        // Dropped operations leave the gauges
+        drop(queued);
+        let mut cancelled = metrics.track(Operation::Get);
+        cancelled.start();
+        assert_eq!(metrics.snapshot()[Operation::Get as usize].in_flight, 1);
+        drop(cancelled);
+
+        let get = &metrics.snapshot()[Operation::Get as usize];
+        assert_eq!(get.calls, 1);
+        assert_eq!(get.queued, 0);
+        assert_eq!(get.in_flight, 0);
+    }
+}
@@ -0,0 +1,184 @@
+use std::convert::TryInto;
+
+use rocksdb::{IteratorMode, WriteBatch};
+
+use super::{
+    decode_record,
+    errors::DBError,
+    expiry,
+    pubkeys::referenced_keys,
+    rocks::{META_CF, PUBKEY_CF},
+    KeyDB,
+};
+
+/// Version of the layout written by this binary.
+///
+/// - 0: Unversioned, address bodies mapped to encoded `AddressMetadata`
+/// - 1: Adds the expiry column families
+/// - 2: Adds the public key index
+/// - 3: Normalises the encodings of indexed public keys
+pub const SCHEMA_VERSION: u32 = 3;
+
+const VERSION_KEY: &[u8] = b"schema_version";
+const PROGRESS_INTERVAL: usize = 10_000;
+
+type Migration = fn(&KeyDB) -> Result<(), DBError>;
+
+/// Migrations, indexed by the version they upgrade from.
+const MIGRATIONS: [Migration; SCHEMA_VERSION as usize] =
+    [backfill_expiry, backfill_pubkeys, normalize_pubkeys];
+
+impl KeyDB {
+    pub fn schema_version(&self) -> Result<Option<u32>, DBError> {
+        let raw_version = self.db.get_cf(self.cf(META_CF)?, VERSION_KEY)?;
+        Ok(raw_version
+            .and_then(|raw_version| raw_version[..].try_into().ok())
+            .map(u32::from_be_bytes))
+    }
+
+    fn set_schema_version(&self, version: u32) -> Result<(), DBError> {
+        self.db
+            .put_cf(self.cf(META_CF)?, VERSION_KEY, version.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Upgrade the layout in place, refusing databases newer than this binary supports.
+    pub(super) fn migrate(&self) -> Result<(), DBError> {
+        let mut version = match self.schema_version()? {
+            Some(some) => some,
+            None => {
+                // An empty keyspace is a new database rather than the unversioned layout
+                if self.db.iterator(IteratorMode::Start).next().is_none() {
+                    return self.set_schema_version(SCHEMA_VERSION);
+                }
+                0
+            }
+        };
+
+        if version > SCHEMA_VERSION {
+            return Err(DBError::UnsupportedSchema(version));
+        }
+
+        while version < SCHEMA_VERSION {
+            info!(
+                "migrating database from schema version {} to {}",
+                version,
+                version + 1
+            );
+            MIGRATIONS[version as usize](self)?;
+            version += 1;
+            self.set_schema_version(version)?;
+        }
+        Ok(())
+    }
+}
+
+fn log_progress(migrated: usize) {
+    if migrated % PROGRESS_INTERVAL == 0 {
+        info!("migrated {} records", migrated);
+    }
+}
+
+// Index the expiry of every record
+fn backfill_expiry(key_db: &KeyDB) -> Result<(), DBError> {
+    let mut migrated = 0;
+    for (key, value) in key_db.db.iterator(IteratorMode::Start) {
+        match decode_record(&value) {
+            Ok((_, payload)) => {
+                let mut batch = WriteBatch::default();
+                key_db.batch_expiry(&mut batch, &key, expiry(&payload))?;
+                key_db.db.write(batch)?;
+            }
+            Err(_) => key_db.quarantine(&key, &value)?,
+        }
+        migrated += 1;
+        log_progress(migrated);
+    }
+    info!("migrated {} records", migrated);
+    Ok(())
+}
+
+// Index the public keys referenced by every record
+fn backfill_pubkeys(key_db: &KeyDB) -> Result<(), DBError> {
+    let mut migrated = 0;
+    for (key, value) in key_db.db.iterator(IteratorMode::Start) {
+        // Undecodable records were quarantined by the previous migration
+        if let Ok((metadata, payload)) = decode_record(&value) {
+            let mut batch = WriteBatch::default();
+            let keys = referenced_keys(&metadata, &payload);
+            key_db.batch_pubkeys(&mut batch, &key, &Default::default(), &keys)?;
+            key_db.db.write(batch)?;
+        }
+        migrated += 1;
+        log_progress(migrated);
+    }
+    info!("migrated {} records", migrated);
+    Ok(())
+}
+
+// Rebuild the public key index, which held keys in the encoding they were stored with
+fn normalize_pubkeys(key_db: &KeyDB) -> Result<(), DBError> {
+    let pubkey_cf = key_db.cf(PUBKEY_CF)?;
+    for (key, _) in key_db.db.iterator_cf(pubkey_cf, IteratorMode::Start)? {
+        key_db.db.delete_cf(pubkey_cf, key)?;
+    }
+    backfill_pubkeys(key_db)
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::Message;
+
+    use crate::{
+        crypto::Address,
+        db::{tests::metadata_with_entries, Database},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_new_database_is_current() {
+        let _ = std::fs::remove_dir_all("./test_db/schema_new");
+        let key_db = KeyDB::try_new("./test_db/schema_new").unwrap();
+        assert_eq!(key_db.schema_version().unwrap(), Some(SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn test_migrate_unversioned() {
+        let _ = std::fs::remove_dir_all("./test_db/schema_unversioned");
+        let key_db = KeyDB::try_new("./test_db/schema_unversioned").unwrap();
+
+        // Write an expired record in the unversioned layout
+        let metadata = metadata_with_entries(key_db.clock().now() - 20, 10, vec![]);
+        let mut raw_metadata = Vec::with_capacity(metadata.encoded_len());
+        metadata.encode(&mut raw_metadata).unwrap();
+        let addr = Address {
+            body: vec![1; 20],
+            ..Default::default()
+        };
+        key_db.db.put(addr.as_body(), raw_metadata).unwrap();
+        key_db
+            .db
+            .delete_cf(key_db.cf(META_CF).unwrap(), VERSION_KEY)
+            .unwrap();
+        key_db.close();
+
+        // Reopening migrates and indexes the record
+        let key_db = KeyDB::try_new("./test_db/schema_unversioned").unwrap();
+        assert_eq!(key_db.schema_version().unwrap(), Some(SCHEMA_VERSION));
+        assert_eq!(key_db.remove_expired().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_refuse_newer() {
+        let _ = std::fs::remove_dir_all("./test_db/schema_newer");
+        let key_db = KeyDB::try_new("./test_db/schema_newer").unwrap();
+        key_db.set_schema_version(SCHEMA_VERSION + 1).unwrap();
+        key_db.close();
+
+        match KeyDB::try_new("./test_db/schema_newer") {
+            Err(DBError::UnsupportedSchema(version)) => assert_eq!(version, SCHEMA_VERSION + 1),
+            _ => panic!("expected unsupported schema"),
+        }
+    }
+}
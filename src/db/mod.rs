@@ -0,0 +1,192 @@
+pub mod backup;
+pub mod errors;
+pub mod listing;
+pub mod locks;
+pub mod maintenance;
+pub mod memory;
+pub mod metrics;
+pub mod migrations;
+pub mod pool;
+pub mod pubkeys;
+pub mod rocks;
+pub mod scrub;
+pub mod search;
+pub mod sweeper;
+
+use bytes::Bytes;
+use prost::Message;
+
+use keyserver_core::expiry::{check_replacement, expiry};
+
+use crate::{
+    clock::Clock,
+    crypto::Address,
+    models::address_metadata::{AddressMetadata, Payload},
+    net::errors::ValidationError,
+};
+
+use errors::DBError;
+use listing::KeySummary;
+use search::{Found, Term};
+
+pub use memory::MemoryDB;
+pub use pool::AsyncDB;
+pub use rocks::KeyDB;
+
+/// Decode a stored record into its metadata and inner payload.
+pub fn decode_record(raw: &[u8]) -> Result<(AddressMetadata, Payload), DBError> {
+    let metadata = AddressMetadata::decode(raw)?;
+    let payload = Payload::decode(&metadata.serialized_payload[..])?;
+    Ok((metadata, payload))
+}
+
+/// Storage backend for address metadata.
+pub trait Database: Clone + Send + Sync + 'static {
+    /// Clock against which expiry is judged.
+    fn clock(&self) -> &Clock;
+
+    /// Get unexpired metadata stored for an address.
+    fn get(&self, addr: &Address) -> Result<Option<AddressMetadata>, DBError>;
+
+    /// Get the encoded metadata stored for an address, using the stored expiry rather than
+    /// decoding it.
+    fn get_raw(&self, addr: &Address) -> Result<Option<Bytes>, DBError>;
+
+    /// Put metadata unconditionally, serialized against other writes to the address.
+    fn put(&self, addr: &Address, metadata: &AddressMetadata) -> Result<(), DBError>;
+
+    /// Remove the entry for an address regardless of expiry, returning whether one was stored.
+    fn remove(&self, addr: &Address) -> Result<bool, DBError>;
+
+    /// Remove every expired entry, returning the number removed.
+    fn remove_expired(&self) -> Result<usize, DBError>;
+
+    /// Atomically check the new payload against the stored payload and, if it may replace it,
+    /// put the metadata.
+    fn put_checked(
+        &self,
+        addr: &Address,
+        metadata: &AddressMetadata,
+        new_payload: &Payload,
+    ) -> Result<Result<(), ValidationError>, DBError>;
+
+    /// Find up to `limit` live addresses whose payload has every term, in address order and
+    /// starting after the `after` address. Backends may return a short page, with a cursor to
+    /// resume from, rather than scan without bound.
+    fn search(&self, terms: &[Term], after: Option<&[u8]>, limit: usize) -> Result<Found, DBError>;
+
+    /// Find up to `limit` live addresses whose metadata references a normalised public key,
+    /// either as its signing key or within an `xpub` or `pubkey` entry, in address order and
+    /// starting after the `after` address.
+    fn lookup_pubkey(
+        &self,
+        pub_key: &[u8],
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<Vec<u8>>, DBError>;
+
+    /// Summarise up to `limit` live records in address order, starting after the `after`
+    /// address.
+    fn list(&self, after: Option<&[u8]>, limit: usize) -> Result<Vec<KeySummary>, DBError>;
+
+    fn check_timestamp(
+        &self,
+        addr: &Address,
+        new_payload: &Payload,
+    ) -> Result<Result<(), ValidationError>, DBError> {
+        let old_payload = match self.get(addr)? {
+            Some(old_metadata) => Some(Payload::decode(&old_metadata.serialized_payload[..])?),
+            None => None,
+        };
+        Ok(check_replacement(
+            old_payload.as_ref(),
+            new_payload,
+            self.clock().now(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        thread,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use secp256k1::rand::{self, Rng};
+
+    use crate::models::address_metadata::Entry;
+
+    use super::*;
+
+    /// Unsigned metadata wrapping a payload with the given entries.
+    pub fn metadata_with_entries(timestamp: i64, ttl: i64, entries: Vec<Entry>) -> AddressMetadata {
+        let payload = Payload {
+            timestamp,
+            ttl,
+            entries,
+        };
+        let mut serialized_payload = Vec::with_capacity(payload.encoded_len());
+        payload.encode(&mut serialized_payload).unwrap();
+        AddressMetadata {
+            pub_key: vec![],
+            serialized_payload,
+            signature: vec![],
+            scheme: 1,
+        }
+    }
+
+    const WRITERS: usize = 8;
+    const WRITES_PER_WRITER: usize = 64;
+
+    // Hammer a single address with concurrent writers and check the newest payload wins
+    fn stress_put_checked<D: Database>(db: D) {
+        let addr = Address {
+            body: vec![9; 20],
+            ..Default::default()
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let handles: Vec<_> = (0..WRITERS)
+            .map(|_| {
+                let db = db.clone();
+                let addr = addr.clone();
+                thread::spawn(move || {
+                    let mut rng = rand::thread_rng();
+                    let mut newest = 0;
+                    for _ in 0..WRITES_PER_WRITER {
+                        let metadata =
+                            metadata_with_entries(now + rng.gen_range(0, 1000), 3000, vec![]);
+                        let payload = Payload::decode(&metadata.serialized_payload[..]).unwrap();
+                        let _ = db.put_checked(&addr, &metadata, &payload).unwrap();
+                        newest = newest.max(payload.timestamp);
+                    }
+                    newest
+                })
+            })
+            .collect();
+        let newest = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .max()
+            .unwrap();
+
+        let stored = db.get(&addr).unwrap().unwrap();
+        let stored_payload = Payload::decode(&stored.serialized_payload[..]).unwrap();
+        assert_eq!(stored_payload.timestamp, newest);
+    }
+
+    #[test]
+    fn test_stress_put_checked_memory() {
+        stress_put_checked(MemoryDB::default());
+    }
+
+    #[test]
+    fn test_stress_put_checked_rocks() {
+        let _ = std::fs::remove_dir_all("./test_db/stress");
+        stress_put_checked(KeyDB::try_new("./test_db/stress").unwrap());
+    }
+}
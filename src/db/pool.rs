@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use actix_web::{error::BlockingError, web};
+use bytes::Bytes;
+use tokio::sync::Semaphore;
+
+use crate::{
+    crypto::Address,
+    metrics::DB_LATENCY,
+    models::address_metadata::{AddressMetadata, Payload},
+    net::errors::ValidationError,
+};
+
+use super::{
+    errors::DBError,
+    listing::KeySummary,
+    metrics::{DBMetrics, Operation},
+    search::{Found, Term},
+    Database,
+};
+
+/// Runs database operations on the blocking thread pool, keeping them off the async workers and
+/// bounding how many may be in flight at once.
+#[derive(Clone)]
+pub struct AsyncDB<D> {
+    db: D,
+    permits: Arc<Semaphore>,
+    metrics: DBMetrics,
+}
+
+impl<D: Database> AsyncDB<D> {
+    pub fn new(db: D, max_concurrency: usize) -> Self {
+        AsyncDB {
+            db,
+            permits: Arc::new(Semaphore::new(max_concurrency)),
+            metrics: DBMetrics::default(),
+        }
+    }
+
+    pub fn inner(&self) -> &D {
+        &self.db
+    }
+
+    pub fn metrics(&self) -> &DBMetrics {
+        &self.metrics
+    }
+
+    async fn run<F, T>(&self, op: Operation, f: F) -> Result<T, DBError>
+    where
+        F: FnOnce(D) -> Result<T, DBError> + Send + 'static,
+        T: Send + 'static,
+    {
+        // Wait for a permit
+        let mut tracked = self.metrics.track(op);
+        let _permit = self.permits.acquire().await;
+        tracked.start();
+
+        // Run on blocking thread pool
+        let db = self.db.clone();
+        let result = web::block(move || f(db)).await;
+        let latency = tracked.finish();
+        DB_LATENCY
+            .with_label_values(&[op.name()])
+            .observe(latency.as_secs_f64());
+
+        result.map_err(|err| match err {
+            BlockingError::Error(err) => err,
+            BlockingError::Canceled => DBError::Canceled,
+        })
+    }
+
+    pub async fn get(&self, addr: Address) -> Result<Option<AddressMetadata>, DBError> {
+        self.run(Operation::Get, move |db| db.get(&addr)).await
+    }
+
+    pub async fn get_raw(&self, addr: Address) -> Result<Option<Bytes>, DBError> {
+        self.run(Operation::GetRaw, move |db| db.get_raw(&addr))
+            .await
+    }
+
+    pub async fn put_checked(
+        &self,
+        addr: Address,
+        metadata: AddressMetadata,
+        new_payload: Payload,
+    ) -> Result<Result<(), ValidationError>, DBError> {
+        self.run(Operation::PutChecked, move |db| {
+            db.put_checked(&addr, &metadata, &new_payload)
+        })
+        .await
+    }
+
+    pub async fn remove(&self, addr: Address) -> Result<bool, DBError> {
+        self.run(Operation::Remove, move |db| db.remove(&addr))
+            .await
+    }
+
+    /// Run a backend specific maintenance task on the blocking thread pool.
+    pub async fn maintenance<F, T>(&self, f: F) -> Result<T, DBError>
+    where
+        F: FnOnce(D) -> Result<T, DBError> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.run(Operation::Maintenance, f).await
+    }
+
+    pub async fn remove_expired(&self) -> Result<usize, DBError> {
+        self.run(Operation::RemoveExpired, |db| db.remove_expired())
+            .await
+    }
+
+    pub async fn search(
+        &self,
+        terms: Vec<Term>,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Found, DBError> {
+        self.run(Operation::Search, move |db| {
+            db.search(&terms, after.as_deref(), limit)
+        })
+        .await
+    }
+
+    pub async fn lookup_pubkey(
+        &self,
+        pub_key: Vec<u8>,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Vec<Vec<u8>>, DBError> {
+        self.run(Operation::LookupPubkey, move |db| {
+            db.lookup_pubkey(&pub_key, after.as_deref(), limit)
+        })
+        .await
+    }
+
+    pub async fn list(
+        &self,
+        after: Option<Vec<u8>>,
+        limit: usize,
+    ) -> Result<Vec<KeySummary>, DBError> {
+        self.run(Operation::List, move |db| db.list(after.as_deref(), limit))
+            .await
+    }
+}
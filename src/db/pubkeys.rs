@@ -0,0 +1,254 @@
+use std::collections::BTreeSet;
+
+use bitcoin::util::base58;
+use rocksdb::{Direction, IteratorMode, WriteBatch};
+use secp256k1::PublicKey;
+
+use crate::{
+    crypto::Address,
+    models::address_metadata::{AddressMetadata, Payload},
+};
+
+use super::{decode_record, errors::DBError, rocks::PUBKEY_CF, Database, KeyDB};
+
+/// Length of a serialized BIP32 extended key.
+const XPUB_LEN: usize = 78;
+
+/// Normalise a public key to its compressed encoding.
+pub fn normalize_pubkey(raw: &[u8]) -> Option<Vec<u8>> {
+    PublicKey::from_slice(raw)
+        .ok()
+        .map(|pub_key| pub_key.serialize().to_vec())
+}
+
+/// Normalise an extended public key, either serialized or base58check encoded, to its
+/// serialization.
+pub fn normalize_xpub(raw: &[u8]) -> Option<Vec<u8>> {
+    if raw.len() == XPUB_LEN {
+        return Some(raw.to_vec());
+    }
+    let encoded = std::str::from_utf8(raw).ok()?;
+    base58::from_check(encoded.trim())
+        .ok()
+        .filter(|decoded| decoded.len() == XPUB_LEN)
+}
+
+/// Normalise a key being looked up, given as a hex encoded public key or extended public key,
+/// or a base58check encoded extended public key.
+pub fn normalize_query(query: &str) -> Option<Vec<u8>> {
+    match hex::decode(query) {
+        Ok(raw) => normalize_pubkey(&raw).or_else(|| normalize_xpub(&raw)),
+        Err(_) => normalize_xpub(query.as_bytes()),
+    }
+}
+
+/// Collect the normalised public keys referenced by a record, being its signing key and any key
+/// entries. Keys which fail to decode are not indexed.
+pub fn referenced_keys(metadata: &AddressMetadata, payload: &Payload) -> BTreeSet<Vec<u8>> {
+    let mut keys = BTreeSet::new();
+    keys.extend(normalize_pubkey(&metadata.pub_key));
+    for entry in &payload.entries {
+        let key = match entry.kind.as_str() {
+            "pubkey" => normalize_pubkey(&entry.entry_data),
+            "xpub" => normalize_xpub(&entry.entry_data),
+            _ => None,
+        };
+        keys.extend(key);
+    }
+    keys
+}
+
+// Keys are length prefixed so that no key is a prefix of another
+fn pubkey_prefix(pub_key: &[u8]) -> Vec<u8> {
+    [&(pub_key.len() as u32).to_be_bytes()[..], pub_key].concat()
+}
+
+// Public key index keys are the prefixed key followed by the address
+fn pubkey_index_key(pub_key: &[u8], addr_raw: &[u8]) -> Vec<u8> {
+    [&pubkey_prefix(pub_key)[..], addr_raw].concat()
+}
+
+impl KeyDB {
+    // Add the index changes for a record's keys being replaced to a batch
+    pub(super) fn batch_pubkeys(
+        &self,
+        batch: &mut WriteBatch,
+        addr_raw: &[u8],
+        old_keys: &BTreeSet<Vec<u8>>,
+        new_keys: &BTreeSet<Vec<u8>>,
+    ) -> Result<(), DBError> {
+        let pubkey_cf = self.cf(PUBKEY_CF)?;
+        for pub_key in old_keys.difference(new_keys) {
+            batch.delete_cf(pubkey_cf, pubkey_index_key(pub_key, addr_raw))?;
+        }
+        for pub_key in new_keys.difference(old_keys) {
+            batch.put_cf(pubkey_cf, pubkey_index_key(pub_key, addr_raw), [])?;
+        }
+        Ok(())
+    }
+
+    /// Scan the index for a normalised key, checking each candidate against the live record.
+    pub(super) fn find_pubkey(
+        &self,
+        pub_key: &[u8],
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<Vec<u8>>, DBError> {
+        let prefix = pubkey_prefix(pub_key);
+        let start = match after {
+            Some(after) => pubkey_index_key(pub_key, after),
+            None => prefix.clone(),
+        };
+        let mut found = Vec::new();
+        let pubkey_cf = self.cf(PUBKEY_CF)?;
+        let mode = IteratorMode::From(&start, Direction::Forward);
+        for (key, _) in self.db.iterator_cf(pubkey_cf, mode)? {
+            if !key.starts_with(&prefix) || found.len() == limit {
+                break;
+            }
+            let addr_raw = &key[prefix.len()..];
+            if after.map_or(false, |after| addr_raw <= after) {
+                continue;
+            }
+
+            // Expired records awaiting the sweeper and keys left behind by quarantined records
+            // are skipped
+            let addr = Address {
+                body: addr_raw.to_vec(),
+                ..Default::default()
+            };
+            let raw_metadata = match self.get_raw(&addr) {
+                Ok(Some(some)) => some,
+                Ok(None) | Err(DBError::Corrupted(_)) => continue,
+                Err(err) => return Err(err),
+            };
+            if let Ok((metadata, payload)) = decode_record(&raw_metadata) {
+                if referenced_keys(&metadata, &payload).contains(pub_key) {
+                    found.push(addr.body);
+                }
+            }
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use secp256k1::{rand, Secp256k1};
+
+    use crate::{db::tests::metadata_with_entries, models::address_metadata::Entry};
+
+    use super::*;
+
+    fn metadata_with_keys(timestamp: i64, pub_key: &[u8], xpub: &[u8]) -> AddressMetadata {
+        let xpub_entry = Entry {
+            kind: "xpub".to_string(),
+            headers: vec![],
+            entry_data: xpub.to_vec(),
+        };
+        AddressMetadata {
+            pub_key: pub_key.to_vec(),
+            ..metadata_with_entries(timestamp, 3000, vec![xpub_entry])
+        }
+    }
+
+    fn generate_pubkey() -> PublicKey {
+        let secp = Secp256k1::new();
+        secp.generate_keypair(&mut rand::thread_rng()).1
+    }
+
+    #[test]
+    fn test_normalize() {
+        let pub_key = generate_pubkey();
+        let compressed = pub_key.serialize().to_vec();
+        assert_eq!(normalize_pubkey(&compressed), Some(compressed.clone()));
+        assert_eq!(
+            normalize_pubkey(&pub_key.serialize_uncompressed()),
+            Some(compressed.clone())
+        );
+        assert_eq!(normalize_pubkey(&[2; 33]), None);
+
+        let xpub = [9; XPUB_LEN];
+        let xpub_base58 = base58::check_encode_slice(&xpub);
+        assert_eq!(normalize_xpub(&xpub), Some(xpub.to_vec()));
+        assert_eq!(normalize_xpub(xpub_base58.as_bytes()), Some(xpub.to_vec()));
+        assert_eq!(normalize_xpub(b"not an xpub"), None);
+
+        assert_eq!(normalize_query(&hex::encode(&compressed)), Some(compressed));
+        assert_eq!(
+            normalize_query(&hex::encode(&xpub[..])),
+            Some(xpub.to_vec())
+        );
+        assert_eq!(normalize_query(&xpub_base58), Some(xpub.to_vec()));
+        assert_eq!(normalize_query("xyz"), None);
+    }
+
+    #[test]
+    fn test_lookup_pubkey() {
+        // Open DB
+        let _ = std::fs::remove_dir_all("./test_db/lookup_pubkey");
+        let key_db = KeyDB::try_new("./test_db/lookup_pubkey").unwrap();
+        let now = key_db.clock().now();
+
+        // Two addresses sharing an xpub, one encoding it as base58
+        let (key_a, key_b) = (generate_pubkey(), generate_pubkey());
+        let xpub = [9; XPUB_LEN];
+        let xpub_base58 = base58::check_encode_slice(&xpub);
+        let addr_a = Address {
+            body: vec![1; 20],
+            ..Default::default()
+        };
+        let addr_b = Address {
+            body: vec![2; 20],
+            ..Default::default()
+        };
+        key_db
+            .put(&addr_a, &metadata_with_keys(now, &key_a.serialize(), &xpub))
+            .unwrap();
+        key_db
+            .put(
+                &addr_b,
+                &metadata_with_keys(now, &key_b.serialize_uncompressed(), xpub_base58.as_bytes()),
+            )
+            .unwrap();
+
+        assert_eq!(
+            key_db.lookup_pubkey(&xpub, None, 10).unwrap(),
+            vec![addr_a.body.clone(), addr_b.body.clone()]
+        );
+        assert_eq!(
+            key_db.lookup_pubkey(&key_b.serialize(), None, 10).unwrap(),
+            vec![addr_b.body.clone()]
+        );
+
+        // Pages continue after the cursor
+        assert_eq!(
+            key_db.lookup_pubkey(&xpub, None, 1).unwrap(),
+            vec![addr_a.body.clone()]
+        );
+        assert_eq!(
+            key_db.lookup_pubkey(&xpub, Some(&addr_a.body), 1).unwrap(),
+            vec![addr_b.body.clone()]
+        );
+
+        // Replacing the metadata drops its old keys
+        key_db
+            .put(
+                &addr_a,
+                &metadata_with_keys(now + 1, &key_a.serialize(), &[8; XPUB_LEN]),
+            )
+            .unwrap();
+        assert_eq!(
+            key_db.lookup_pubkey(&xpub, None, 10).unwrap(),
+            vec![addr_b.body.clone()]
+        );
+        assert!(key_db
+            .db
+            .get_cf(
+                key_db.cf(PUBKEY_CF).unwrap(),
+                pubkey_index_key(&xpub, &addr_a.body)
+            )
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,514 @@
+use std::{convert::TryInto, sync::Arc};
+
+use bytes::Bytes;
+use prost::Message;
+use rocksdb::{ColumnFamily, IteratorMode, Options, WriteBatch, DB};
+
+use crate::{
+    clock::Clock,
+    crypto::Address,
+    models::address_metadata::{AddressMetadata, Payload},
+    net::errors::ValidationError,
+};
+
+use super::{
+    check_replacement, decode_record,
+    errors::DBError,
+    expiry,
+    listing::KeySummary,
+    locks::AddressLocks,
+    pubkeys::referenced_keys,
+    search::{Found, Term},
+    Database,
+};
+
+const QUARANTINE_CF: &str = "quarantine";
+pub(super) const EXPIRY_CF: &str = "expiry";
+const EXPIRY_INDEX_CF: &str = "expiry_index";
+pub(super) const META_CF: &str = "meta";
+pub(super) const SEARCH_CF: &str = "search";
+pub(super) const PUBKEY_CF: &str = "pubkey";
+
+pub(super) const COLUMN_FAMILIES: [&str; 6] = [
+    QUARANTINE_CF,
+    EXPIRY_CF,
+    EXPIRY_INDEX_CF,
+    META_CF,
+    SEARCH_CF,
+    PUBKEY_CF,
+];
+
+const SWEEP_BATCH: usize = 1024;
+
+// Expiry index keys are the big-endian expiry time followed by the address, so that
+// iterating the index yields entries in order of expiry.
+fn index_key(expiry: i64, addr_raw: &[u8]) -> Vec<u8> {
+    [&(expiry.max(0) as u64).to_be_bytes()[..], addr_raw].concat()
+}
+
+// The address part of an expiry index key, empty if the key is truncated
+fn index_addr(key: &[u8]) -> &[u8] {
+    key.get(8..).unwrap_or_default()
+}
+
+fn decode_expiry(raw: &[u8]) -> Option<i64> {
+    raw.get(..8)
+        .and_then(|raw| raw.try_into().ok())
+        .map(|raw| u64::from_be_bytes(raw) as i64)
+}
+
+#[derive(Clone)]
+pub struct KeyDB {
+    pub(super) db: Arc<DB>,
+    locks: AddressLocks,
+    pub(super) search_index: bool,
+    pub(super) clock: Clock,
+}
+
+impl KeyDB {
+    pub fn try_new(path: &str) -> Result<Self, DBError> {
+        let mut opts = Options::default();
+        opts.create_if_missing(true);
+        opts.create_missing_column_families(true);
+
+        let db = DB::open_cf(&opts, &path, &COLUMN_FAMILIES)?;
+        let key_db = KeyDB {
+            db: Arc::new(db),
+            locks: AddressLocks::default(),
+            search_index: false,
+            clock: Clock::default(),
+        };
+
+        // Bring the layout up to date
+        key_db.migrate()?;
+        Ok(key_db)
+    }
+
+    /// Judge expiry against the given clock rather than the system clock.
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn close(self) {
+        drop(self)
+    }
+
+    pub(super) fn cf(&self, name: &'static str) -> Result<&ColumnFamily, DBError> {
+        self.db
+            .cf_handle(name)
+            .ok_or(DBError::MissingColumnFamily(name))
+    }
+
+    pub(super) fn quarantine_cf(&self) -> Result<&ColumnFamily, DBError> {
+        self.cf(QUARANTINE_CF)
+    }
+
+    /// Get the expiry time recorded for an address.
+    pub(super) fn stored_expiry(&self, addr_raw: &[u8]) -> Result<Option<i64>, DBError> {
+        let raw_expiry = self.db.get_cf(self.cf(EXPIRY_CF)?, addr_raw)?;
+        Ok(raw_expiry.and_then(|raw_expiry| decode_expiry(&raw_expiry)))
+    }
+
+    // Add the expiry entries for a record to a batch, replacing any old index entry
+    pub(super) fn batch_expiry(
+        &self,
+        batch: &mut WriteBatch,
+        addr_raw: &[u8],
+        new_expiry: i64,
+    ) -> Result<(), DBError> {
+        let index_cf = self.cf(EXPIRY_INDEX_CF)?;
+        if let Some(old_expiry) = self.stored_expiry(addr_raw)? {
+            batch.delete_cf(index_cf, index_key(old_expiry, addr_raw))?;
+        }
+        batch.put_cf(index_cf, index_key(new_expiry, addr_raw), [])?;
+        batch.put_cf(self.cf(EXPIRY_CF)?, addr_raw, new_expiry.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Get the record stored for an address, ignoring expiry and undecodable records.
+    pub(super) fn stored_record(
+        &self,
+        addr_raw: &[u8],
+    ) -> Result<Option<(AddressMetadata, Payload)>, DBError> {
+        Ok(self
+            .db
+            .get(addr_raw)?
+            .and_then(|raw_metadata| decode_record(&raw_metadata).ok()))
+    }
+
+    // Add the secondary index changes for a record being replaced to a batch
+    fn batch_indexes(
+        &self,
+        batch: &mut WriteBatch,
+        addr_raw: &[u8],
+        old_record: Option<(&AddressMetadata, &Payload)>,
+        new_record: Option<(&AddressMetadata, &Payload)>,
+    ) -> Result<(), DBError> {
+        let keys = |record: Option<(&AddressMetadata, &Payload)>| {
+            record
+                .map(|(metadata, payload)| referenced_keys(metadata, payload))
+                .unwrap_or_default()
+        };
+        self.batch_pubkeys(batch, addr_raw, &keys(old_record), &keys(new_record))?;
+        if self.search_index {
+            let old_payload = old_record.map(|(_, payload)| payload);
+            let new_payload = new_record.map(|(_, payload)| payload);
+            self.batch_terms(batch, addr_raw, old_payload, new_payload)?;
+        }
+        Ok(())
+    }
+
+    // Add deletions of a record, its expiry entries and its index entries to a batch
+    fn batch_remove(
+        &self,
+        batch: &mut WriteBatch,
+        addr_raw: &[u8],
+        expiry: Option<i64>,
+    ) -> Result<(), DBError> {
+        let old_record = self.stored_record(addr_raw)?;
+        let old_record = old_record
+            .as_ref()
+            .map(|(metadata, payload)| (metadata, payload));
+        self.batch_indexes(batch, addr_raw, old_record, None)?;
+        batch.delete(addr_raw)?;
+        batch.delete_cf(self.cf(EXPIRY_CF)?, addr_raw)?;
+        if let Some(expiry) = expiry {
+            batch.delete_cf(self.cf(EXPIRY_INDEX_CF)?, index_key(expiry, addr_raw))?;
+        }
+        Ok(())
+    }
+
+    // Write a record and its index entries, the caller holding the address lock
+    fn write_record(&self, addr: &Address, metadata: &AddressMetadata) -> Result<(), DBError> {
+        let addr_raw = addr.as_body();
+        let payload = Payload::decode(&metadata.serialized_payload[..])?;
+        let mut raw_metadata = Vec::with_capacity(metadata.encoded_len());
+        metadata.encode(&mut raw_metadata).unwrap();
+
+        let mut batch = WriteBatch::default();
+        self.batch_expiry(&mut batch, addr_raw, expiry(&payload))?;
+        let old_record = self.stored_record(addr_raw)?;
+        let old_record = old_record
+            .as_ref()
+            .map(|(metadata, payload)| (metadata, payload));
+        self.batch_indexes(&mut batch, addr_raw, old_record, Some((metadata, &payload)))?;
+        batch.put(addr_raw, raw_metadata)?;
+        self.db.write(batch)?;
+        Ok(())
+    }
+
+    // Get the unexpired record for an address, quarantining it if either the metadata or its
+    // payload is undecodable
+    fn get_record(&self, addr: &Address) -> Result<Option<(AddressMetadata, Payload)>, DBError> {
+        let raw_metadata = match self.get_raw(addr)? {
+            Some(some) => some,
+            None => return Ok(None),
+        };
+
+        match decode_record(&raw_metadata[..]) {
+            Ok(record) => Ok(Some(record)),
+            Err(err) => {
+                self.quarantine(addr.as_body(), &raw_metadata[..])?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Move a record out of the keyspace and into the quarantine column family.
+    pub fn quarantine(&self, key: &[u8], value: &[u8]) -> Result<(), DBError> {
+        error!("quarantining corrupted record {}", hex::encode(key));
+        let mut batch = WriteBatch::default();
+        batch.put_cf(self.quarantine_cf()?, key, value)?;
+        self.batch_remove(&mut batch, key, self.stored_expiry(key)?)?;
+        self.db.write(batch)?;
+        Ok(())
+    }
+}
+
+impl Database for KeyDB {
+    fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    fn put(&self, addr: &Address, metadata: &AddressMetadata) -> Result<(), DBError> {
+        let _guard = self.locks.lock(addr.as_body());
+        self.write_record(addr, metadata)
+    }
+
+    fn get(&self, addr: &Address) -> Result<Option<AddressMetadata>, DBError> {
+        Ok(self.get_record(addr)?.map(|(metadata, _)| metadata))
+    }
+
+    fn get_raw(&self, addr: &Address) -> Result<Option<Bytes>, DBError> {
+        let addr_raw = addr.as_body();
+        let raw_metadata = match self.db.get_pinned(addr_raw)? {
+            Some(some) => some,
+            None => return Ok(None),
+        };
+
+        let expiry = match self.stored_expiry(addr_raw)? {
+            Some(some) => some,
+            None => {
+                // Records without an expiry entry predate the index
+                match decode_record(&raw_metadata[..]) {
+                    Ok((_, payload)) => expiry(&payload),
+                    Err(err) => {
+                        self.quarantine(addr_raw, &raw_metadata[..])?;
+                        return Err(err);
+                    }
+                }
+            }
+        };
+
+        // Expired entries are left for the sweeper
+        if expiry < self.clock.now() {
+            Ok(None)
+        } else {
+            // Copy once, straight out of the pinned block
+            Ok(Some(Bytes::copy_from_slice(&raw_metadata)))
+        }
+    }
+
+    fn put_checked(
+        &self,
+        addr: &Address,
+        metadata: &AddressMetadata,
+        new_payload: &Payload,
+    ) -> Result<Result<(), ValidationError>, DBError> {
+        let _guard = self.locks.lock(addr.as_body());
+
+        // A corrupted record has been quarantined, leaving nothing to replace
+        let old_payload = match self.get_record(addr) {
+            Ok(old_record) => old_record.map(|(_, payload)| payload),
+            Err(DBError::Corrupted(_)) => None,
+            Err(err) => return Err(err),
+        };
+        if let Err(err) = check_replacement(old_payload.as_ref(), new_payload, self.clock.now()) {
+            return Ok(Err(err));
+        }
+
+        self.write_record(addr, metadata)?;
+        Ok(Ok(()))
+    }
+
+    fn remove(&self, addr: &Address) -> Result<bool, DBError> {
+        let addr_raw = addr.as_body();
+        let _guard = self.locks.lock(addr_raw);
+
+        if self.db.get(addr_raw)?.is_none() {
+            return Ok(false);
+        }
+        let mut batch = WriteBatch::default();
+        self.batch_remove(&mut batch, addr_raw, self.stored_expiry(addr_raw)?)?;
+        self.db.write(batch)?;
+        Ok(true)
+    }
+
+    fn remove_expired(&self) -> Result<usize, DBError> {
+        let now = self.clock.now();
+        let index_cf = self.cf(EXPIRY_INDEX_CF)?;
+        let mut removed = 0;
+        loop {
+            // Collect a batch of due index entries
+            let due: Vec<Box<[u8]>> = self
+                .db
+                .iterator_cf(index_cf, IteratorMode::Start)?
+                .map(|(key, _)| key)
+                .take_while(|key| decode_expiry(key).map_or(true, |expiry| expiry < now))
+                .take(SWEEP_BATCH)
+                .collect();
+            if due.is_empty() {
+                break;
+            }
+
+            // Hold the address locks until the batch is written
+            let _guards = self.locks.lock_all(due.iter().map(|key| index_addr(key)));
+            let mut batch = WriteBatch::default();
+            for key in &due {
+                batch.delete_cf(index_cf, key)?;
+
+                // Skip addresses which have been updated since they were indexed, comparing
+                // the stored expiry as the index encodes it
+                let addr_raw = index_addr(key);
+                let expiry = decode_expiry(key);
+                let stored_expiry = self.stored_expiry(addr_raw)?.map(|stored| stored.max(0));
+                if expiry.is_some() && stored_expiry == expiry {
+                    self.batch_remove(&mut batch, addr_raw, None)?;
+                    removed += 1;
+                }
+            }
+            self.db.write(batch)?;
+
+            if due.len() < SWEEP_BATCH {
+                break;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn search(&self, terms: &[Term], after: Option<&[u8]>, limit: usize) -> Result<Found, DBError> {
+        if !self.search_index {
+            return Err(DBError::SearchDisabled);
+        }
+        self.find(terms, after, limit)
+    }
+
+    fn lookup_pubkey(
+        &self,
+        pub_key: &[u8],
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Vec<Vec<u8>>, DBError> {
+        self.find_pubkey(pub_key, after, limit)
+    }
+
+    fn list(&self, after: Option<&[u8]>, limit: usize) -> Result<Vec<KeySummary>, DBError> {
+        self.summaries(after, limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use secp256k1::{rand, Secp256k1};
+
+    use crate::{
+        crypto::{ecdsa::Secp256k1PublicKey, *},
+        db::tests::metadata_with_entries,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_ttl_ok() {
+        // Open DB
+        let _ = std::fs::remove_dir_all("./test_db/ttl_ok");
+        let clock = Clock::frozen();
+        let key_db = KeyDB::try_new("./test_db/ttl_ok")
+            .unwrap()
+            .with_clock(clock.clone());
+
+        // Generate metadata with 10 sec TTL
+        let metadata = metadata_with_entries(clock.now(), 10, vec![]);
+
+        // Generate address
+        let secp = Secp256k1::new();
+        let (_, pk) = secp.generate_keypair(&mut rand::thread_rng());
+        let public_key = Secp256k1PublicKey(pk);
+        let addr = Address {
+            body: public_key.to_raw_address(),
+            ..Default::default()
+        };
+
+        // Put to database
+        key_db.put(&addr, &metadata).unwrap();
+
+        // Get from database before TTL
+        assert!(key_db.get(&addr).unwrap().is_some());
+
+        // Fast-forward until TTL is over
+        clock.advance(Duration::from_secs(12));
+
+        // Get from database after TTL
+        assert!(key_db.get(&addr).unwrap().is_none());
+
+        // Sweep expired entries
+        assert_eq!(key_db.remove_expired().unwrap(), 1);
+        assert!(key_db.db.get(addr.as_body()).unwrap().is_none());
+        assert_eq!(key_db.remove_expired().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_remove_negative_expiry() {
+        // Open DB
+        let _ = std::fs::remove_dir_all("./test_db/negative_expiry");
+        let key_db = KeyDB::try_new("./test_db/negative_expiry").unwrap();
+
+        // Expiry before the epoch is indexed at zero
+        let metadata = metadata_with_entries(-100, 10, vec![]);
+        let addr = Address {
+            body: vec![5; 20],
+            ..Default::default()
+        };
+        key_db.put(&addr, &metadata).unwrap();
+
+        // Truncated index keys are dropped rather than panicking
+        let index_cf = key_db.cf(EXPIRY_INDEX_CF).unwrap();
+        key_db.db.put_cf(index_cf, [0; 4], []).unwrap();
+
+        assert_eq!(key_db.remove_expired().unwrap(), 1);
+        assert!(key_db.db.get(addr.as_body()).unwrap().is_none());
+        assert!(key_db
+            .db
+            .iterator_cf(index_cf, IteratorMode::Start)
+            .unwrap()
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    fn test_get_corrupted() {
+        // Open DB
+        let key_db = KeyDB::try_new("./test_db/get_corrupted").unwrap();
+
+        // Store garbage bytes
+        let addr = Address {
+            body: vec![7; 20],
+            ..Default::default()
+        };
+        key_db.db.put(addr.as_body(), vec![2, 3, 5]).unwrap();
+
+        // First read surfaces the corruption
+        match key_db.get(&addr) {
+            Err(DBError::Corrupted(_)) => (),
+            _ => panic!("expected corrupted record"),
+        }
+
+        // Record has been moved to quarantine
+        assert!(key_db.get(&addr).unwrap().is_none());
+        let quarantine = key_db.quarantine_cf().unwrap();
+        assert!(key_db
+            .db
+            .get_cf(quarantine, addr.as_body())
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_put_over_corrupted_payload() {
+        let _ = std::fs::remove_dir_all("./test_db/put_corrupted_payload");
+        let key_db = KeyDB::try_new("./test_db/put_corrupted_payload").unwrap();
+
+        // Store metadata wrapping a garbage payload
+        let addr = Address {
+            body: vec![8; 20],
+            ..Default::default()
+        };
+        let corrupted = AddressMetadata {
+            pub_key: vec![],
+            serialized_payload: vec![2, 3, 5],
+            signature: vec![],
+            scheme: 1,
+        };
+        let mut raw_corrupted = Vec::with_capacity(corrupted.encoded_len());
+        corrupted.encode(&mut raw_corrupted).unwrap();
+        key_db.db.put(addr.as_body(), &raw_corrupted).unwrap();
+
+        // A valid replacement quarantines the corrupted record
+        let metadata = metadata_with_entries(key_db.clock.now(), 3000, vec![]);
+        let payload = Payload::decode(&metadata.serialized_payload[..]).unwrap();
+        key_db
+            .put_checked(&addr, &metadata, &payload)
+            .unwrap()
+            .unwrap();
+        assert_eq!(key_db.get(&addr).unwrap(), Some(metadata));
+        let quarantine = key_db.quarantine_cf().unwrap();
+        assert!(key_db
+            .db
+            .get_cf(quarantine, addr.as_body())
+            .unwrap()
+            .is_some());
+    }
+}
@@ -0,0 +1,146 @@
+use rocksdb::IteratorMode;
+use serde_derive::Serialize;
+
+use crate::crypto::{authentication::validate, ecdsa::Secp256k1, Address};
+
+use super::{decode_record, errors::DBError, KeyDB};
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct ScrubReport {
+    pub checked: usize,
+    pub undecodable: usize,
+    pub invalid: usize,
+}
+
+impl ScrubReport {
+    pub fn quarantined(&self) -> usize {
+        self.undecodable + self.invalid
+    }
+}
+
+impl KeyDB {
+    /// Verify every stored record, quarantining those which fail to decode, whose public key
+    /// does not hash to the address, or whose signature is invalid.
+    pub fn scrub(&self) -> Result<ScrubReport, DBError> {
+        let mut report = ScrubReport::default();
+        for (key, value) in self.db.iterator(IteratorMode::Start) {
+            report.checked += 1;
+
+            let metadata = match decode_record(&value) {
+                Ok((metadata, _)) => metadata,
+                Err(err) => {
+                    warn!("{} failed to decode: {}", hex::encode(&key), err);
+                    report.undecodable += 1;
+                    self.quarantine(&key, &value)?;
+                    continue;
+                }
+            };
+
+            let addr = Address {
+                body: key.to_vec(),
+                ..Default::default()
+            };
+
+            // TODO: Support Schnorr
+            let validation = match metadata.scheme {
+                1 => validate::<Secp256k1>(&addr, &metadata).map_err(|err| err.to_string()),
+                scheme => Err(format!("unsupported signature scheme {}", scheme)),
+            };
+            if let Err(err) = validation {
+                warn!("{} failed validation: {}", hex::encode(&key), err);
+                report.invalid += 1;
+                self.quarantine(&key, &value)?;
+            }
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use bitcoin_hashes::{sha256, Hash};
+    use prost::Message;
+    use secp256k1::{rand, Secp256k1 as Secp};
+
+    use crate::{
+        crypto::{ecdsa::Secp256k1PublicKey, *},
+        db::Database,
+        models::address_metadata::{AddressMetadata, Payload},
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_scrub() {
+        let _ = std::fs::remove_dir_all("./test_db/scrub");
+        let key_db = KeyDB::try_new("./test_db/scrub").unwrap();
+
+        // Generate signed metadata
+        let secp = Secp::new();
+        let (sk, pk) = secp.generate_keypair(&mut rand::thread_rng());
+        let public_key = Secp256k1PublicKey(pk);
+        let payload = Payload {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64,
+            ttl: 3000,
+            entries: vec![],
+        };
+        let mut serialized_payload = Vec::with_capacity(payload.encoded_len());
+        payload.encode(&mut serialized_payload).unwrap();
+        let digest = sha256::Hash::hash(&serialized_payload);
+        let signature = secp.sign(&secp256k1::Message::from_slice(&digest[..]).unwrap(), &sk);
+        let metadata = AddressMetadata {
+            pub_key: public_key.serialize(),
+            serialized_payload,
+            signature: signature.serialize_compact().to_vec(),
+            scheme: 1,
+        };
+        let valid_addr = Address {
+            body: public_key.to_raw_address(),
+            ..Default::default()
+        };
+        key_db.put(&valid_addr, &metadata).unwrap();
+
+        // Store the same metadata under an address it does not hash to
+        let wrong_addr = Address {
+            body: vec![1; 20],
+            ..Default::default()
+        };
+        key_db.put(&wrong_addr, &metadata).unwrap();
+
+        // Store garbage bytes
+        let garbage_addr = Address {
+            body: vec![2; 20],
+            ..Default::default()
+        };
+        key_db
+            .db
+            .put(garbage_addr.as_body(), vec![2, 3, 5])
+            .unwrap();
+
+        let report = key_db.scrub().unwrap();
+        assert_eq!(
+            report,
+            ScrubReport {
+                checked: 3,
+                undecodable: 1,
+                invalid: 1,
+            }
+        );
+
+        // Only the valid record remains
+        assert!(key_db.get(&valid_addr).unwrap().is_some());
+        assert!(key_db.get(&wrong_addr).unwrap().is_none());
+        assert!(key_db.get(&garbage_addr).unwrap().is_none());
+        let quarantine = key_db.quarantine_cf().unwrap();
+        assert!(key_db
+            .db
+            .get_cf(quarantine, garbage_addr.as_body())
+            .unwrap()
+            .is_some());
+    }
+}
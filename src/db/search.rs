@@ -0,0 +1,402 @@
+use std::collections::BTreeSet;
+
+use rocksdb::{Direction, IteratorMode, WriteBatch};
+
+use crate::{crypto::Address, models::address_metadata::Payload};
+
+use super::{
+    decode_record,
+    errors::DBError,
+    rocks::{META_CF, SEARCH_CF},
+    Database, KeyDB,
+};
+
+const INDEXED_KEY: &[u8] = b"search_indexed";
+
+/// Index keys scanned per result requested, bounding the work done for sparse matches.
+const SCAN_FACTOR: usize = 10;
+
+/// A page of addresses found by a search.
+#[derive(Debug, Default, PartialEq)]
+pub struct Found {
+    pub addrs: Vec<Vec<u8>>,
+    /// Address to resume the search after, if it may continue.
+    pub next: Option<Vec<u8>>,
+}
+
+impl Found {
+    /// A page which continues after its last address if it is full.
+    pub fn page(addrs: Vec<Vec<u8>>, limit: usize) -> Self {
+        let next = if addrs.len() == limit {
+            addrs.last().cloned()
+        } else {
+            None
+        };
+        Found { addrs, next }
+    }
+}
+
+/// A searchable property of a payload.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Term {
+    Kind(String),
+    Header(String, String),
+}
+
+fn push_field(buf: &mut Vec<u8>, field: &str) {
+    buf.extend_from_slice(&(field.len() as u32).to_be_bytes());
+    buf.extend_from_slice(field.as_bytes());
+}
+
+impl Term {
+    /// Parse a header term of the form `name:value`.
+    pub fn header(spec: &str) -> Option<Term> {
+        let mut parts = spec.splitn(2, ':');
+        let name = parts.next()?;
+        let value = parts.next()?;
+        Some(Term::Header(name.to_string(), value.to_string()))
+    }
+
+    // Terms are encoded with length prefixed fields so that no term is a prefix of another
+    fn prefix(&self) -> Vec<u8> {
+        let mut prefix = Vec::new();
+        match self {
+            Term::Kind(kind) => {
+                prefix.push(b'k');
+                push_field(&mut prefix, kind);
+            }
+            Term::Header(name, value) => {
+                prefix.push(b'h');
+                push_field(&mut prefix, name);
+                push_field(&mut prefix, value);
+            }
+        }
+        prefix
+    }
+
+    // Search index keys are the encoded term followed by the address
+    fn index_key(&self, addr_raw: &[u8]) -> Vec<u8> {
+        [&self.prefix()[..], addr_raw].concat()
+    }
+}
+
+/// Collect the entry kinds and headers of a payload.
+pub fn payload_terms(payload: &Payload) -> BTreeSet<Term> {
+    let mut terms = BTreeSet::new();
+    for entry in &payload.entries {
+        terms.insert(Term::Kind(entry.kind.clone()));
+        for header in &entry.headers {
+            terms.insert(Term::Header(header.name.clone(), header.value.clone()));
+        }
+    }
+    terms
+}
+
+/// Check whether a payload has every given term.
+pub fn matches(payload: &Payload, terms: &[Term]) -> bool {
+    let payload_terms = payload_terms(payload);
+    terms.iter().all(|term| payload_terms.contains(term))
+}
+
+impl KeyDB {
+    /// Enable or disable maintenance of the search index, rebuilding it if it may be stale.
+    pub fn with_search_index(mut self, enabled: bool) -> Result<Self, DBError> {
+        let meta_cf = self.cf(META_CF)?;
+        let indexed = self.db.get_cf(meta_cf, INDEXED_KEY)?.is_some();
+        if enabled && !indexed {
+            self.rebuild_search_index()?;
+            self.db.put_cf(meta_cf, INDEXED_KEY, [])?;
+        } else if !enabled && indexed {
+            // Writes made while disabled are not indexed
+            self.db.delete_cf(meta_cf, INDEXED_KEY)?;
+        }
+        self.search_index = enabled;
+        Ok(self)
+    }
+
+    fn rebuild_search_index(&self) -> Result<(), DBError> {
+        info!("rebuilding search index");
+        let search_cf = self.cf(SEARCH_CF)?;
+
+        // Clear stale terms
+        let mut batch = WriteBatch::default();
+        for (key, _) in self.db.iterator_cf(search_cf, IteratorMode::Start)? {
+            batch.delete_cf(search_cf, key)?;
+        }
+        self.db.write(batch)?;
+
+        // Index every decodable record
+        let mut indexed = 0;
+        for (key, value) in self.db.iterator(IteratorMode::Start) {
+            if let Ok((_, payload)) = decode_record(&value) {
+                let mut batch = WriteBatch::default();
+                self.batch_terms(&mut batch, &key, None, Some(&payload))?;
+                self.db.write(batch)?;
+                indexed += 1;
+            }
+        }
+        info!("indexed {} records", indexed);
+        Ok(())
+    }
+
+    // Add the index changes for a record's payload being replaced to a batch
+    pub(super) fn batch_terms(
+        &self,
+        batch: &mut WriteBatch,
+        addr_raw: &[u8],
+        old_payload: Option<&Payload>,
+        new_payload: Option<&Payload>,
+    ) -> Result<(), DBError> {
+        let search_cf = self.cf(SEARCH_CF)?;
+        let old_terms = old_payload.map(payload_terms).unwrap_or_default();
+        let new_terms = new_payload.map(payload_terms).unwrap_or_default();
+        for term in old_terms.difference(&new_terms) {
+            batch.delete_cf(search_cf, term.index_key(addr_raw))?;
+        }
+        for term in new_terms.difference(&old_terms) {
+            batch.put_cf(search_cf, term.index_key(addr_raw), [])?;
+        }
+        Ok(())
+    }
+
+    /// Scan the index for the first term, checking each candidate against the live record. At
+    /// most `limit * SCAN_FACTOR` index keys are scanned, after which the page is returned short
+    /// with the last key scanned as its cursor.
+    pub(super) fn find(
+        &self,
+        terms: &[Term],
+        after: Option<&[u8]>,
+        limit: usize,
+    ) -> Result<Found, DBError> {
+        let first = match terms.first() {
+            Some(some) => some,
+            None => return Ok(vec![]),
+        };
+        let prefix = first.prefix();
+        let start = [&prefix[..], after.unwrap_or_default()].concat();
+        let max_scanned = limit.saturating_mul(SCAN_FACTOR);
+
+        let mut found = Vec::new();
+        let mut scanned = 0;
+        let search_cf = self.cf(SEARCH_CF)?;
+        let mode = IteratorMode::From(&start, Direction::Forward);
+        for (key, _) in self.db.iterator_cf(search_cf, mode)? {
+            if !key.starts_with(&prefix) {
+                break;
+            }
+            let addr_raw = &key[prefix.len()..];
+            if Some(addr_raw) == after {
+                continue;
+            }
+
+            let addr = Address {
+                body: addr_raw.to_vec(),
+                ..Default::default()
+            };
+            if self.is_live_match(&addr, terms)? {
+                found.push(addr.body.clone());
+            }
+
+            // Stop once the page is full or the scan budget is spent
+            scanned += 1;
+            if found.len() == limit || scanned == max_scanned {
+                return Ok(Found {
+                    addrs: found,
+                    next: Some(addr.body),
+                });
+            }
+        }
+        Ok(Found {
+            addrs: found,
+            next: None,
+        })
+    }
+
+    // Check a candidate against its live record. Expired records awaiting the sweeper and terms
+    // left behind by quarantined records don't match.
+    fn is_live_match(&self, addr: &Address, terms: &[Term]) -> Result<bool, DBError> {
+        let raw_metadata = match self.get_raw(addr) {
+            Ok(Some(some)) => some,
+            Ok(None) | Err(DBError::Corrupted(_)) => return Ok(false),
+            Err(err) => return Err(err),
+        };
+        Ok(decode_record(&raw_metadata).map_or(false, |(_, payload)| matches(&payload, terms)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use crate::{
+        db::tests::metadata_with_entries,
+        models::address_metadata::{AddressMetadata, Entry, Header},
+    };
+
+    use super::*;
+
+    fn metadata_with_entry(
+        timestamp: i64,
+        ttl: i64,
+        kind: &str,
+        type_header: &str,
+    ) -> AddressMetadata {
+        let entry = Entry {
+            kind: kind.to_string(),
+            headers: vec![Header {
+                name: "Type".to_string(),
+                value: type_header.to_string(),
+            }],
+            entry_data: vec![],
+        };
+        metadata_with_entries(timestamp, ttl, vec![entry])
+    }
+
+    #[test]
+    fn test_search_index() {
+        // Open DB
+        let _ = std::fs::remove_dir_all("./test_db/search_index");
+        let key_db = KeyDB::try_new("./test_db/search_index")
+            .unwrap()
+            .with_search_index(true)
+            .unwrap();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        // Put three addresses, one of them expired
+        let addrs: Vec<Address> = (1..=3)
+            .map(|i| Address {
+                body: vec![i; 20],
+                ..Default::default()
+            })
+            .collect();
+        key_db
+            .put(&addrs[0], &metadata_with_entry(now, 3000, "xpub", "avatar"))
+            .unwrap();
+        key_db
+            .put(&addrs[1], &metadata_with_entry(now, 3000, "xpub", "wallet"))
+            .unwrap();
+        key_db
+            .put(
+                &addrs[2],
+                &metadata_with_entry(now - 20, 10, "xpub", "avatar"),
+            )
+            .unwrap();
+
+        let xpub = [Term::Kind("xpub".to_string())];
+        let avatar = [Term::header("Type:avatar").unwrap()];
+        assert_eq!(
+            key_db.search(&xpub, None, 10).unwrap().addrs,
+            vec![addrs[0].body.clone(), addrs[1].body.clone()]
+        );
+        assert_eq!(
+            key_db.search(&avatar, None, 10).unwrap().addrs,
+            vec![addrs[0].body.clone()]
+        );
+
+        // Paginate
+        let page = key_db.search(&xpub, None, 1).unwrap();
+        assert_eq!(page.addrs, vec![addrs[0].body.clone()]);
+        let page = key_db.search(&xpub, page.next.as_deref(), 1).unwrap();
+        assert_eq!(page.addrs, vec![addrs[1].body.clone()]);
+
+        // Replacing the metadata drops its old terms
+        key_db
+            .put(
+                &addrs[0],
+                &metadata_with_entry(now + 1, 3000, "text_utf8", "bio"),
+            )
+            .unwrap();
+        assert_eq!(
+            key_db.search(&xpub, None, 10).unwrap().addrs,
+            vec![addrs[1].body.clone()]
+        );
+        assert!(key_db.search(&avatar, None, 10).unwrap().addrs.is_empty());
+
+        // Sweeping drops the terms of expired records
+        assert_eq!(key_db.remove_expired().unwrap(), 1);
+        let search_cf = key_db.cf(SEARCH_CF).unwrap();
+        assert!(key_db
+            .db
+            .get_cf(search_cf, avatar[0].index_key(&addrs[2].body))
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_rebuild_search_index() {
+        // Open DB without the index
+        let _ = std::fs::remove_dir_all("./test_db/rebuild_search_index");
+        let key_db = KeyDB::try_new("./test_db/rebuild_search_index").unwrap();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let addr = Address {
+            body: vec![1; 20],
+            ..Default::default()
+        };
+        key_db
+            .put(&addr, &metadata_with_entry(now, 3000, "xpub", "avatar"))
+            .unwrap();
+
+        let xpub = [Term::Kind("xpub".to_string())];
+        match key_db.search(&xpub, None, 10) {
+            Err(DBError::SearchDisabled) => (),
+            _ => panic!("expected search to be disabled"),
+        }
+
+        // Enabling the index picks up existing records
+        let key_db = key_db.with_search_index(true).unwrap();
+        assert_eq!(
+            key_db.search(&xpub, None, 10).unwrap().addrs,
+            vec![addr.body]
+        );
+    }
+
+    #[test]
+    fn test_search_scan_budget() {
+        // Open DB
+        let _ = std::fs::remove_dir_all("./test_db/search_scan_budget");
+        let key_db = KeyDB::try_new("./test_db/search_scan_budget")
+            .unwrap()
+            .with_search_index(true)
+            .unwrap();
+        let now = key_db.clock().now();
+
+        // A single match behind more candidates than one page may scan
+        let addrs: Vec<Address> = (1..=SCAN_FACTOR as u8 + 1)
+            .map(|i| Address {
+                body: vec![i; 20],
+                ..Default::default()
+            })
+            .collect();
+        let (last, candidates) = addrs.split_last().unwrap();
+        for addr in candidates {
+            key_db
+                .put(addr, &metadata_with_entry(now, 3000, "xpub", "wallet"))
+                .unwrap();
+        }
+        key_db
+            .put(last, &metadata_with_entry(now, 3000, "xpub", "avatar"))
+            .unwrap();
+
+        // The budget runs out with a short page resuming from the last key scanned
+        let terms = [
+            Term::Kind("xpub".to_string()),
+            Term::header("Type:avatar").unwrap(),
+        ];
+        let page = key_db.search(&terms, None, 1).unwrap();
+        assert_eq!(
+            page,
+            Found {
+                addrs: vec![],
+                next: Some(candidates[SCAN_FACTOR - 1].body.clone()),
+            }
+        );
+        let page = key_db.search(&terms, page.next.as_deref(), 1).unwrap();
+        assert_eq!(page.addrs, vec![last.body.clone()]);
+    }
+}
@@ -0,0 +1,16 @@
+use std::time::Duration;
+
+use super::{AsyncDB, Database};
+
+/// Periodically remove expired entries from the database.
+pub async fn sweep_expired<D: Database>(db: AsyncDB<D>, period: Duration) {
+    let mut interval = tokio::time::interval(period);
+    loop {
+        interval.tick().await;
+        match db.remove_expired().await {
+            Ok(0) => (),
+            Ok(removed) => info!("swept {} expired entries", removed),
+            Err(err) => error!("failed to sweep expired entries: {}", err),
+        }
+    }
+}
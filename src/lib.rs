@@ -0,0 +1,26 @@
+#[macro_use]
+extern crate clap;
+#[macro_use]
+extern crate log;
+
+pub mod bitcoin;
+pub mod clock;
+pub mod commands;
+pub mod crypto;
+pub mod db;
+pub mod metrics;
+pub mod net;
+pub mod server;
+pub mod settings;
+
+pub use crate::{
+    server::{Keyserver, KeyserverBuilder},
+    settings::Settings,
+};
+
+pub mod models {
+    pub mod bip70 {
+        include!(concat!(env!("OUT_DIR"), "/bip70.rs"));
+    }
+    pub use keyserver_core::models::address_metadata;
+}
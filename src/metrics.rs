@@ -0,0 +1,65 @@
+use lazy_static::lazy_static;
+use prometheus::{
+    exponential_buckets, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    HistogramVec, IntCounter, IntCounterVec,
+};
+
+lazy_static! {
+    pub static ref KEY_GETS: IntCounterVec = register_int_counter_vec!(
+        "keyserver_key_gets_total",
+        "Metadata GET requests by result",
+        &["result"]
+    )
+    .unwrap();
+    pub static ref KEY_PUTS: IntCounterVec = register_int_counter_vec!(
+        "keyserver_key_puts_total",
+        "Metadata PUT requests by outcome",
+        &["outcome"]
+    )
+    .unwrap();
+    pub static ref INVOICES_ISSUED: IntCounter =
+        register_int_counter!("keyserver_invoices_issued_total", "Payment requests issued")
+            .unwrap();
+    pub static ref INVOICES_SETTLED: IntCounterVec = register_int_counter_vec!(
+        "keyserver_invoices_settled_total",
+        "Invoices marked paid by source",
+        &["source"]
+    )
+    .unwrap();
+    pub static ref PAYMENTS_ACCEPTED: IntCounter =
+        register_int_counter!("keyserver_payments_accepted_total", "Payments accepted").unwrap();
+    pub static ref PAYMENTS_REJECTED: IntCounterVec = register_int_counter_vec!(
+        "keyserver_payments_rejected_total",
+        "Payments rejected by reason",
+        &["reason"]
+    )
+    .unwrap();
+    pub static ref SATOSHIS_EARNED: IntCounter = register_int_counter!(
+        "keyserver_satoshis_earned_total",
+        "Satoshis paid to settled invoices"
+    )
+    .unwrap();
+    pub static ref PEER_FETCHES: IntCounterVec = register_int_counter_vec!(
+        "keyserver_peer_fetches_total",
+        "Metadata fetches from peers by result",
+        &["result"]
+    )
+    .unwrap();
+    pub static ref ZMQ_TRANSACTIONS: IntCounter = register_int_counter!(
+        "keyserver_zmq_transactions_total",
+        "Transactions received over ZMQ"
+    )
+    .unwrap();
+    pub static ref KEYSERVER_OP_RETURNS: IntCounter = register_int_counter!(
+        "keyserver_op_returns_total",
+        "Keyserver OP_RETURN outputs matched in received transactions"
+    )
+    .unwrap();
+    pub static ref DB_LATENCY: HistogramVec = register_histogram_vec!(
+        "keyserver_db_operation_seconds",
+        "Database operation latency by operation",
+        &["operation"],
+        exponential_buckets(0.000_05, 2.0, 16).unwrap()
+    )
+    .unwrap();
+}
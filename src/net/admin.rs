@@ -0,0 +1,488 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    pin::Pin,
+};
+
+use actix_service::{Service, Transform};
+use actix_web::{
+    dev::{Body, ServiceRequest, ServiceResponse},
+    http::header::AUTHORIZATION,
+    web, Error, HttpResponse,
+};
+use futures::{
+    future::{err, ready, Ready},
+    prelude::*,
+    task::{Context, Poll},
+};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use subtle::ConstantTimeEq;
+
+use crate::{
+    bitcoin::WalletState,
+    clock::Clock,
+    crypto::Address,
+    db::{AsyncDB, Database, KeyDB},
+    settings::{KeyListing, Settings},
+};
+
+use super::{encode_address, errors::ServerError, listing::list_keys, peer::PeerBans};
+
+const REDACTED: &str = "<redacted>";
+
+/// Settings shown as they are, every other setting being redacted.
+const PUBLIC_SETTINGS: [&str; 15] = [
+    "bind",
+    "node_ip",
+    "rpc_port",
+    "zmq_port",
+    "db_path",
+    "network",
+    "sweep_interval",
+    "db_concurrency",
+    "search_index",
+    "key_listing",
+    "snapshot_dir",
+    "zmq_staleness",
+    "breaker_threshold",
+    "breaker_cooldown",
+    "pki_cert_chain",
+];
+
+/// Register the admin scope, protected by the admin key. Routes specific to the storage backend
+/// are registered by `maintenance`.
+pub fn configure<D: Database>(
+    cfg: &mut web::ServiceConfig,
+    admin_key: String,
+    key_listing: KeyListing,
+    key_db: AsyncDB<D>,
+    wallet_state: WalletState,
+    bans: PeerBans,
+    maintenance: fn(&mut web::ServiceConfig),
+) {
+    cfg.service(
+        web::scope("/admin")
+            .wrap(AdminAuth::new(admin_key))
+            .data(key_db)
+            .data(wallet_state)
+            .data(bans)
+            .configure(|cfg| {
+                // Key listing
+                if key_listing == KeyListing::Admin {
+                    cfg.route("/keys", web::get().to(list_keys::<D>));
+                }
+            })
+            .route("/keys/{addr}", web::delete().to(delete_key::<D>))
+            .route("/bans", web::get().to(list_bans))
+            .route("/bans", web::put().to(ban_peer))
+            .route("/bans", web::delete().to(unban_peer))
+            .route("/invoices", web::get().to(list_invoices))
+            .route("/invoices/{addr}", web::delete().to(cancel_invoice))
+            .configure(maintenance)
+            .route("/settings", web::get().to(settings)),
+    );
+}
+
+/// Register the RocksDB maintenance routes within the admin scope.
+pub fn rocks_maintenance(cfg: &mut web::ServiceConfig) {
+    cfg.route("/db/stats", web::get().to(db_stats))
+        .route("/db/compact", web::post().to(compact))
+        .route("/db/scrub", web::post().to(scrub))
+        .route("/db/snapshot", web::post().to(snapshot));
+}
+
+/*
+Admin authentication middleware
+*/
+pub struct AdminAuth(String);
+
+impl AdminAuth {
+    pub fn new(admin_key: String) -> Self {
+        AdminAuth(admin_key)
+    }
+}
+
+impl<S> Transform<S> for AdminAuth
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<Body>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AdminAuthMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AdminAuthMiddleware {
+            service,
+            admin_key: self.0.clone(),
+        }))
+    }
+}
+
+pub struct AdminAuthMiddleware<S> {
+    service: S,
+    admin_key: String,
+}
+
+impl<S> Service for AdminAuthMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<Body>, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<Body>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        // Grab bearer key from authorization header
+        let authorized = match req.headers().get(AUTHORIZATION) {
+            Some(auth) => match auth.to_str() {
+                Ok(auth_str) if auth_str.starts_with("Bearer ") => {
+                    let key = &auth_str.as_bytes()[7..];
+                    key.ct_eq(self.admin_key.as_bytes()).unwrap_u8() == 1
+                }
+                _ => false,
+            },
+            None => false,
+        };
+
+        if authorized {
+            Box::pin(self.service.call(req))
+        } else {
+            Box::pin(err(ServerError::Unauthorized.into()))
+        }
+    }
+}
+
+pub async fn delete_key<D: Database>(
+    addr_str: web::Path<String>,
+    db_data: web::Data<AsyncDB<D>>,
+) -> Result<HttpResponse, ServerError> {
+    // Convert address
+    let addr = Address::decode(&addr_str)?;
+
+    // Remove from database
+    if db_data.remove(addr).await? {
+        info!("force deleted {}", addr_str);
+        Ok(HttpResponse::Ok().finish())
+    } else {
+        Err(ServerError::NotFound)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PeerQuery {
+    peer: String,
+}
+
+pub async fn list_bans(bans: web::Data<PeerBans>) -> HttpResponse {
+    HttpResponse::Ok().json(bans.list())
+}
+
+pub async fn ban_peer(query: web::Query<PeerQuery>, bans: web::Data<PeerBans>) -> HttpResponse {
+    info!("banning peer {}", query.peer);
+    bans.ban(query.into_inner().peer);
+    HttpResponse::Ok().finish()
+}
+
+pub async fn unban_peer(
+    query: web::Query<PeerQuery>,
+    bans: web::Data<PeerBans>,
+) -> Result<HttpResponse, ServerError> {
+    if bans.unban(&query.peer) {
+        info!("unbanned peer {}", query.peer);
+        Ok(HttpResponse::Ok().finish())
+    } else {
+        Err(ServerError::NotFound)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct PendingInvoice {
+    address: String,
+    issued: u64,
+}
+
+pub async fn list_invoices(
+    wallet_state: web::Data<WalletState>,
+    settings: web::Data<Settings>,
+    clock: web::Data<Clock>,
+) -> HttpResponse {
+    let invoices: Vec<PendingInvoice> = wallet_state
+        .pending(clock.now() as u64)
+        .into_iter()
+        .filter_map(|(addr_raw, issued)| {
+            Some(PendingInvoice {
+                address: encode_address(addr_raw, &settings.network)?,
+                issued,
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(invoices)
+}
+
+pub async fn cancel_invoice(
+    addr_str: web::Path<String>,
+    wallet_state: web::Data<WalletState>,
+) -> Result<HttpResponse, ServerError> {
+    let addr = Address::decode(&addr_str)?;
+    if wallet_state.cancel(addr.as_body()) {
+        info!("canceled invoice {}", addr_str);
+        Ok(HttpResponse::Ok().finish())
+    } else {
+        Err(ServerError::NotFound)
+    }
+}
+
+pub async fn db_stats(db_data: web::Data<AsyncDB<KeyDB>>) -> Result<HttpResponse, ServerError> {
+    let stats = db_data.maintenance(|db| db.stats()).await?;
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+pub async fn compact(db_data: web::Data<AsyncDB<KeyDB>>) -> Result<HttpResponse, ServerError> {
+    info!("compacting database");
+    db_data.maintenance(|db| db.compact()).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+pub async fn scrub(db_data: web::Data<AsyncDB<KeyDB>>) -> Result<HttpResponse, ServerError> {
+    let report = db_data.maintenance(|db| db.scrub()).await?;
+    info!(
+        "checked {} records, quarantined {} ({} undecodable, {} invalid)",
+        report.checked,
+        report.quarantined(),
+        report.undecodable,
+        report.invalid
+    );
+    Ok(HttpResponse::Ok().json(report))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SnapshotRequest {
+    path: String,
+}
+
+// Resolve a requested snapshot path within the snapshot directory, refusing absolute paths and
+// any which could escape it
+fn snapshot_path(snapshot_dir: &str, requested: &str) -> Option<PathBuf> {
+    let requested = Path::new(requested);
+    let contained = requested.components().all(|component| match component {
+        Component::Normal(_) => true,
+        _ => false,
+    });
+    if !contained || requested.as_os_str().is_empty() {
+        return None;
+    }
+    Some(Path::new(snapshot_dir).join(requested))
+}
+
+pub async fn snapshot(
+    request: web::Json<SnapshotRequest>,
+    db_data: web::Data<AsyncDB<KeyDB>>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, ServerError> {
+    let snapshot_dir = settings
+        .snapshot_dir
+        .as_ref()
+        .ok_or(ServerError::SnapshotDisabled)?;
+    let path = snapshot_path(snapshot_dir, &request.path).ok_or(ServerError::SnapshotPath)?;
+    let path_inner = path.clone();
+    db_data
+        .maintenance(move |db| db.checkpoint(path_inner))
+        .await?;
+    info!("created snapshot at {}", path.display());
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Serialize settings, redacting all but the public settings which are set.
+pub fn redacted(settings: &Settings) -> Value {
+    let mut value = serde_json::to_value(settings).unwrap();
+    if let Value::Object(fields) = &mut value {
+        for (name, field) in fields.iter_mut() {
+            if !PUBLIC_SETTINGS.contains(&name.as_str()) && !field.is_null() {
+                *field = REDACTED.into();
+            }
+        }
+    }
+    value
+}
+
+pub async fn settings(settings: web::Data<Settings>) -> HttpResponse {
+    HttpResponse::Ok().json(redacted(&settings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bitcoin::Invoice,
+        db::MemoryDB,
+        models::{address_metadata::AddressMetadata, bip70::PaymentDetails},
+        net::tests::*,
+    };
+    use actix_web::{http::StatusCode, test, App};
+    use prost::Message;
+
+    const ADMIN_KEY: &str = "admin";
+
+    #[actix_rt::test]
+    async fn test_admin_auth() {
+        // Init routes
+        let mut app = test::init_service(
+            App::new().service(
+                web::scope("/admin")
+                    .wrap(AdminAuth::new(ADMIN_KEY.to_string()))
+                    .data(PeerBans::default())
+                    .route("/bans", web::get().to(list_bans)),
+            ),
+        )
+        .await;
+
+        // Missing key
+        let req = test::TestRequest::get().uri("/admin/bans").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Wrong key
+        let req = test::TestRequest::get()
+            .uri("/admin/bans")
+            .header(AUTHORIZATION, "Bearer wrong")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Correct key
+        let req = test::TestRequest::get()
+            .uri("/admin/bans")
+            .header(AUTHORIZATION, format!("Bearer {}", ADMIN_KEY))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_delete_key() {
+        // Init routes
+        let key_db = AsyncDB::new(MemoryDB::default(), 1);
+        let mut app = test::init_service(App::new().data(key_db.clone()).route(
+            "/admin/keys/{addr}",
+            web::delete().to(delete_key::<MemoryDB>),
+        ))
+        .await;
+
+        // Put metadata
+        let (address_base58, metadata_raw) = generate_address_metadata();
+        let addr = Address::decode(&address_base58).unwrap();
+        let metadata = AddressMetadata::decode(&metadata_raw[..]).unwrap();
+        key_db.inner().put(&addr, &metadata).unwrap();
+
+        // Delete twice
+        let req = test::TestRequest::delete()
+            .uri(&format!("/admin/keys/{}", address_base58))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(key_db.inner().get(&addr).unwrap().is_none());
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/admin/keys/{}", address_base58))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn test_ban_unban() {
+        // Init routes
+        let bans = PeerBans::default();
+        let mut app = test::init_service(
+            App::new()
+                .data(bans.clone())
+                .route("/admin/bans", web::put().to(ban_peer))
+                .route("/admin/bans", web::delete().to(unban_peer)),
+        )
+        .await;
+
+        let req = test::TestRequest::put()
+            .uri("/admin/bans?peer=http://peer.example:8080")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(bans.is_banned("http://peer.example:8080"));
+
+        let req = test::TestRequest::delete()
+            .uri("/admin/bans?peer=http://peer.example:8080")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(bans.list().is_empty());
+    }
+
+    #[actix_rt::test]
+    async fn test_cancel_invoice() {
+        // Init routes
+        let wallet_state = WalletState::default();
+        let mut app = test::init_service(
+            App::new()
+                .data(wallet_state.clone())
+                .route("/admin/invoices/{addr}", web::delete().to(cancel_invoice)),
+        )
+        .await;
+
+        let (address_base58, _) = generate_address_metadata();
+        let addr = Address::decode(&address_base58).unwrap();
+        wallet_state.add(
+            addr.as_body().to_vec(),
+            Invoice {
+                id: "id".to_string(),
+                details: PaymentDetails::default(),
+                payment_request: vec![],
+                uri: String::new(),
+                paid: false,
+            },
+        );
+        assert_eq!(wallet_state.pending(0).len(), 1);
+
+        let req = test::TestRequest::delete()
+            .uri(&format!("/admin/invoices/{}", address_base58))
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(wallet_state.pending(0).is_empty());
+    }
+
+    #[test]
+    fn test_snapshot_path() {
+        assert_eq!(
+            snapshot_path("/snapshots", "nightly/1"),
+            Some(PathBuf::from("/snapshots/nightly/1"))
+        );
+        assert_eq!(snapshot_path("/snapshots", "../db"), None);
+        assert_eq!(snapshot_path("/snapshots", "nightly/../../db"), None);
+        assert_eq!(snapshot_path("/snapshots", "/tmp/db"), None);
+        assert_eq!(snapshot_path("/snapshots", ""), None);
+    }
+
+    #[test]
+    fn test_redacted() {
+        let settings = Settings {
+            admin_key: Some("0123456789abcdef".to_string()),
+            ..Default::default()
+        };
+        let value = redacted(&settings);
+        assert_eq!(value["secret"], REDACTED);
+        assert_eq!(value["rpc_password"], REDACTED);
+        assert_eq!(value["rpc_username"], REDACTED);
+        assert_eq!(value["admin_key"], REDACTED);
+        assert_eq!(value["bind"], settings.bind.as_str());
+        assert!(value["pki_key"].is_null());
+    }
+}
@@ -1,50 +1,34 @@
-use std::fmt;
+use std::{fmt, time::Duration};
 
-use actix_web::{error, HttpResponse};
+use actix_web::{
+    error,
+    http::header::{RETRY_AFTER, WWW_AUTHENTICATE},
+    HttpResponse,
+};
 use bitcoin::consensus::encode::Error as TxDeserializeError;
 use bitcoincash_addr::{base58, cashaddr};
 use prost::DecodeError;
 use rocksdb::Error as RocksError;
 
-use crate::crypto::errors::CryptoError;
+use crate::{crypto::errors::CryptoError, db::errors::DBError};
 
-#[derive(Debug)]
-pub enum ValidationError {
-    KeyType,
-    Preimage,
-    Outdated,
-    ExpiredTTL,
-    Crypto(CryptoError),
-}
-
-impl fmt::Display for ValidationError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let printable = match self {
-            ValidationError::KeyType => "bad key type",
-            ValidationError::Preimage => "digest mismatch",
-            ValidationError::Outdated => "metadata is outdated",
-            ValidationError::ExpiredTTL => "expired TTL",
-            ValidationError::Crypto(err) => return err.fmt(f),
-        };
-        write!(f, "{}", printable)
-    }
-}
-
-impl Into<ValidationError> for CryptoError {
-    fn into(self) -> ValidationError {
-        ValidationError::Crypto(self)
-    }
-}
+pub use crate::crypto::errors::ValidationError;
 
 #[derive(Debug)]
 pub enum ServerError {
-    DB(RocksError),
+    DB(DBError),
     Validation(ValidationError),
     Crypto(CryptoError),
     NotFound,
     MetadataDecode,
     PayloadDecode,
     UnsupportedSigScheme,
+    SearchQuery,
+    PubkeyDecode,
+    Cursor,
+    Unauthorized,
+    SnapshotDisabled,
+    SnapshotPath,
     Payment(PaymentError),
     Address(cashaddr::DecodingError, base58::DecodingError),
 }
@@ -58,6 +42,12 @@ impl fmt::Display for ServerError {
             ServerError::MetadataDecode => "metadata decoding error",
             ServerError::PayloadDecode => "payload decoding error",
             ServerError::UnsupportedSigScheme => "signature scheme not supported",
+            ServerError::SearchQuery => "invalid search query",
+            ServerError::PubkeyDecode => "public key must be hex encoded, or a base58 encoded xpub",
+            ServerError::Cursor => "invalid cursor",
+            ServerError::Unauthorized => "unauthorized",
+            ServerError::SnapshotDisabled => "snapshots are disabled",
+            ServerError::SnapshotPath => "snapshot path must be relative to the snapshot directory",
             ServerError::Payment(err) => return err.fmt(f),
             ServerError::Validation(err) => return err.fmt(f),
             ServerError::Address(cash_err, base58_err) => {
@@ -68,6 +58,29 @@ impl fmt::Display for ServerError {
     }
 }
 
+impl ServerError {
+    /// Metric label for the variant, using the label of any wrapped validation or payment error.
+    pub fn label(&self) -> &'static str {
+        match self {
+            ServerError::DB(_) => "db",
+            ServerError::Validation(err) => err.label(),
+            ServerError::Crypto(_) => "crypto",
+            ServerError::NotFound => "not_found",
+            ServerError::MetadataDecode => "metadata_decode",
+            ServerError::PayloadDecode => "payload_decode",
+            ServerError::UnsupportedSigScheme => "unsupported_sig_scheme",
+            ServerError::SearchQuery => "search_query",
+            ServerError::PubkeyDecode => "pubkey_decode",
+            ServerError::Cursor => "cursor",
+            ServerError::Unauthorized => "unauthorized",
+            ServerError::SnapshotDisabled => "snapshot_disabled",
+            ServerError::SnapshotPath => "snapshot_path",
+            ServerError::Payment(err) => err.label(),
+            ServerError::Address(_, _) => "address",
+        }
+    }
+}
+
 impl From<(cashaddr::DecodingError, base58::DecodingError)> for ServerError {
     fn from((cash_err, base58_err): (cashaddr::DecodingError, base58::DecodingError)) -> Self {
         ServerError::Address(cash_err, base58_err)
@@ -86,9 +99,15 @@ impl From<DecodeError> for ServerError {
     }
 }
 
+impl From<DBError> for ServerError {
+    fn from(err: DBError) -> Self {
+        ServerError::DB(err)
+    }
+}
+
 impl From<RocksError> for ServerError {
     fn from(err: RocksError) -> Self {
-        ServerError::DB(err)
+        ServerError::DB(err.into())
     }
 }
 
@@ -98,41 +117,28 @@ impl From<ValidationError> for ServerError {
     }
 }
 
-impl error::ResponseError for CryptoError {
-    fn error_response(&self) -> HttpResponse {
-        match self {
-            CryptoError::PubkeyDeserialization => HttpResponse::BadRequest(),
-            CryptoError::SigDeserialization => HttpResponse::BadRequest(),
-            CryptoError::Verification => HttpResponse::BadRequest(),
-        }
-        .body(self.to_string())
-    }
-}
-
-impl error::ResponseError for ValidationError {
-    fn error_response(&self) -> HttpResponse {
-        match self {
-            ValidationError::Crypto(err_inner) => return err_inner.error_response(),
-            ValidationError::KeyType => HttpResponse::BadRequest(),
-            ValidationError::Preimage => HttpResponse::BadRequest(),
-            ValidationError::Outdated => HttpResponse::BadRequest(),
-            ValidationError::ExpiredTTL => HttpResponse::BadRequest(),
-        }
-        .body(self.to_string())
-    }
-}
-
 impl error::ResponseError for ServerError {
     fn error_response(&self) -> HttpResponse {
         match self {
-            ServerError::Validation(err) => err.error_response(),
+            ServerError::Validation(_) => HttpResponse::BadRequest().body(self.to_string()),
+            ServerError::DB(DBError::SearchDisabled) => {
+                HttpResponse::NotFound().body(self.to_string())
+            }
             // Do not yield sensitive information to clients
             ServerError::DB(_) => HttpResponse::InternalServerError().body("internal db error"),
             ServerError::NotFound => HttpResponse::NotFound().body(self.to_string()),
             ServerError::MetadataDecode => HttpResponse::BadRequest().body(self.to_string()),
             ServerError::PayloadDecode => HttpResponse::BadRequest().body(self.to_string()),
             ServerError::UnsupportedSigScheme => HttpResponse::BadRequest().body(self.to_string()),
-            ServerError::Crypto(err) => err.error_response(),
+            ServerError::SearchQuery => HttpResponse::BadRequest().body(self.to_string()),
+            ServerError::PubkeyDecode => HttpResponse::BadRequest().body(self.to_string()),
+            ServerError::Cursor => HttpResponse::BadRequest().body(self.to_string()),
+            ServerError::Unauthorized => HttpResponse::Unauthorized()
+                .header(WWW_AUTHENTICATE, "Bearer")
+                .body(self.to_string()),
+            ServerError::SnapshotDisabled => HttpResponse::NotFound().body(self.to_string()),
+            ServerError::SnapshotPath => HttpResponse::BadRequest().body(self.to_string()),
+            ServerError::Crypto(_) => HttpResponse::BadRequest().body(self.to_string()),
             ServerError::Payment(err) => err.error_response(),
             ServerError::Address(_, _) => HttpResponse::BadRequest().body(self.to_string()),
         }
@@ -145,7 +151,6 @@ pub enum PaymentError {
     Accept,
     Decode,
     Payload,
-    NoMerchantDat,
     InvalidMerchantDat,
     InvalidAuth,
     NoToken,
@@ -156,6 +161,32 @@ pub enum PaymentError {
     InvalidTx,
     MismatchedNetwork,
     AddrFetchFailed,
+    NodeUnavailable(Duration),
+    InvoiceSigning,
+}
+
+impl PaymentError {
+    /// Metric label for the variant.
+    pub fn label(&self) -> &'static str {
+        match self {
+            PaymentError::Content => "content",
+            PaymentError::Accept => "accept",
+            PaymentError::Decode => "decode",
+            PaymentError::Payload => "payload",
+            PaymentError::InvalidMerchantDat => "invalid_merchant_data",
+            PaymentError::InvalidAuth => "invalid_auth",
+            PaymentError::NoToken => "no_token",
+            PaymentError::URIMalformed => "uri_malformed",
+            PaymentError::NoTx => "no_tx",
+            PaymentError::TxDeserialize(_) => "tx_deserialize",
+            PaymentError::InvalidOutputs => "invalid_outputs",
+            PaymentError::InvalidTx => "invalid_tx",
+            PaymentError::MismatchedNetwork => "mismatched_network",
+            PaymentError::AddrFetchFailed => "addr_fetch_failed",
+            PaymentError::NodeUnavailable(_) => "node_unavailable",
+            PaymentError::InvoiceSigning => "invoice_signing",
+        }
+    }
 }
 
 impl From<PaymentError> for ServerError {
@@ -177,7 +208,6 @@ impl fmt::Display for PaymentError {
             PaymentError::Accept => "not acceptable",
             PaymentError::Decode => "failed to decode body",
             PaymentError::Payload => "failed to receive payload",
-            PaymentError::NoMerchantDat => "no merchant data",
             PaymentError::InvalidMerchantDat => "invalid merchant data",
             PaymentError::NoToken => "no token",
             PaymentError::InvalidAuth => "invalid authorization",
@@ -188,6 +218,8 @@ impl fmt::Display for PaymentError {
             PaymentError::InvalidTx => "invalid tx",
             PaymentError::AddrFetchFailed => "failed to fetch address",
             PaymentError::MismatchedNetwork => "address mismatched with node network",
+            PaymentError::NodeUnavailable(_) => "bitcoin node unavailable",
+            PaymentError::InvoiceSigning => "failed to sign invoice",
         };
         write!(f, "{}", printable)
     }
@@ -198,7 +230,6 @@ impl error::ResponseError for PaymentError {
         match self {
             PaymentError::Accept => HttpResponse::NotAcceptable(),
             PaymentError::Content => HttpResponse::UnsupportedMediaType(),
-            PaymentError::NoMerchantDat => HttpResponse::BadRequest(),
             PaymentError::Payload => HttpResponse::BadRequest(),
             PaymentError::Decode => HttpResponse::BadRequest(),
             PaymentError::InvalidMerchantDat => HttpResponse::BadRequest(),
@@ -211,6 +242,14 @@ impl error::ResponseError for PaymentError {
             PaymentError::InvalidTx => HttpResponse::BadRequest(),
             PaymentError::MismatchedNetwork => HttpResponse::BadRequest(),
             PaymentError::AddrFetchFailed => HttpResponse::InternalServerError(),
+            PaymentError::InvoiceSigning => HttpResponse::InternalServerError(),
+            PaymentError::NodeUnavailable(retry_after) => {
+                // Round up so clients never retry immediately
+                let secs = retry_after.as_secs() + 1;
+                return HttpResponse::ServiceUnavailable()
+                    .header(RETRY_AFTER, secs.to_string())
+                    .body(self.to_string());
+            }
         }
         .body(self.to_string())
     }
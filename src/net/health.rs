@@ -0,0 +1,218 @@
+use std::time::Duration;
+
+use actix_web::{web, HttpResponse};
+use json_rpc::clients::http::HttpConnector;
+use serde_derive::Serialize;
+use tokio::time::timeout;
+
+use crate::{
+    bitcoin::{tx_stream::LastMessage, BitcoinClient, Network},
+    crypto::Address,
+    db::{AsyncDB, Database},
+};
+
+const RPC_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize)]
+pub struct Check {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+impl Check {
+    fn pass() -> Self {
+        Check {
+            ok: true,
+            detail: None,
+        }
+    }
+
+    fn fail(detail: String) -> Self {
+        Check {
+            ok: false,
+            detail: Some(detail),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    ready: bool,
+    db: Check,
+    rpc: Check,
+    zmq: Check,
+}
+
+/// Dependencies checked by the readiness probe.
+pub struct HealthState<D> {
+    pub key_db: AsyncDB<D>,
+    pub bitcoin_client: BitcoinClient<HttpConnector>,
+    pub last_message: LastMessage,
+    pub network: Network,
+    pub zmq_staleness: Duration,
+}
+
+pub async fn live() -> HttpResponse {
+    HttpResponse::Ok().finish()
+}
+
+async fn check_db<D: Database>(key_db: &AsyncDB<D>) -> Check {
+    // Any lookup, hit or miss, proves the database is serving requests
+    let probe = Address {
+        body: vec![0; 20],
+        ..Default::default()
+    };
+    match key_db.get_raw(probe).await {
+        Ok(_) => Check::pass(),
+        Err(err) => Check::fail(err.to_string()),
+    }
+}
+
+async fn check_rpc(bitcoin_client: &BitcoinClient<HttpConnector>, network: &Network) -> Check {
+    let expected = network.chain_name();
+    match timeout(RPC_TIMEOUT, bitcoin_client.get_blockchain_info()).await {
+        Ok(Ok(info)) if info.chain == expected => Check::pass(),
+        Ok(Ok(info)) => Check::fail(format!(
+            "node is on chain {}, expected {}",
+            info.chain, expected
+        )),
+        Ok(Err(err)) => Check::fail(format!("{:?}", err)),
+        Err(_) => Check::fail("rpc timed out".to_string()),
+    }
+}
+
+fn check_zmq(last_message: &LastMessage, staleness: Duration) -> Check {
+    // A quiet chain is only stale once it has been quiet for the threshold since subscribing
+    match (last_message.idle(), last_message.elapsed()) {
+        (Some(idle), _) if idle <= staleness => Check::pass(),
+        (Some(_), Some(elapsed)) => {
+            Check::fail(format!("last message {} seconds ago", elapsed.as_secs()))
+        }
+        (Some(idle), None) => Check::fail(format!(
+            "no message in the {} seconds since subscribing",
+            idle.as_secs()
+        )),
+        (None, _) => Check::fail("not subscribed".to_string()),
+    }
+}
+
+pub async fn ready<D: Database>(state: web::Data<HealthState<D>>) -> HttpResponse {
+    // Run checks
+    let db = check_db(&state.key_db).await;
+    let rpc = check_rpc(&state.bitcoin_client, &state.network).await;
+    let zmq = check_zmq(&state.last_message, state.zmq_staleness);
+
+    // Respond
+    let ready = db.ok && rpc.ok && zmq.ok;
+    let readiness = Readiness {
+        ready,
+        db,
+        rpc,
+        zmq,
+    };
+    if ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bitcoin::{mock::MockNode, tx_stream},
+        db::MemoryDB,
+    };
+    use actix_service::Service;
+    use actix_web::{http::StatusCode, test, App};
+    use futures::{future, prelude::*};
+    use serde_json::Value;
+
+    #[actix_rt::test]
+    async fn test_live() {
+        let mut app =
+            test::init_service(App::new().route("/health/live", web::get().to(live))).await;
+        let req = test::TestRequest::get().uri("/health/live").to_request();
+        let resp = app.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_check_zmq() {
+        let last_message = LastMessage::default();
+        let staleness = Duration::from_secs(60);
+
+        // Not subscribed
+        assert!(!check_zmq(&last_message, staleness).ok);
+
+        // Subscribed with nothing received yet
+        last_message.subscribe();
+        assert!(check_zmq(&last_message, staleness).ok);
+
+        // Fresh message
+        last_message.touch();
+        assert!(check_zmq(&last_message, staleness).ok);
+    }
+
+    #[actix_rt::test]
+    async fn test_ready() {
+        // Follow the mock node's transactions
+        let node = MockNode::start();
+        let last_message = LastMessage::default();
+        let tx_stream = tx_stream::get_tx_stream(node.zmq_url(), last_message.clone())
+            .await
+            .unwrap();
+        actix_rt::spawn(tx_stream.for_each(|_| future::ready(())));
+        node.await_subscriber(&last_message).await;
+
+        let state = HealthState {
+            key_db: AsyncDB::new(MemoryDB::default(), 1),
+            bitcoin_client: node.client(),
+            last_message,
+            network: Network::Regnet,
+            zmq_staleness: Duration::from_secs(60),
+        };
+        let mut app = test::init_service(
+            App::new()
+                .data(state)
+                .route("/health/ready", web::get().to(ready::<MemoryDB>)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/health/ready").to_request();
+        let resp = app.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_ready_unavailable() {
+        // Point the client at a closed port and never deliver a ZMQ message
+        let state = HealthState {
+            key_db: AsyncDB::new(MemoryDB::default(), 1),
+            bitcoin_client: BitcoinClient::new(
+                "http://127.0.0.1:1".to_string(),
+                "username".to_string(),
+                "password".to_string(),
+            ),
+            last_message: LastMessage::default(),
+            network: Network::Regnet,
+            zmq_staleness: Duration::from_secs(60),
+        };
+        let mut app = test::init_service(
+            App::new()
+                .data(state)
+                .route("/health/ready", web::get().to(ready::<MemoryDB>)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/health/ready").to_request();
+        let resp = app.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let readiness: Value = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(readiness["db"]["ok"], true);
+        assert_eq!(readiness["rpc"]["ok"], false);
+        assert_eq!(readiness["zmq"]["ok"], false);
+    }
+}
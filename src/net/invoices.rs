@@ -0,0 +1,395 @@
+//! Invoices fetchable by ID, for wallets paying via a BIP21 URI rather than the 402 body.
+
+use actix_web::{
+    http::header::{AUTHORIZATION, LOCATION, PRAGMA},
+    web, HttpRequest, HttpResponse,
+};
+use serde::{Deserialize, Serialize};
+use url::form_urlencoded;
+
+use crate::{
+    bitcoin::{InvoiceStatus, WalletState},
+    clock::Clock,
+    crypto::token::{generate_token, validate_token},
+    settings::Settings,
+};
+
+use super::{errors::ServerError, json_payments, payments::issue_token};
+
+pub const INVOICE_PATH: &str = "/invoices";
+
+/// Header carrying the claim secret issued alongside an invoice.
+pub const CLAIM_HEADER: &str = "x-claim-secret";
+
+const BIP70_PAYMENT_REQUEST: &str = "application/bitcoincash-paymentrequest";
+const SATS_PER_BCH: u64 = 100_000_000;
+
+/// BIP21 URI paying an address, with `r=` pointing at the payment request.
+pub fn payment_uri(addr_str: &str, amount: u64, request_url: &str) -> String {
+    let request_url: String = form_urlencoded::byte_serialize(request_url.as_bytes()).collect();
+    format!(
+        "{}?amount={}.{:08}&r={}",
+        addr_str,
+        amount / SATS_PER_BCH,
+        amount % SATS_PER_BCH,
+        request_url
+    )
+}
+
+fn claim_message(id: &str) -> Vec<u8> {
+    format!("claim:{}", id).into_bytes()
+}
+
+/// Secret letting the payer collect the token of an invoice by polling its status.
+pub fn claim_secret(id: &str, secret: &str) -> String {
+    let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+    base64::encode_config(
+        &generate_token(&claim_message(id), secret.as_bytes()),
+        url_safe_config,
+    )
+}
+
+fn validate_claim(id: &str, secret: &str, claim: &str) -> bool {
+    let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+    match base64::decode_config(claim, url_safe_config) {
+        Ok(claim) => validate_token(&claim_message(id), secret.as_bytes(), &claim),
+        Err(_) => false,
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct InvoiceView {
+    pub id: String,
+    pub status: InvoiceStatus,
+    pub issued: u64,
+    pub expires: u64,
+    pub uri: String,
+    /// Proof of payment token, once paid and claimed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    /// URL the token is valid for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+}
+
+/// Serve the payment request for the accepted protocol, otherwise the invoice status.
+///
+/// Presenting the claim secret of a paid invoice in the claim header collects its token. The
+/// secret is kept out of the URL so that it isn't recorded in access logs.
+pub async fn get_invoice(
+    req: HttpRequest,
+    id: web::Path<String>,
+    wallet_state: web::Data<WalletState>,
+    clock: web::Data<Clock>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, ServerError> {
+    let invoice = wallet_state.get(&id).ok_or(ServerError::NotFound)?;
+    let status = invoice.status(clock.now() as u64);
+
+    // Negotiate protocol
+    let headers = req.headers();
+    if json_payments::accepts(headers, BIP70_PAYMENT_REQUEST) {
+        return Ok(HttpResponse::Ok()
+            .content_type(BIP70_PAYMENT_REQUEST)
+            .header("Content-Transfer-Encoding", "binary")
+            .body(invoice.payment_request));
+    }
+    if json_payments::accepts(headers, json_payments::PAYMENT_REQUEST) {
+        let payment_request =
+            json_payments::payment_request(&invoice.details, &settings.network, invoice.id);
+        return Ok(HttpResponse::Ok()
+            .content_type(json_payments::PAYMENT_REQUEST)
+            .body(serde_json::to_string(&payment_request).unwrap()));
+    }
+
+    // Check claim
+    let claimed = match headers.get(CLAIM_HEADER).map(|claim| claim.to_str()) {
+        Some(Ok(claim)) if validate_claim(&invoice.id, &settings.secret, claim) => {
+            status == InvoiceStatus::Paid
+        }
+        Some(_) => return Err(ServerError::Unauthorized),
+        None => false,
+    };
+
+    let mut view = InvoiceView {
+        status,
+        issued: invoice.issued(),
+        expires: invoice.expires(),
+        id: invoice.id.clone(),
+        uri: invoice.uri.clone(),
+        token: None,
+        location: None,
+    };
+    if !claimed {
+        return Ok(HttpResponse::Ok().json(view));
+    }
+
+    // Generate token
+    let (token, redirect_url) = issue_token(invoice.merchant_data(), &settings.secret)?;
+    view.token = Some(token.clone());
+    view.location = Some(redirect_url.clone().into_string());
+    Ok(HttpResponse::Ok()
+        .header(LOCATION, redirect_url.into_string())
+        .header(AUTHORIZATION, format!("POP {}", token))
+        .header(PRAGMA, "no-cache")
+        .json(view))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use actix_web::{
+        http::{
+            header::{ACCEPT, LINK},
+            StatusCode,
+        },
+        test, App,
+    };
+    use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut};
+    use bytes::BytesMut;
+    use futures::{future, prelude::*};
+    use prost::Message;
+
+    use super::*;
+    use crate::{
+        bitcoin::{mock::MockNode, tx_stream, PRICE},
+        db::{AsyncDB, MemoryDB},
+        models::bip70::{PaymentDetails, PaymentRequest},
+        net::{payments::CheckPayment, put_key, tests::generate_address_metadata},
+    };
+
+    #[test]
+    fn test_payment_uri() {
+        assert_eq!(
+            payment_uri(
+                "bitcoincash:qq",
+                5,
+                "http://localhost:8080/invoices/ab?x=1"
+            ),
+            "bitcoincash:qq?amount=0.00000005&r=http%3A%2F%2Flocalhost%3A8080%2Finvoices%2Fab%3Fx%3D1"
+        );
+        assert_eq!(
+            payment_uri("bitcoincash:qq", 123_000_000, "r"),
+            "bitcoincash:qq?amount=1.23000000&r=r"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_get_invoice() {
+        // Init testing app
+        let settings = Settings::default();
+        let wallet_state = WalletState::default();
+        let clock = Clock::frozen();
+        let node = MockNode::start();
+        let check_payment = CheckPayment::new(
+            node.client(),
+            wallet_state.clone(),
+            settings.network.clone(),
+            settings.secret.clone(),
+        )
+        .with_clock(clock.clone());
+        let mut app = test::init_service(
+            App::new()
+                .data(settings)
+                .service(
+                    web::resource("/keys/{addr}")
+                        .data(AsyncDB::new(MemoryDB::default(), 1))
+                        .wrap(check_payment)
+                        .route(web::put().to(put_key::<MemoryDB>)),
+                )
+                .service(
+                    web::resource("/invoices/{id}")
+                        .data(wallet_state)
+                        .data(clock.clone())
+                        .route(web::get().to(get_invoice)),
+                ),
+        )
+        .await;
+
+        // Put key with no token
+        let (address_base58, metadata_raw) = generate_address_metadata();
+        let req = test::TestRequest::put()
+            .uri(&format!("http://localhost:8080/keys/{}", address_base58))
+            .set_payload(metadata_raw)
+            .to_request();
+        let mut resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::PAYMENT_REQUIRED);
+        let link = resp
+            .headers()
+            .get(LINK)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string();
+        let mut payload = resp.take_body();
+        let mut invoice_raw = BytesMut::new();
+        while let Some(item) = payload.next().await {
+            invoice_raw.extend_from_slice(&item.unwrap());
+        }
+
+        // Find invoice URL
+        let invoice_url = link
+            .split(", ")
+            .find(|link| link.ends_with("rel=\"invoice\""))
+            .and_then(|link| link.split(|c| c == '<' || c == '>').nth(1))
+            .unwrap()
+            .to_string();
+        assert!(invoice_url.starts_with("http://localhost:8080/invoices/"));
+
+        // Fetch payment request
+        let req = test::TestRequest::get()
+            .uri(&invoice_url)
+            .header(ACCEPT, BIP70_PAYMENT_REQUEST)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(test::read_body(resp).await, invoice_raw.freeze());
+
+        // Fetch status
+        let req = test::TestRequest::get().uri(&invoice_url).to_request();
+        let view: InvoiceView = test::read_response_json(&mut app, req).await;
+        assert_eq!(view.status, InvoiceStatus::Pending);
+        assert!(link.starts_with(&format!("<{}>", view.uri)));
+        assert!(view
+            .uri
+            .contains("?amount=0.00000005&r=http%3A%2F%2Flocalhost"));
+
+        // Status expires with the invoice
+        clock.advance(Duration::from_secs(view.expires - view.issued + 1));
+        let req = test::TestRequest::get().uri(&invoice_url).to_request();
+        let view: InvoiceView = test::read_response_json(&mut app, req).await;
+        assert_eq!(view.status, InvoiceStatus::Expired);
+
+        // Unknown invoice
+        let req = test::TestRequest::get()
+            .uri("/invoices/unknown")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_rt::test]
+    async fn test_claim_broadcast_payment() {
+        // Init testing app
+        let settings = Settings::default();
+        let wallet_state = WalletState::default();
+        let node = MockNode::start();
+        let check_payment = CheckPayment::new(
+            node.client(),
+            wallet_state.clone(),
+            settings.network.clone(),
+            settings.secret.clone(),
+        );
+        let mut app = test::init_service(
+            App::new()
+                .data(settings)
+                .service(
+                    web::resource("/keys/{addr}")
+                        .data(AsyncDB::new(MemoryDB::default(), 1))
+                        .wrap(check_payment)
+                        .route(web::put().to(put_key::<MemoryDB>)),
+                )
+                .service(
+                    web::resource("/invoices/{id}")
+                        .data(wallet_state.clone())
+                        .data(Clock::system())
+                        .route(web::get().to(get_invoice)),
+                ),
+        )
+        .await;
+
+        // Settle invoices from the mock node's transactions
+        let last_message = tx_stream::LastMessage::default();
+        let tx_stream = tx_stream::get_tx_stream(node.zmq_url(), last_message.clone())
+            .await
+            .unwrap();
+        let tx_stream = tx_stream::settle_invoices(tx_stream, wallet_state, Clock::system());
+        actix_rt::spawn(tx_stream.for_each(|_| future::ready(())));
+        node.await_subscriber(&last_message).await;
+
+        // Put key with no token
+        let (address_base58, metadata_raw) = generate_address_metadata();
+        let key_url = format!("http://localhost:8080/keys/{}", address_base58);
+        let req = test::TestRequest::put()
+            .uri(&key_url)
+            .set_payload(metadata_raw.clone())
+            .to_request();
+        let mut resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::PAYMENT_REQUIRED);
+        let claim = resp.headers().get(CLAIM_HEADER).unwrap().clone();
+        let link = resp.headers().get(LINK).unwrap().clone();
+        let invoice_url = link
+            .to_str()
+            .unwrap()
+            .split(", ")
+            .find(|link| link.ends_with("rel=\"invoice\""))
+            .and_then(|link| link.split(|c| c == '<' || c == '>').nth(1))
+            .unwrap()
+            .to_string();
+        let get_claimed = || {
+            test::TestRequest::get()
+                .uri(&invoice_url)
+                .header(CLAIM_HEADER, claim.clone())
+                .to_request()
+        };
+        let mut payload = resp.take_body();
+        let mut invoice_raw = BytesMut::new();
+        while let Some(item) = payload.next().await {
+            invoice_raw.extend_from_slice(&item.unwrap());
+        }
+        let invoice = PaymentRequest::decode(invoice_raw).unwrap();
+        let payment_details =
+            PaymentDetails::decode(&invoice.serialized_payment_details[..]).unwrap();
+
+        // Claim is rejected until paid, and a wrong claim is refused
+        let view: InvoiceView = test::read_response_json(&mut app, get_claimed()).await;
+        assert_eq!(view.status, InvoiceStatus::Pending);
+        assert_eq!(view.token, None);
+        let req = test::TestRequest::get()
+            .uri(&invoice_url)
+            .header(CLAIM_HEADER, "wrong")
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        // Wallet broadcasts the payment itself
+        let p2pkh = &payment_details.outputs[0];
+        node.broadcast(Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Script::new(),
+                sequence: 0xffff_ffff,
+                witness: vec![],
+            }],
+            output: vec![TxOut {
+                value: PRICE,
+                script_pubkey: Script::from(p2pkh.script.clone()),
+            }],
+        });
+
+        // Poll until paid, collecting the token
+        let mut view = None;
+        for _ in 0..500 {
+            let polled: InvoiceView = test::read_response_json(&mut app, get_claimed()).await;
+            if polled.status == InvoiceStatus::Paid {
+                view = Some(polled);
+                break;
+            }
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+        let view = view.expect("invoice was not paid");
+        assert_eq!(view.location.as_deref(), Some(key_url.as_str()));
+
+        // Check token works
+        let req = test::TestRequest::put()
+            .uri(&key_url)
+            .header(AUTHORIZATION, format!("POP {}", view.token.unwrap()))
+            .set_payload(metadata_raw)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,213 @@
+//! BitPay-style JSON Payment Protocol, served alongside BIP70 from the same invoices.
+
+use actix_web::http::{header::ACCEPT, HeaderMap};
+use bitcoin::{util::psbt::serialize::Deserialize as _, Transaction};
+use chrono::{SecondsFormat, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bitcoin::{extract_pubkey_hash, Network},
+    models::bip70::PaymentDetails,
+};
+
+use super::{encode_address, errors::PaymentError};
+
+pub const PAYMENT_REQUEST: &str = "application/payment-request";
+pub const PAYMENT: &str = "application/payment";
+pub const PAYMENT_VERIFICATION: &str = "application/payment-verification";
+pub const PAYMENT_ACK: &str = "application/payment-ack";
+
+const CURRENCY: &str = "BCH";
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JsonPaymentRequest {
+    pub network: String,
+    pub currency: String,
+    pub required_fee_per_byte: f64,
+    pub outputs: Vec<JsonOutput>,
+    pub time: String,
+    pub expires: String,
+    pub memo: String,
+    pub payment_url: String,
+    pub payment_id: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct JsonOutput {
+    pub amount: u64,
+    pub address: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct JsonPayment {
+    pub currency: String,
+    /// Hex encoded transactions.
+    pub transactions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+pub struct JsonPaymentAck {
+    pub payment: JsonPayment,
+    pub memo: String,
+}
+
+/// Whether the `Accept` header lists the media type.
+pub fn accepts(headers: &HeaderMap, media_type: &str) -> bool {
+    headers
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map(|accept| {
+            accept
+                .split(',')
+                .any(|part| part.split(';').next().unwrap_or_default().trim() == media_type)
+        })
+        .unwrap_or(false)
+}
+
+fn iso_time(timestamp: u64) -> String {
+    Utc.timestamp(timestamp as i64, 0)
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Convert BIP70 payment details into a JSON payment request.
+///
+/// Only outputs paying an address are expressible, so the keyserver OP_RETURN is omitted.
+pub fn payment_request(
+    details: &PaymentDetails,
+    network: &Network,
+    payment_id: String,
+) -> JsonPaymentRequest {
+    let outputs = details
+        .outputs
+        .iter()
+        .filter_map(|output| {
+            let pubkey_hash = extract_pubkey_hash(&output.script)?;
+            Some(JsonOutput {
+                amount: output.amount.unwrap_or_default(),
+                address: encode_address(pubkey_hash, network)?,
+            })
+        })
+        .collect();
+    let merchant_url = details
+        .merchant_data
+        .as_ref()
+        .map(|merchant_data| String::from_utf8_lossy(merchant_data).into_owned())
+        .unwrap_or_default();
+
+    JsonPaymentRequest {
+        network: network.chain_name().to_string(),
+        currency: CURRENCY.to_string(),
+        required_fee_per_byte: 1.0,
+        outputs,
+        time: iso_time(details.time),
+        expires: iso_time(details.expires.unwrap_or(details.time)),
+        memo: format!("Payment for {}", merchant_url),
+        payment_url: details.payment_url.clone().unwrap_or_default(),
+        payment_id,
+    }
+}
+
+/// Decode a JSON payment, returning it alongside its first transaction raw and parsed.
+pub fn decode_payment(raw: &[u8]) -> Result<(JsonPayment, Vec<u8>, Transaction), PaymentError> {
+    let payment: JsonPayment = serde_json::from_slice(raw).map_err(|_| PaymentError::Decode)?;
+    if payment.currency != CURRENCY {
+        return Err(PaymentError::Decode);
+    }
+
+    // Assume first tx
+    let tx_hex = payment.transactions.get(0).ok_or(PaymentError::NoTx)?;
+    let tx_raw = hex::decode(tx_hex).map_err(|_| PaymentError::Decode)?;
+    let tx = Transaction::deserialize(&tx_raw)?;
+    Ok((payment, tx_raw, tx))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::HeaderValue;
+
+    use super::*;
+    use crate::{bitcoin::generate_outputs, models::bip70::Output};
+
+    #[test]
+    fn test_accepts() {
+        let mut headers = HeaderMap::new();
+        assert!(!accepts(&headers, PAYMENT_REQUEST));
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/json, application/payment-request;q=0.9"),
+        );
+        assert!(accepts(&headers, PAYMENT_REQUEST));
+        assert!(!accepts(&headers, PAYMENT));
+    }
+
+    #[test]
+    fn test_payment_request() {
+        let pk_hash = [3; 20].to_vec();
+        let details = PaymentDetails {
+            network: Some("regnet".to_string()),
+            outputs: generate_outputs(pk_hash.clone(), "http://localhost", pk_hash.clone()),
+            time: 0,
+            expires: Some(30),
+            memo: None,
+            payment_url: Some("http://localhost/payments".to_string()),
+            merchant_data: Some(b"http://localhost/keys/addr".to_vec()),
+        };
+        let request = payment_request(&details, &Network::Regnet, "id".to_string());
+
+        // OP_RETURN is dropped
+        assert_eq!(
+            request.outputs,
+            vec![JsonOutput {
+                amount: details.outputs[0].amount.unwrap(),
+                address: encode_address(pk_hash, &Network::Regnet).unwrap(),
+            }]
+        );
+        assert_eq!(request.time, "1970-01-01T00:00:00Z");
+        assert_eq!(request.expires, "1970-01-01T00:00:30Z");
+        assert_eq!(request.payment_url, "http://localhost/payments");
+
+        // Unknown scripts are skipped
+        let details = PaymentDetails {
+            outputs: vec![Output {
+                amount: Some(1),
+                script: vec![0; 3],
+            }],
+            ..details
+        };
+        assert!(
+            payment_request(&details, &Network::Regnet, "id".to_string())
+                .outputs
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_decode_payment() {
+        let payment = json_payment("BCH", vec!["zz".to_string()]);
+        match decode_payment(payment.as_bytes()) {
+            Err(PaymentError::Decode) => (),
+            other => panic!("expected decode error, got {:?}", other.map(|_| ())),
+        }
+
+        let payment = json_payment("BTC", vec![]);
+        match decode_payment(payment.as_bytes()) {
+            Err(PaymentError::Decode) => (),
+            other => panic!("expected decode error, got {:?}", other.map(|_| ())),
+        }
+
+        let payment = json_payment("BCH", vec![]);
+        match decode_payment(payment.as_bytes()) {
+            Err(PaymentError::NoTx) => (),
+            other => panic!("expected no tx, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    fn json_payment(currency: &str, transactions: Vec<String>) -> String {
+        serde_json::to_string(&JsonPayment {
+            currency: currency.to_string(),
+            transactions,
+        })
+        .unwrap()
+    }
+}
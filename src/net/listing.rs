@@ -0,0 +1,133 @@
+use actix_web::{web, HttpResponse};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    db::{AsyncDB, Database},
+    settings::Settings,
+};
+
+use super::{encode_address, errors::ServerError};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListedKey {
+    address: String,
+    timestamp: i64,
+    expiry: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListPage {
+    keys: Vec<ListedKey>,
+    next: Option<String>,
+}
+
+pub async fn list_keys<D: Database>(
+    query: web::Query<ListQuery>,
+    db_data: web::Data<AsyncDB<D>>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, ServerError> {
+    // The cursor is the hex encoded body of the last address on the previous page
+    let after = match &query.cursor {
+        Some(cursor) => Some(hex::decode(cursor).map_err(|_| ServerError::Cursor)?),
+        None => None,
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).max(1).min(MAX_LIMIT);
+
+    // List database
+    let summaries = db_data.list(after, limit).await?;
+
+    // Respond
+    let next = if summaries.len() == limit {
+        summaries
+            .last()
+            .map(|summary| hex::encode(&summary.addr_raw))
+    } else {
+        None
+    };
+    let keys = summaries
+        .into_iter()
+        .filter_map(|summary| {
+            Some(ListedKey {
+                address: encode_address(summary.addr_raw, &settings.network)?,
+                timestamp: summary.timestamp,
+                expiry: summary.expiry,
+            })
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(ListPage { keys, next }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypto::Address, db::MemoryDB, models::address_metadata::AddressMetadata, net::tests::*,
+    };
+    use actix_service::Service;
+    use actix_web::{http::StatusCode, test, App};
+    use prost::Message;
+    use serde_json::Value;
+
+    #[actix_rt::test]
+    async fn test_list_keys() {
+        // Init routes
+        let key_db = AsyncDB::new(MemoryDB::default(), 1);
+        let mut app = test::init_service(
+            App::new()
+                .data(key_db.clone())
+                .data(Settings::default())
+                .route("/keys", web::get().to(list_keys::<MemoryDB>)),
+        )
+        .await;
+
+        // Put metadata
+        let mut addresses = Vec::new();
+        for _ in 0..3 {
+            let (address_base58, metadata_raw) = generate_address_metadata();
+            let addr = Address::decode(&address_base58).unwrap();
+            let metadata = AddressMetadata::decode(&metadata_raw[..]).unwrap();
+            key_db.inner().put(&addr, &metadata).unwrap();
+            addresses.push(addr);
+        }
+        addresses.sort_by(|a, b| a.body.cmp(&b.body));
+
+        // Page through every key
+        let mut listed = Vec::new();
+        let mut uri = "/keys?limit=2".to_string();
+        loop {
+            let req = test::TestRequest::get().uri(&uri).to_request();
+            let resp = app.call(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let page: Value = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+            for key in page["keys"].as_array().unwrap() {
+                assert!(key["expiry"].as_i64().unwrap() > key["timestamp"].as_i64().unwrap());
+                listed.push(key["address"].as_str().unwrap().to_string());
+            }
+            match page["next"].as_str() {
+                Some(cursor) => uri = format!("/keys?limit=2&cursor={}", cursor),
+                None => break,
+            }
+        }
+        let expected: Vec<String> = addresses
+            .into_iter()
+            .map(|addr| encode_address(addr.body, &Settings::default().network).unwrap())
+            .collect();
+        assert_eq!(listed, expected);
+
+        // Malformed cursor
+        let req = test::TestRequest::get()
+            .uri("/keys?cursor=xyz")
+            .to_request();
+        let resp = app.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
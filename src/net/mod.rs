@@ -1,42 +1,92 @@
+pub mod admin;
 pub mod errors;
+pub mod health;
+pub mod invoices;
+pub mod json_payments;
+pub mod listing;
 pub mod payments;
 pub mod peer;
+pub mod search;
 
 use actix_web::{web, HttpResponse};
 use bytes::BytesMut;
 use futures::prelude::*;
+use prometheus::{Encoder, TextEncoder};
 use prost::Message;
 
 use crate::{
+    bitcoin::Network,
     crypto::{authentication::validate, ecdsa::Secp256k1, Address},
-    db::KeyDB,
+    db::{AsyncDB, Database},
+    metrics::{KEY_GETS, KEY_PUTS},
     models::address_metadata::{AddressMetadata, Payload},
 };
 
 use errors::ServerError;
 
-pub async fn get_key(
+/// Encode an address body for a network.
+pub fn encode_address(body: Vec<u8>, network: &Network) -> Option<String> {
+    Address {
+        body,
+        network: network.clone().into(),
+        ..Default::default()
+    }
+    .encode()
+    .ok()
+}
+
+pub async fn get_key<D: Database>(
     addr_str: web::Path<String>,
-    db_data: web::Data<KeyDB>,
+    db_data: web::Data<AsyncDB<D>>,
 ) -> Result<HttpResponse, ServerError> {
     // Convert address
     let addr = Address::decode(&addr_str)?;
 
-    // Grab metadata from DB
-    let metadata = db_data.get(&addr)?.ok_or(ServerError::NotFound)?;
-
-    // Encode metadata as hex
-    let mut raw_payload = Vec::with_capacity(metadata.encoded_len());
-    metadata.encode(&mut raw_payload).unwrap();
+    // Grab stored metadata from DB
+    let raw_metadata = db_data.get_raw(addr).await?;
+    let result = if raw_metadata.is_some() {
+        "hit"
+    } else {
+        "miss"
+    };
+    KEY_GETS.with_label_values(&[result]).inc();
+    let raw_metadata = raw_metadata.ok_or(ServerError::NotFound)?;
 
     // Respond
-    Ok(HttpResponse::Ok().body(raw_payload))
+    Ok(HttpResponse::Ok().body(raw_metadata))
+}
+
+pub async fn db_stats<D: Database>(db_data: web::Data<AsyncDB<D>>) -> HttpResponse {
+    HttpResponse::Ok().json(db_data.metrics().snapshot())
+}
+
+pub async fn export_metrics() -> HttpResponse {
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder.encode(&prometheus::gather(), &mut buffer).unwrap();
+    HttpResponse::Ok()
+        .content_type(encoder.format_type())
+        .body(buffer)
+}
+
+pub async fn put_key<D: Database>(
+    addr_str: web::Path<String>,
+    payload: web::Payload,
+    db_data: web::Data<AsyncDB<D>>,
+) -> Result<HttpResponse, ServerError> {
+    let result = store_key(addr_str, payload, db_data).await;
+    let outcome = match &result {
+        Ok(_) => "ok",
+        Err(err) => err.label(),
+    };
+    KEY_PUTS.with_label_values(&[outcome]).inc();
+    result
 }
 
-pub async fn put_key(
+async fn store_key<D: Database>(
     addr_str: web::Path<String>,
     mut payload: web::Payload,
-    db_data: web::Data<KeyDB>,
+    db_data: web::Data<AsyncDB<D>>,
 ) -> Result<HttpResponse, ServerError> {
     // Decode metadata
     let mut metadata_raw = BytesMut::new();
@@ -59,11 +109,8 @@ pub async fn put_key(
     let raw_payload = &metadata.serialized_payload;
     let payload = Payload::decode(&raw_payload[..]).map_err(|_| ServerError::PayloadDecode)?;
 
-    // Check age
-    db_data.check_timestamp(&addr, &payload)??;
-
-    // Put to database
-    db_data.put(&addr, &metadata)?;
+    // Check age and put to database
+    db_data.put_checked(addr, metadata, payload).await??;
 
     // Respond
     Ok(HttpResponse::Ok().finish())
@@ -74,8 +121,9 @@ mod tests {
     use super::*;
     use crate::{
         crypto::{ecdsa::Secp256k1PublicKey, *},
+        db::MemoryDB,
         models::address_metadata::*,
-        SETTINGS,
+        settings::Settings,
     };
     use actix_service::Service;
     use actix_web::{http::StatusCode, test, web, App};
@@ -93,9 +141,12 @@ mod tests {
         let address_raw = public_key.to_raw_address();
 
         // Generate address
-        let address_base58 =
-            Base58Codec::encode(&address_raw, HashType::Key, SETTINGS.network.clone().into())
-                .unwrap();
+        let address_base58 = Base58Codec::encode(
+            &address_raw,
+            HashType::Key,
+            Settings::default().network.into(),
+        )
+        .unwrap();
 
         // Construct header
         let headers = vec![Header {
@@ -149,11 +200,11 @@ mod tests {
     #[actix_rt::test]
     async fn test_index_put_ok() {
         // Init routes
-        let key_db = KeyDB::try_new("./test_db/put_ok").unwrap();
+        let key_db = AsyncDB::new(MemoryDB::default(), 1);
         let mut app = test::init_service(
             App::new()
                 .data(key_db)
-                .route("/keys/{addr}", web::put().to(put_key)),
+                .route("/keys/{addr}", web::put().to(put_key::<MemoryDB>)),
         )
         .await;
 
@@ -170,11 +221,11 @@ mod tests {
     #[actix_rt::test]
     async fn test_index_put_malformed_payload() {
         // Init routes
-        let key_db = KeyDB::try_new("./test_db/put_malformed").unwrap();
+        let key_db = AsyncDB::new(MemoryDB::default(), 1);
         let mut app = test::init_service(
             App::new()
                 .data(key_db)
-                .route("/keys/{addr}", web::put().to(put_key)),
+                .route("/keys/{addr}", web::put().to(put_key::<MemoryDB>)),
         )
         .await;
 
@@ -191,11 +242,11 @@ mod tests {
     #[actix_rt::test]
     async fn test_index_put_invalid_address() {
         // Init routes
-        let key_db = KeyDB::try_new("./test_db/put_invalid_addr").unwrap();
+        let key_db = AsyncDB::new(MemoryDB::default(), 1);
         let mut app = test::init_service(
             App::new()
                 .data(key_db)
-                .route("/keys/{addr}", web::put().to(put_key)),
+                .route("/keys/{addr}", web::put().to(put_key::<MemoryDB>)),
         )
         .await;
 
@@ -212,11 +263,11 @@ mod tests {
     #[actix_rt::test]
     async fn test_index_get_invalid_address() {
         // Init routes
-        let key_db = KeyDB::try_new("./test_db/get_invalid_addr").unwrap();
+        let key_db = AsyncDB::new(MemoryDB::default(), 1);
         let mut app = test::init_service(
             App::new()
                 .data(key_db)
-                .route("/keys/{addr}", web::get().to(get_key)),
+                .route("/keys/{addr}", web::get().to(get_key::<MemoryDB>)),
         )
         .await;
 
@@ -230,11 +281,11 @@ mod tests {
     #[actix_rt::test]
     async fn test_index_get_not_found() {
         // Init routes
-        let key_db = KeyDB::try_new("./test_db/get_not_found").unwrap();
+        let key_db = AsyncDB::new(MemoryDB::default(), 1);
         let mut app = test::init_service(
             App::new()
                 .data(key_db)
-                .route("/keys/{addr}", web::get().to(get_key)),
+                .route("/keys/{addr}", web::get().to(get_key::<MemoryDB>)),
         )
         .await;
 
@@ -250,12 +301,12 @@ mod tests {
     #[actix_rt::test]
     async fn test_index_put_get() {
         // Init routes
-        let key_db = KeyDB::try_new("./test_db/put_get").unwrap();
+        let key_db = AsyncDB::new(MemoryDB::default(), 1);
         let mut app = test::init_service(
             App::new()
                 .data(key_db)
-                .route("/keys/{addr}", web::get().to(get_key))
-                .route("/keys/{addr}", web::put().to(put_key)),
+                .route("/keys/{addr}", web::get().to(get_key::<MemoryDB>))
+                .route("/keys/{addr}", web::put().to(put_key::<MemoryDB>)),
         )
         .await;
 
@@ -1,14 +1,10 @@
-use std::{
-    pin::Pin,
-    str,
-    time::{Duration, SystemTime, UNIX_EPOCH},
-};
+use std::{pin::Pin, str, sync::Arc};
 
 use actix_service::{Service, Transform};
 use actix_web::{
     dev::{Body, ServiceRequest, ServiceResponse},
     http::{
-        header::{HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE, LOCATION, PRAGMA},
+        header::{HeaderValue, ACCEPT, AUTHORIZATION, CONTENT_TYPE, LINK, LOCATION, PRAGMA},
         Method,
     },
     web, Error, HttpRequest, HttpResponse, ResponseError,
@@ -25,28 +21,141 @@ use json_rpc::clients::http::HttpConnector;
 use prost::Message;
 use url::Url;
 
-use crate::{bitcoin::*, models::bip70::*, SETTINGS};
+use crate::{
+    bitcoin::*,
+    clock::Clock,
+    crypto::pki::PaymentSigner,
+    metrics::{
+        INVOICES_ISSUED, INVOICES_SETTLED, PAYMENTS_ACCEPTED, PAYMENTS_REJECTED, SATOSHIS_EARNED,
+    },
+    models::bip70::*,
+    settings::Settings,
+};
 
-use super::errors::*;
+use super::{
+    encode_address,
+    errors::*,
+    invoices::{claim_secret, payment_uri, CLAIM_HEADER, INVOICE_PATH},
+    json_payments,
+};
 
 use crate::crypto::token::*;
 
 const PAYMENT_PATH: &str = "/payments";
 pub const VALID_DURATION: u64 = 30;
 
+const BIP70_PAYMENT: &str = "application/bitcoincash-payment";
+
 /// Payment handler
 pub async fn payment_handler(
     req: HttpRequest,
-    mut payload: web::Payload,
+    payload: web::Payload,
+    data: web::Data<(BitcoinClient<HttpConnector>, WalletState)>,
+    settings: web::Data<Settings>,
+    clock: web::Data<Clock>,
+) -> Result<HttpResponse, ServerError> {
+    // Dispatch on protocol, against invoices pending now
+    let now = clock.now() as u64;
+    let content_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let result = match content_type.as_str() {
+        BIP70_PAYMENT => process_payment(req, payload, data, &settings.secret, now).await,
+        json_payments::PAYMENT => process_json_payment(payload, data, &settings.secret, now).await,
+        json_payments::PAYMENT_VERIFICATION => {
+            return verify_json_payment(payload, data, now).await
+        }
+        _ => Err(PaymentError::Accept.into()),
+    };
+    match &result {
+        Ok(_) => PAYMENTS_ACCEPTED.inc(),
+        Err(err) => PAYMENTS_REJECTED.with_label_values(&[err.label()]).inc(),
+    }
+    result
+}
+
+// Read the request body
+async fn read_body(mut payload: web::Payload) -> Result<BytesMut, ServerError> {
+    let mut body = BytesMut::new();
+    while let Some(item) = payload.next().await {
+        body.extend_from_slice(&item.map_err(|_| ServerError::PayloadDecode)?);
+    }
+    Ok(body)
+}
+
+// Broadcast the payment tx
+async fn broadcast(
+    bitcoin_client: &BitcoinClient<HttpConnector>,
+    tx_raw: &[u8],
+) -> Result<(), PaymentError> {
+    bitcoin_client.send_tx(tx_raw).await.map_err(|err| {
+        if err.is_unavailable() {
+            PaymentError::NodeUnavailable(bitcoin_client.retry_after())
+        } else {
+            PaymentError::InvalidTx
+        }
+    })?;
+    Ok(())
+}
+
+// Issue a token for the merchant URL, returning it with the URL to redirect to
+pub fn issue_token(merchant_data: &[u8], secret: &str) -> Result<(String, Url), PaymentError> {
+    // Generate token
+    let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
+    let token = base64::encode_config(
+        &generate_token(merchant_data, secret.as_bytes()),
+        url_safe_config,
+    );
+
+    // Generate paymentredirect
+    let redirect_url =
+        Url::parse(str::from_utf8(merchant_data).map_err(|_| PaymentError::InvalidMerchantDat)?)
+            .map_err(|_| PaymentError::InvalidMerchantDat)?;
+    Ok((token, redirect_url))
+}
+
+// Settle the invoice pending at `now` paid by the tx, broadcasting it first. Merchant data echoed
+// by the payment must match the invoice.
+async fn settle_payment(
+    data: &(BitcoinClient<HttpConnector>, WalletState),
+    merchant_data: Option<&[u8]>,
+    tx_raw: &[u8],
+    tx: &Transaction,
+    now: u64,
+) -> Result<Invoice, PaymentError> {
+    let (bitcoin_client, wallet_state) = data;
+    let invoice = wallet_state
+        .match_outputs(tx, now)
+        .ok_or(PaymentError::InvalidOutputs)?;
+    if let Some(merchant_data) = merchant_data {
+        if merchant_data != invoice.merchant_data() {
+            return Err(PaymentError::InvalidMerchantDat);
+        }
+    }
+
+    // Send tx
+    broadcast(bitcoin_client, tx_raw).await?;
+
+    // ZMQ may have settled the invoice on seeing the broadcast
+    if let Some(invoice) = wallet_state.settle_outputs(tx, now) {
+        INVOICES_SETTLED.with_label_values(&["payment"]).inc();
+        SATOSHIS_EARNED.inc_by(invoice.value_paid(tx) as i64);
+    }
+    Ok(invoice)
+}
+
+async fn process_payment(
+    req: HttpRequest,
+    payload: web::Payload,
     data: web::Data<(BitcoinClient<HttpConnector>, WalletState)>,
+    secret: &str,
+    now: u64,
 ) -> Result<HttpResponse, ServerError> {
     // Check headers
     let headers = req.headers();
-    if headers.get(CONTENT_TYPE)
-        != Some(&HeaderValue::from_str("application/bitcoincash-payment").unwrap())
-    {
-        return Err(PaymentError::Accept.into());
-    }
     if headers.get(ACCEPT)
         != Some(&HeaderValue::from_str("application/bitcoincash-paymentack").unwrap())
     {
@@ -54,10 +163,7 @@ pub async fn payment_handler(
     }
 
     // Read and parse payment proto
-    let mut payment_raw = BytesMut::new();
-    while let Some(item) = payload.next().await {
-        payment_raw.extend_from_slice(&item.map_err(|_| ServerError::PayloadDecode)?);
-    }
+    let payment_raw = read_body(payload).await?;
     let payment = Payment::decode(&payment_raw[..]).map_err(|_| PaymentError::Decode)?;
 
     // Parse tx
@@ -69,18 +175,8 @@ pub async fn payment_handler(
     // Assume first tx
     let tx = Transaction::deserialize(tx_raw).map_err(PaymentError::from)?;
 
-    // Check outputs
-    let wallet_data = &data.1;
-    if !wallet_data.check_outputs(tx) {
-        return Err(ServerError::Payment(PaymentError::InvalidOutputs));
-    }
-
-    // Send tx
-    let bitcoin_client = &data.0;
-    bitcoin_client
-        .send_tx(tx_raw)
-        .await
-        .map_err(|_| PaymentError::InvalidTx)?;
+    // Check outputs and merchant data, then send tx
+    let invoice = settle_payment(&data, payment.merchant_data.as_deref(), tx_raw, &tx, now).await?;
 
     // Create payment ack
     let memo = Some("Thanks for your custom!".to_string());
@@ -90,23 +186,8 @@ pub async fn payment_handler(
     let mut raw_ack = Vec::with_capacity(payment_ack.encoded_len());
     payment_ack.encode(&mut raw_ack).unwrap();
 
-    // Get merchant data
-    let merchant_data = payment_ack
-        .payment
-        .merchant_data
-        .ok_or(PaymentError::NoMerchantDat)?;
-
-    // Generate token
-    let url_safe_config = base64::Config::new(base64::CharacterSet::UrlSafe, false);
-    let token = base64::encode_config(
-        &generate_token(&merchant_data, SETTINGS.secret.as_bytes()),
-        url_safe_config,
-    );
-
-    // Generate paymentredirect
-    let redirect_url =
-        Url::parse(str::from_utf8(&merchant_data).map_err(|_| PaymentError::InvalidMerchantDat)?)
-            .map_err(|_| PaymentError::InvalidMerchantDat)?;
+    // Generate token for the put the invoice pays for
+    let (token, redirect_url) = issue_token(invoice.merchant_data(), secret)?;
 
     // Generate response
     Ok(HttpResponse::Accepted()
@@ -116,14 +197,97 @@ pub async fn payment_handler(
         .body(raw_ack))
 }
 
+async fn process_json_payment(
+    payload: web::Payload,
+    data: web::Data<(BitcoinClient<HttpConnector>, WalletState)>,
+    secret: &str,
+    now: u64,
+) -> Result<HttpResponse, ServerError> {
+    // Read and parse payment
+    let payment_raw = read_body(payload).await?;
+    let (payment, tx_raw, tx) = json_payments::decode_payment(&payment_raw)?;
+
+    // Check outputs and send tx, the invoice standing in for merchant data
+    let invoice = settle_payment(&data, None, &tx_raw, &tx, now).await?;
+
+    // Generate token
+    let (token, redirect_url) = issue_token(invoice.merchant_data(), secret)?;
+
+    // Generate response
+    let payment_ack = json_payments::JsonPaymentAck {
+        payment,
+        memo: "Thanks for your custom!".to_string(),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(json_payments::PAYMENT_ACK)
+        .header(LOCATION, redirect_url.into_string())
+        .header(AUTHORIZATION, format!("POP {}", token))
+        .header(PRAGMA, "no-cache")
+        .body(serde_json::to_string(&payment_ack).unwrap()))
+}
+
+async fn verify_json_payment(
+    payload: web::Payload,
+    data: web::Data<(BitcoinClient<HttpConnector>, WalletState)>,
+    now: u64,
+) -> Result<HttpResponse, ServerError> {
+    // Read and parse payment
+    let payment_raw = read_body(payload).await?;
+    let (payment, _, tx) = json_payments::decode_payment(&payment_raw)?;
+
+    // Check outputs without consuming the invoice
+    data.1
+        .match_outputs(&tx, now)
+        .ok_or(PaymentError::InvalidOutputs)?;
+
+    let payment_ack = json_payments::JsonPaymentAck {
+        payment,
+        memo: "Payment appears valid".to_string(),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(json_payments::PAYMENT_ACK)
+        .body(serde_json::to_string(&payment_ack).unwrap()))
+}
+
 /*
 Payment middleware
 */
-pub struct CheckPayment(BitcoinClient<HttpConnector>, WalletState);
+pub struct CheckPayment {
+    client: BitcoinClient<HttpConnector>,
+    wallet_state: WalletState,
+    clock: Clock,
+    network: Network,
+    secret: String,
+    signer: Option<Arc<PaymentSigner>>,
+}
 
 impl CheckPayment {
-    pub fn new(client: BitcoinClient<HttpConnector>, wallet_state: WalletState) -> Self {
-        CheckPayment(client, wallet_state)
+    pub fn new(
+        client: BitcoinClient<HttpConnector>,
+        wallet_state: WalletState,
+        network: Network,
+        secret: String,
+    ) -> Self {
+        CheckPayment {
+            client,
+            wallet_state,
+            clock: Clock::default(),
+            network,
+            secret,
+            signer: None,
+        }
+    }
+
+    /// Sign invoices with the merchant certificate chain.
+    pub fn with_signer(mut self, signer: Arc<PaymentSigner>) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Time invoices against the given clock rather than the system clock.
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
     }
 }
 
@@ -142,8 +306,12 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(CheckPaymentMiddleware {
             service,
-            client: self.0.clone(),
-            wallet_state: self.1.clone(),
+            client: self.client.clone(),
+            wallet_state: self.wallet_state.clone(),
+            clock: self.clock.clone(),
+            network: self.network.clone(),
+            secret: self.secret.clone(),
+            signer: self.signer.clone(),
         }))
     }
 }
@@ -151,6 +319,10 @@ pub struct CheckPaymentMiddleware<S> {
     service: S,
     client: BitcoinClient<HttpConnector>,
     wallet_state: WalletState,
+    clock: Clock,
+    network: Network,
+    secret: String,
+    signer: Option<Arc<PaymentSigner>>,
 }
 
 impl<S> Service for CheckPaymentMiddleware<S>
@@ -200,12 +372,27 @@ where
                 // If no token found then generate invoice
 
                 // Valid interval
-                let current_time = SystemTime::now();
-                let expiry_time = current_time + Duration::from_secs(VALID_DURATION);
+                let current_time = self.clock.now() as u64;
+                let expiry_time = current_time + VALID_DURATION;
+
+                // Decode put address
+                let uri = req.uri();
+                let put_addr_path = uri.path();
+                let put_addr_str = &put_addr_path[6..]; // TODO: This is super hacky
+                let put_addr = match Address::decode(put_addr_str) {
+                    Ok(ok) => ok,
+                    Err((cash_err, base58_err)) => {
+                        return Box::pin(err(ServerError::Address(cash_err, base58_err).into()))
+                    }
+                };
 
-                // Get new addr and add to wallet
-                let wallet_state_inner = self.wallet_state.clone();
+                // Generate merchant URL
+                let base_url = format!("{}://{}", scheme, host);
+                let merchant_url = format!("{}{}", base_url, put_addr_path);
+
+                // Get new addr
                 let client_inner = self.client.clone();
+                let network_inner = self.network.clone();
                 let new_addr = async move {
                     let addr_opt = client_inner.get_new_addr().await;
                     match addr_opt {
@@ -215,45 +402,41 @@ where
                                     ServerError::Address(cash_err, base58_err)
                                 })?;
                             let network: Network = addr.network.clone().into();
-                            if network != SETTINGS.network || addr.hash_type != HashType::Key {
+                            if network != network_inner || addr.hash_type != HashType::Key {
                                 return Err(
                                     ServerError::Payment(PaymentError::MismatchedNetwork).into()
                                 );
                                 // TODO: Finer grained error here
                             }
-                            let addr_raw = addr.into_body();
-                            wallet_state_inner.add(addr_raw.clone());
-                            Ok(addr_raw)
+                            Ok(addr.into_body())
                         }
+                        Err(e) if e.is_unavailable() => Err(ServerError::Payment(
+                            PaymentError::NodeUnavailable(client_inner.retry_after()),
+                        )
+                        .into()),
                         Err(_e) => Err(ServerError::Payment(PaymentError::AddrFetchFailed).into()),
                     }
                 };
 
-                // Decode put address
-                let uri = req.uri();
-                let put_addr_path = uri.path();
-                let put_addr_str = &put_addr_path[6..]; // TODO: This is super hacky
-                let put_addr = match Address::decode(put_addr_str) {
-                    Ok(ok) => ok,
-                    Err((cash_err, base58_err)) => {
-                        return Box::pin(err(ServerError::Address(cash_err, base58_err).into()))
-                    }
-                };
-
-                // Generate merchant URL
-                let base_url = format!("{}://{}", scheme, host);
-                let merchant_url = format!("{}{}", base_url, put_addr_path);
+                // Negotiate protocol
+                let json_invoice =
+                    json_payments::accepts(req.headers(), json_payments::PAYMENT_REQUEST);
 
+                let wallet_state = self.wallet_state.clone();
+                let network = self.network.clone();
+                let secret = self.secret.clone();
+                let signer = self.signer.clone();
                 let response = new_addr.and_then(move |addr_raw| {
                     // Generate outputs
-                    let outputs = generate_outputs(addr_raw, &base_url, put_addr.into_body());
+                    let outputs =
+                        generate_outputs(addr_raw.clone(), &base_url, put_addr.into_body());
 
                     // Collect payment details
                     let payment_url = Some(format!("{}{}", base_url, PAYMENT_PATH));
                     let payment_details = PaymentDetails {
-                        network: Some(SETTINGS.network.to_string()),
-                        time: current_time.duration_since(UNIX_EPOCH).unwrap().as_secs(),
-                        expires: Some(expiry_time.duration_since(UNIX_EPOCH).unwrap().as_secs()),
+                        network: Some(network.to_string()),
+                        time: current_time,
+                        expires: Some(expiry_time),
                         memo: None,
                         merchant_data: Some(merchant_url.as_bytes().to_vec()),
                         outputs,
@@ -267,20 +450,60 @@ where
 
                     // Generate payment invoice
                     let pki_type = Some("none".to_string());
-                    let payment_invoice = PaymentRequest {
+                    let mut payment_invoice = PaymentRequest {
                         pki_type,
                         pki_data: None,
                         payment_details_version: Some(1),
                         serialized_payment_details,
                         signature: None,
                     };
+
+                    // Sign payment invoice
+                    if let Some(signer) = signer {
+                        if let Err(sign_err) = signer.sign(&mut payment_invoice) {
+                            error!("failed to sign invoice: {}", sign_err);
+                            return err(ServerError::Payment(PaymentError::InvoiceSigning).into());
+                        }
+                    }
                     let mut payment_invoice_raw = Vec::with_capacity(payment_invoice.encoded_len());
                     payment_invoice.encode(&mut payment_invoice_raw).unwrap();
 
-                    HttpResponse::PaymentRequired()
+                    // Add to wallet
+                    let id = hex::encode(generate_secret(16));
+                    let invoice_url = format!("{}{}/{}", base_url, INVOICE_PATH, id);
+                    let addr_str = encode_address(addr_raw.clone(), &network).unwrap_or_default();
+                    let invoice = Invoice {
+                        id: id.clone(),
+                        details: payment_details,
+                        payment_request: payment_invoice_raw,
+                        uri: payment_uri(&addr_str, PRICE, &invoice_url),
+                        paid: false,
+                    };
+                    let link = format!(
+                        "<{}>; rel=\"payment\", <{}>; rel=\"invoice\"",
+                        invoice.uri, invoice_url
+                    );
+                    let claim = claim_secret(&id, &secret);
+                    wallet_state.add(addr_raw, invoice.clone());
+                    INVOICES_ISSUED.inc();
+
+                    // Generate JSON payment invoice
+                    if json_invoice {
+                        let payment_invoice =
+                            json_payments::payment_request(&invoice.details, &network, id);
+                        return ok(HttpResponse::PaymentRequired()
+                            .content_type(json_payments::PAYMENT_REQUEST)
+                            .header(LINK, link)
+                            .header(CLAIM_HEADER, claim)
+                            .body(serde_json::to_string(&payment_invoice).unwrap()));
+                    }
+
+                    ok(HttpResponse::PaymentRequired()
                         .content_type("application/bitcoincash-paymentrequest")
                         .header("Content-Transfer-Encoding", "binary")
-                        .body(payment_invoice_raw)
+                        .header(LINK, link)
+                        .header(CLAIM_HEADER, claim)
+                        .body(invoice.payment_request))
                 });
 
                 // Respond
@@ -304,7 +527,7 @@ where
         let merchant_url = format!("{}://{}{}", scheme, host, uri.path());
 
         // Validate
-        if !validate_token(merchant_url.as_bytes(), SETTINGS.secret.as_bytes(), &token) {
+        if !validate_token(merchant_url.as_bytes(), self.secret.as_bytes(), &token) {
             Box::pin(ok(req.into_response(
                 ServerError::Payment(PaymentError::InvalidAuth).error_response(),
             )))
@@ -316,9 +539,12 @@ where
 
 #[cfg(test)]
 mod tests {
-    use std::time::{SystemTime, UNIX_EPOCH};
+    use std::time::Duration;
 
-    use actix_web::{http::StatusCode, test, web, App};
+    use actix_web::{
+        http::{header::RETRY_AFTER, StatusCode},
+        test, web, App,
+    };
     use bigdecimal::BigDecimal;
     use bitcoincash_addr::{AddressCodec, Base58Codec, HashType};
     use json_rpc::prelude::*;
@@ -326,8 +552,12 @@ mod tests {
     use serde_json::json;
 
     use crate::{
-        bitcoin::PRICE,
-        db::KeyDB,
+        bitcoin::{
+            mock::{self, MockNode},
+            PRICE,
+        },
+        crypto::pki,
+        db::{AsyncDB, MemoryDB},
         models::bip70::PaymentRequest,
         net::{tests::generate_address_metadata, *},
     };
@@ -347,11 +577,37 @@ mod tests {
         pub hex: String,
     }
 
-    async fn generate_raw_tx(recv_addr: Vec<u8>, _data: Vec<u8>) -> Vec<u8> {
+    async fn read_response(mut body: actix_web::dev::ResponseBody<Body>) -> BytesMut {
+        let mut raw = BytesMut::new();
+        while let Some(item) = body.next().await {
+            raw.extend_from_slice(&item.unwrap());
+        }
+        raw
+    }
+
+    fn check_payment(
+        bitcoin_client: BitcoinClient<HttpConnector>,
+        wallet_state: WalletState,
+    ) -> CheckPayment {
+        let settings = Settings::default();
+        CheckPayment::new(
+            bitcoin_client,
+            wallet_state,
+            settings.network,
+            settings.secret,
+        )
+    }
+
+    // Number of invoices pending now
+    fn pending_count(wallet_state: &WalletState) -> usize {
+        wallet_state.pending(Clock::system().now() as u64).len()
+    }
+
+    async fn generate_raw_tx(rpc_url: &str, recv_addr: Vec<u8>, data: Vec<u8>) -> Vec<u8> {
         let client = HttpClient::new(
-            format!("http://{}:{}", SETTINGS.node_ip.clone(), SETTINGS.rpc_port),
-            Some(SETTINGS.rpc_username.clone()),
-            Some(SETTINGS.rpc_password.clone()),
+            rpc_url.to_string(),
+            Some("username".to_string()),
+            Some("password".to_string()),
         );
 
         // Get unspent output
@@ -381,10 +637,11 @@ mod tests {
         let change = &utxo.amount - &bitcoin_amount - fee;
         let outputs_json = json!([
             {
-                Base58Codec::encode(&recv_addr, HashType::Key, SETTINGS.network.clone().into())
+                Base58Codec::encode(&recv_addr, HashType::Key, mock::NETWORK.into())
                     .unwrap(): bitcoin_amount
             },
-            { &utxo.address: change } // { "data": hex::encode(data[1..]) } // TODO: Add op_return data
+            { &utxo.address: change },
+            { "data": hex::encode(data) }
         ]);
 
         // Get raw transaction
@@ -423,24 +680,23 @@ mod tests {
     #[actix_rt::test]
     async fn test_put_no_token() {
         // Init db
-        let key_db = KeyDB::try_new("./test_db/no_token").unwrap();
+        let key_db = AsyncDB::new(MemoryDB::default(), 1);
 
         // Init wallet
         let wallet_state = WalletState::default();
 
-        // Init Bitcoin client
-        let bitcoin_client = BitcoinClient::new(
-            format!("http://{}:{}", SETTINGS.node_ip.clone(), SETTINGS.rpc_port),
-            SETTINGS.rpc_username.clone(),
-            SETTINGS.rpc_password.clone(),
-        );
+        // Init mock node
+        let node = MockNode::start();
+        let bitcoin_client = node.client();
 
         // Init testing app
+        let clock = Clock::frozen();
+        let check_payment = check_payment(bitcoin_client, wallet_state).with_clock(clock.clone());
         let mut app = test::init_service(
             App::new()
                 .data(key_db)
-                .wrap(CheckPayment::new(bitcoin_client, wallet_state)) // Apply payment check to put key
-                .route("/keys/{addr}", web::put().to(put_key)),
+                .wrap(check_payment) // Apply payment check to put key
+                .route("/keys/{addr}", web::put().to(put_key::<MemoryDB>)),
         )
         .await;
 
@@ -468,13 +724,10 @@ mod tests {
         let payment_details =
             PaymentDetails::decode(&invoice.serialized_payment_details[..]).unwrap();
         assert_eq!(payment_details.network.unwrap(), "regnet".to_string());
-        assert!(payment_details.expires.unwrap() > payment_details.time);
-        assert!(
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs()
-                >= payment_details.time
+        assert_eq!(payment_details.time, clock.now() as u64);
+        assert_eq!(
+            payment_details.expires.unwrap(),
+            payment_details.time + VALID_DURATION
         );
         assert_eq!(
             payment_details.payment_url.unwrap(),
@@ -483,20 +736,55 @@ mod tests {
         assert_eq!(payment_details.merchant_data.unwrap(), key_path.as_bytes())
     }
 
+    #[actix_rt::test]
+    async fn test_put_signed_invoice() {
+        // Init signer from a local test CA
+        let ca = pki::tests::test_ca();
+        let signer = PaymentSigner::from_pem(&ca.chain_pem, &ca.key_pem).unwrap();
+
+        // Init testing app
+        let node = MockNode::start();
+        let check_payment =
+            check_payment(node.client(), WalletState::default()).with_signer(Arc::new(signer));
+        let mut app = test::init_service(
+            App::new()
+                .data(AsyncDB::new(MemoryDB::default(), 1))
+                .wrap(check_payment)
+                .route("/keys/{addr}", web::put().to(put_key::<MemoryDB>)),
+        )
+        .await;
+
+        // Put key with no token
+        let (address_base58, metadata_raw) = generate_address_metadata();
+        let req = test::TestRequest::put()
+            .uri(&format!("http://localhost:8080/keys/{}", address_base58))
+            .set_payload(metadata_raw)
+            .to_request();
+        let mut resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::PAYMENT_REQUIRED);
+
+        // Check invoice is signed by the chain
+        let mut payload = resp.take_body();
+        let mut invoice_raw = BytesMut::new();
+        while let Some(item) = payload.next().await {
+            invoice_raw.extend_from_slice(&item.unwrap());
+        }
+        let invoice = PaymentRequest::decode(invoice_raw).unwrap();
+        assert_eq!(invoice.pki_type.as_deref(), Some(pki::PKI_TYPE));
+        pki::verify(&invoice, &pki::tests::trust(ca.root)).unwrap();
+    }
+
     #[actix_rt::test]
     async fn test_put_payment() {
         // Init db
-        let key_db = KeyDB::try_new("./test_db/payment").unwrap();
+        let key_db = AsyncDB::new(MemoryDB::default(), 1);
 
         // Init wallet
         let wallet_state = WalletState::default();
 
-        // Init Bitcoin client
-        let bitcoin_client = BitcoinClient::new(
-            format!("http://{}:{}", SETTINGS.node_ip.clone(), SETTINGS.rpc_port),
-            SETTINGS.rpc_username.clone(),
-            SETTINGS.rpc_password.clone(),
-        );
+        // Init mock node
+        let node = MockNode::start();
+        let bitcoin_client = node.client();
 
         // Init testing app
         let mut app = test::init_service(
@@ -505,16 +793,15 @@ mod tests {
                     web::scope("/keys").service(
                         web::resource("/{addr}")
                             .data(key_db)
-                            .wrap(CheckPayment::new(
-                                bitcoin_client.clone(),
-                                wallet_state.clone(),
-                            )) // Apply payment check to put key
-                            .route(web::put().to(put_key)),
+                            .wrap(check_payment(bitcoin_client.clone(), wallet_state.clone())) // Apply payment check to put key
+                            .route(web::put().to(put_key::<MemoryDB>)),
                     ),
                 )
                 .service(
                     web::resource("/payments")
-                        .data((bitcoin_client, wallet_state))
+                        .data((bitcoin_client, wallet_state.clone()))
+                        .data(Settings::default())
+                        .data(Clock::system())
                         .route(web::post().to(payment_handler)),
                 ),
         )
@@ -540,7 +827,9 @@ mod tests {
             PaymentDetails::decode(&invoice.serialized_payment_details[..]).unwrap();
         let p2pkh = payment_details.outputs.get(0).unwrap();
         let addr = p2pkh.script[3..23].to_vec();
-        let tx = generate_raw_tx(addr, vec![]).await; // TODO: Add op_return
+        let op_return = payment_details.outputs.get(1).unwrap();
+        let data = op_return.script[2..].to_vec();
+        let tx = generate_raw_tx(node.rpc_url(), addr, data).await;
         let payment = Payment {
             merchant_data: payment_details.merchant_data,
             memo: None,
@@ -569,14 +858,30 @@ mod tests {
         let resp = test::call_service(&mut app, req).await;
         assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
 
+        let post_payment = |payment_raw: Vec<u8>| {
+            test::TestRequest::post()
+                .uri(&payment_url)
+                .set_payload(payment_raw)
+                .header(CONTENT_TYPE, "application/bitcoincash-payment")
+                .header(ACCEPT, "application/bitcoincash-paymentack")
+                .to_request()
+        };
+
+        // Check merchant data for another put is refused before broadcasting
+        let forged = Payment {
+            merchant_data: Some(b"http://localhost:8080/keys/forged".to_vec()),
+            ..payment.clone()
+        };
+        let mut forged_raw = Vec::with_capacity(forged.encoded_len());
+        forged.encode(&mut forged_raw).unwrap();
+        let resp = test::call_service(&mut app, post_payment(forged_raw)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(resp.headers().get(AUTHORIZATION).is_none());
+        assert!(node.mempool().is_empty());
+        assert_eq!(pending_count(&wallet_state), 1);
+
         // Check payment ack and a token
-        let req = test::TestRequest::post()
-            .uri(&payment_url)
-            .set_payload(payment_raw.clone())
-            .header(CONTENT_TYPE, "application/bitcoincash-payment")
-            .header(ACCEPT, "application/bitcoincash-paymentack")
-            .to_request();
-        let mut resp = test::call_service(&mut app, req).await;
+        let mut resp = test::call_service(&mut app, post_payment(payment_raw.clone())).await;
         assert_eq!(resp.status(), StatusCode::ACCEPTED);
         let mut payload = resp.take_body();
         let mut payment_ack_raw = BytesMut::new();
@@ -586,6 +891,9 @@ mod tests {
         let payment_ack = PaymentAck::decode(&payment_ack_raw[..]).unwrap();
         assert_eq!(payment, payment_ack.payment);
 
+        // Check payment was broadcast
+        assert_eq!(node.mempool().len(), 1);
+
         // Check token
         let auth = resp.headers().get(AUTHORIZATION).unwrap();
         let loc = Url::parse(resp.headers().get(LOCATION).unwrap().to_str().unwrap()).unwrap();
@@ -607,5 +915,314 @@ mod tests {
             .to_request();
         let resp = test::call_service(&mut app, req).await;
         assert_eq!(resp.status(), StatusCode::OK);
+
+        // Check posting the payment again issues no further token
+        let resp = test::call_service(&mut app, post_payment(payment_raw)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(resp.headers().get(AUTHORIZATION).is_none());
+        assert_eq!(node.mempool().len(), 1);
+    }
+
+    #[actix_rt::test]
+    async fn test_put_json_payment() {
+        // Init wallet and mock node, payments going through a breaker under test control
+        let wallet_state = WalletState::default();
+        let node = MockNode::start();
+        let bitcoin_client = node.client();
+        let breaker = breaker::CircuitBreaker::new(1, Duration::from_secs(60));
+
+        // Init testing app
+        let mut app = test::init_service(
+            App::new()
+                .service(
+                    web::scope("/keys").service(
+                        web::resource("/{addr}")
+                            .data(AsyncDB::new(MemoryDB::default(), 1))
+                            .wrap(check_payment(bitcoin_client.clone(), wallet_state.clone()))
+                            .route(web::put().to(put_key::<MemoryDB>)),
+                    ),
+                )
+                .service(
+                    web::resource("/payments")
+                        .data((
+                            bitcoin_client.with_breaker(breaker.clone()),
+                            wallet_state.clone(),
+                        ))
+                        .data(Settings::default())
+                        .data(Clock::system())
+                        .route(web::post().to(payment_handler)),
+                ),
+        )
+        .await;
+
+        // Put key with no token, negotiating a JSON invoice
+        let (address_base58, metadata_raw) = generate_address_metadata();
+        let key_url = &format!("http://localhost:8080/keys/{}", address_base58);
+        let req = test::TestRequest::put()
+            .uri(key_url)
+            .header(ACCEPT, json_payments::PAYMENT_REQUEST)
+            .set_payload(metadata_raw.clone())
+            .to_request();
+        let mut resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(
+            resp.headers().get(CONTENT_TYPE).unwrap(),
+            json_payments::PAYMENT_REQUEST
+        );
+        let invoice: json_payments::JsonPaymentRequest =
+            serde_json::from_slice(&read_response(resp.take_body()).await).unwrap();
+        assert_eq!(invoice.network, "regtest");
+        assert_eq!(invoice.outputs.len(), 1);
+        assert_eq!(invoice.outputs[0].amount, PRICE);
+
+        // Create payment
+        let addr = Address::decode(&invoice.outputs[0].address)
+            .unwrap()
+            .into_body();
+        let tx = generate_raw_tx(node.rpc_url(), addr, b"keyserver".to_vec()).await;
+        let payment_raw = serde_json::to_vec(&json_payments::JsonPayment {
+            currency: "BCH".to_string(),
+            transactions: vec![hex::encode(tx)],
+        })
+        .unwrap();
+
+        // Check verification neither broadcasts nor consumes the invoice
+        let req = test::TestRequest::post()
+            .uri(&invoice.payment_url)
+            .header(CONTENT_TYPE, json_payments::PAYMENT_VERIFICATION)
+            .set_payload(payment_raw.clone())
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(AUTHORIZATION).is_none());
+        assert!(node.mempool().is_empty());
+        assert_eq!(pending_count(&wallet_state), 1);
+
+        // Check a failed broadcast leaves the invoice pending
+        let post_payment = || {
+            test::TestRequest::post()
+                .uri(&invoice.payment_url)
+                .header(CONTENT_TYPE, json_payments::PAYMENT)
+                .set_payload(payment_raw.clone())
+                .to_request()
+        };
+        breaker.failure();
+        let resp = test::call_service(&mut app, post_payment()).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(node.mempool().is_empty());
+        assert_eq!(pending_count(&wallet_state), 1);
+        breaker.success();
+
+        // Check payment ack and a token
+        let req = post_payment();
+        let mut resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(CONTENT_TYPE).unwrap(),
+            json_payments::PAYMENT_ACK
+        );
+        assert_eq!(resp.headers().get(LOCATION).unwrap(), key_url.as_str());
+        let auth = resp.headers().get(AUTHORIZATION).unwrap().clone();
+        let payment_ack: json_payments::JsonPaymentAck =
+            serde_json::from_slice(&read_response(resp.take_body()).await).unwrap();
+        assert_eq!(payment_ack.payment.transactions.len(), 1);
+        assert_eq!(node.mempool().len(), 1);
+        assert_eq!(pending_count(&wallet_state), 0);
+
+        // Check token works
+        let req = test::TestRequest::put()
+            .uri(key_url)
+            .header(AUTHORIZATION, auth)
+            .set_payload(metadata_raw)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn test_pay_expired_invoice() {
+        // Init wallet and mock node, invoices timed against a frozen clock
+        let wallet_state = WalletState::default();
+        let node = MockNode::start();
+        let bitcoin_client = node.client();
+        let clock = Clock::frozen();
+
+        // Init testing app
+        let check_payment =
+            check_payment(bitcoin_client.clone(), wallet_state.clone()).with_clock(clock.clone());
+        let mut app = test::init_service(
+            App::new()
+                .service(
+                    web::scope("/keys").service(
+                        web::resource("/{addr}")
+                            .data(AsyncDB::new(MemoryDB::default(), 1))
+                            .wrap(check_payment)
+                            .route(web::put().to(put_key::<MemoryDB>)),
+                    ),
+                )
+                .service(
+                    web::resource("/payments")
+                        .data((bitcoin_client, wallet_state.clone()))
+                        .data(Settings::default())
+                        .data(clock.clone())
+                        .route(web::post().to(payment_handler)),
+                ),
+        )
+        .await;
+
+        // Put key with no token, negotiating a JSON invoice
+        let (address_base58, metadata_raw) = generate_address_metadata();
+        let req = test::TestRequest::put()
+            .uri(&format!("http://localhost:8080/keys/{}", address_base58))
+            .header(ACCEPT, json_payments::PAYMENT_REQUEST)
+            .set_payload(metadata_raw)
+            .to_request();
+        let mut resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::PAYMENT_REQUIRED);
+        let invoice: json_payments::JsonPaymentRequest =
+            serde_json::from_slice(&read_response(resp.take_body()).await).unwrap();
+
+        // Create payment
+        let addr = Address::decode(&invoice.outputs[0].address)
+            .unwrap()
+            .into_body();
+        let tx = generate_raw_tx(node.rpc_url(), addr, b"keyserver".to_vec()).await;
+        let payment_raw = serde_json::to_vec(&json_payments::JsonPayment {
+            currency: "BCH".to_string(),
+            transactions: vec![hex::encode(tx)],
+        })
+        .unwrap();
+
+        // Pay after the invoice expires
+        clock.advance(Duration::from_secs(VALID_DURATION + 1));
+        let req = test::TestRequest::post()
+            .uri(&invoice.payment_url)
+            .header(CONTENT_TYPE, json_payments::PAYMENT)
+            .set_payload(payment_raw)
+            .to_request();
+        let resp = test::call_service(&mut app, req).await;
+
+        // Check the payment is refused before broadcasting
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(resp.headers().get(AUTHORIZATION).is_none());
+        assert!(node.mempool().is_empty());
+        assert!(!wallet_state.get(&invoice.payment_id).unwrap().paid);
+    }
+
+    #[actix_rt::test]
+    async fn test_payment_retry_after_breaker_closes() {
+        // Init wallet and mock node, payments going through a breaker under test control
+        let wallet_state = WalletState::default();
+        let node = MockNode::start();
+        let breaker = breaker::CircuitBreaker::new(1, Duration::from_secs(60));
+        let payments_client = node.client().with_breaker(breaker.clone());
+
+        // Init testing app
+        let mut app = test::init_service(
+            App::new()
+                .service(
+                    web::scope("/keys").service(
+                        web::resource("/{addr}")
+                            .data(AsyncDB::new(MemoryDB::default(), 1))
+                            .wrap(check_payment(node.client(), wallet_state.clone()))
+                            .route(web::put().to(put_key::<MemoryDB>)),
+                    ),
+                )
+                .service(
+                    web::resource("/payments")
+                        .data((payments_client, wallet_state.clone()))
+                        .data(Settings::default())
+                        .data(Clock::system())
+                        .route(web::post().to(payment_handler)),
+                ),
+        )
+        .await;
+
+        // Put key with no token
+        let (address_base58, metadata_raw) = generate_address_metadata();
+        let req = test::TestRequest::put()
+            .uri(&format!("http://localhost:8080/keys/{}", address_base58))
+            .set_payload(metadata_raw)
+            .to_request();
+        let mut resp = test::call_service(&mut app, req).await;
+        let invoice = PaymentRequest::decode(read_response(resp.take_body()).await).unwrap();
+        let payment_details =
+            PaymentDetails::decode(&invoice.serialized_payment_details[..]).unwrap();
+
+        // Create payment
+        let addr = payment_details.outputs[0].script[3..23].to_vec();
+        let data = payment_details.outputs[1].script[2..].to_vec();
+        let tx = generate_raw_tx(node.rpc_url(), addr, data).await;
+        let payment = Payment {
+            merchant_data: payment_details.merchant_data,
+            memo: None,
+            refund_to: vec![],
+            transactions: vec![tx],
+        };
+        let mut payment_raw = Vec::with_capacity(payment.encoded_len());
+        payment.encode(&mut payment_raw).unwrap();
+        let post_payment = || {
+            test::TestRequest::post()
+                .uri("http://localhost:8080/payments")
+                .set_payload(payment_raw.clone())
+                .header(CONTENT_TYPE, "application/bitcoincash-payment")
+                .header(ACCEPT, "application/bitcoincash-paymentack")
+                .to_request()
+        };
+
+        // Open breaker, the payment is refused and the invoice left pending
+        breaker.failure();
+        let resp = test::call_service(&mut app, post_payment()).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(node.mempool().is_empty());
+        assert_eq!(pending_count(&wallet_state), 1);
+
+        // Close breaker, the retry is accepted
+        breaker.success();
+        let resp = test::call_service(&mut app, post_payment()).await;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        assert_eq!(node.mempool().len(), 1);
+        assert_eq!(pending_count(&wallet_state), 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_put_node_unavailable() {
+        // Init Bitcoin client against a closed port, opening the breaker on first failure
+        let bitcoin_client = BitcoinClient::new(
+            "http://127.0.0.1:1".to_string(),
+            "username".to_string(),
+            "password".to_string(),
+        )
+        .with_breaker(breaker::CircuitBreaker::new(1, Duration::from_secs(60)));
+
+        // Init testing app
+        let mut app = test::init_service(
+            App::new()
+                .data(AsyncDB::new(MemoryDB::default(), 1))
+                .wrap(check_payment(bitcoin_client, WalletState::default()))
+                .route("/keys/{addr}", web::put().to(put_key::<MemoryDB>)),
+        )
+        .await;
+
+        // Put key with no token, both reaching and skipping the node
+        let (address_base58, metadata_raw) = generate_address_metadata();
+        let key_path = &format!("http://localhost:8080/keys/{}", address_base58);
+        for _ in 0..2 {
+            let req = test::TestRequest::put()
+                .uri(key_path)
+                .set_payload(metadata_raw.clone())
+                .to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+            let retry_after: u64 = resp
+                .headers()
+                .get(RETRY_AFTER)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert!(retry_after > 0 && retry_after <= 61);
+        }
     }
 }
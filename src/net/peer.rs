@@ -1,15 +1,21 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 use bytes::Bytes;
 use futures::prelude::*;
-use log::{error, warn};
+use log::{error, info, warn};
 use prost::Message;
 use reqwest::{Client, Error as ReqError, Url};
 use url::ParseError;
 
 use crate::{
+    clock::Clock,
     crypto::{authentication::validate, ecdsa::Secp256k1, Address},
-    db::KeyDB,
+    db::{AsyncDB, Database},
+    metrics::PEER_FETCHES,
     models::address_metadata::{AddressMetadata, Payload},
     payments::VALID_DURATION,
 };
@@ -29,20 +35,66 @@ impl From<ParseError> for PeerError {
     }
 }
 
+/// Peers whose advertised metadata is ignored.
+#[derive(Clone, Default)]
+pub struct PeerBans(Arc<RwLock<HashSet<String>>>);
+
+impl PeerBans {
+    pub fn ban(&self, peer_url: String) -> bool {
+        self.0.write().unwrap().insert(peer_url)
+    }
+
+    pub fn unban(&self, peer_url: &str) -> bool {
+        self.0.write().unwrap().remove(peer_url)
+    }
+
+    pub fn is_banned(&self, peer_url: &str) -> bool {
+        self.0.read().unwrap().contains(peer_url)
+    }
+
+    pub fn list(&self) -> Vec<String> {
+        let mut peers: Vec<String> = self.0.read().unwrap().iter().cloned().collect();
+        peers.sort();
+        peers
+    }
+}
+
 #[derive(Clone)]
 pub struct PeerClient {
     client: Arc<Client>,
+    bans: PeerBans,
+    delay: Duration,
+    clock: Clock,
 }
 
 impl Default for PeerClient {
     fn default() -> PeerClient {
+        PeerClient::new(PeerBans::default())
+    }
+}
+
+impl PeerClient {
+    pub fn new(bans: PeerBans) -> PeerClient {
         PeerClient {
             client: Arc::new(Client::new()),
+            bans,
+            delay: Duration::from_secs(VALID_DURATION),
+            clock: Clock::default(),
         }
     }
-}
 
-impl PeerClient {
+    /// Measure the fetch delay against the given clock rather than the system clock.
+    pub fn with_clock(mut self, clock: Clock) -> PeerClient {
+        self.clock = clock;
+        self
+    }
+
+    /// Set the wait between seeing a payment and fetching the metadata from the peer.
+    pub fn with_delay(mut self, delay: Duration) -> PeerClient {
+        self.delay = delay;
+        self
+    }
+
     async fn get_metadata(&self, peer_url: &str, bitcoin_addr: &str) -> Result<Bytes, PeerError> {
         // Construct URL
         let url_str = format!("{}/keys/{}", peer_url, bitcoin_addr);
@@ -63,9 +115,9 @@ impl PeerClient {
         response.bytes().await.map_err(PeerError::ResponseError)
     }
 
-    pub async fn peer_polling(
+    pub async fn peer_polling<D: Database>(
         self,
-        key_db: KeyDB,
+        key_db: AsyncDB<D>,
         key_stream: impl Stream<Item = Result<(String, Address), StreamError>>,
     ) {
         key_stream
@@ -77,6 +129,11 @@ impl PeerClient {
                         Ok(ok) => ok,
                         Err(_) => return,
                     };
+                    if client.bans.is_banned(&peer_addr) {
+                        PEER_FETCHES.with_label_values(&["banned"]).inc();
+                        info!("ignoring banned peer {}", peer_addr);
+                        return;
+                    }
                     let bitcoin_addr_str = match bitcoin_addr.encode() {
                         Ok(ok) => ok,
                         Err(e) => {
@@ -86,15 +143,18 @@ impl PeerClient {
                     };
 
                     // Waiting period
-                    let delay = tokio::time::delay_for(Duration::from_secs(VALID_DURATION));
-                    delay.await;
+                    client.clock.sleep(client.delay).await;
 
                     // Get raw metadata from peer
                     let metadata_raw =
                         match client.get_metadata(&peer_addr, &bitcoin_addr_str).await {
-                            Ok(ok) => ok,
+                            Ok(ok) => {
+                                PEER_FETCHES.with_label_values(&["success"]).inc();
+                                ok
+                            }
                             Err(err) => {
-                                warn!("{:?}", err);
+                                PEER_FETCHES.with_label_values(&["failure"]).inc();
+                                warn!("failed to fetch metadata from {}: {:?}", peer_addr, err);
                                 return;
                             }
                         };
@@ -124,23 +184,96 @@ impl PeerClient {
                         }
                     };
 
-                    match key_db_inner.check_timestamp(&bitcoin_addr, &payload) {
-                        Ok(Err(_)) => {
-                            warn!("refusing to pull outdated metadata");
-                            return;
-                        }
-                        Err(_) => {
-                            error!("failed to check timestamp");
-                            return;
-                        }
+                    match key_db_inner
+                        .put_checked(bitcoin_addr, metadata, payload)
+                        .await
+                    {
+                        Ok(Err(_)) => warn!("refusing to pull outdated metadata"),
+                        Err(e) => error!("failed to put peer metadata {}", e),
                         _ => (),
                     }
-
-                    if let Err(e) = key_db_inner.put(&bitcoin_addr, &metadata) {
-                        error!("failed to put peer metadata {}", e);
-                    };
                 }
             })
             .await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        bitcoin::{
+            generate_outputs,
+            mock::{self, MockNode},
+            tx_stream,
+        },
+        db::MemoryDB,
+        net::{get_key, tests::generate_address_metadata},
+    };
+    use actix_web::{web, App, HttpServer};
+    use bitcoin::{OutPoint, Script, Transaction, TxIn, TxOut};
+
+    #[actix_rt::test]
+    async fn test_peer_replication() {
+        let node = MockNode::start();
+
+        // Serve metadata from the origin keyserver
+        let origin_db = AsyncDB::new(MemoryDB::default(), 1);
+        let (address_base58, metadata_raw) = generate_address_metadata();
+        let addr = Address::decode(&address_base58).unwrap();
+        let metadata = AddressMetadata::decode(&metadata_raw[..]).unwrap();
+        origin_db.inner().put(&addr, &metadata).unwrap();
+        let origin_db_inner = origin_db.clone();
+        let origin = HttpServer::new(move || {
+            App::new()
+                .data(origin_db_inner.clone())
+                .route("/keys/{addr}", web::get().to(get_key::<MemoryDB>))
+        })
+        .workers(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let origin_url = format!("http://{}", origin.addrs()[0]);
+        let _ = origin.run();
+
+        // Follow the mock node's transactions from the replica
+        let replica_db = AsyncDB::new(MemoryDB::default(), 1);
+        let last_message = tx_stream::LastMessage::default();
+        let tx_stream = tx_stream::get_tx_stream(node.zmq_url(), last_message.clone())
+            .await
+            .unwrap();
+        let key_stream =
+            tx_stream::extract_details(tx_stream, mock::NETWORK, "http://replica".to_string());
+        let client = PeerClient::default().with_delay(Duration::from_millis(0));
+        actix_rt::spawn(client.peer_polling(replica_db.clone(), key_stream));
+        node.await_subscriber(&last_message).await;
+
+        // Broadcast a payment advertising the origin
+        let outputs = generate_outputs(vec![0; 20], &origin_url, addr.body.clone());
+        node.broadcast(Transaction {
+            version: 2,
+            lock_time: 0,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: Script::new(),
+                sequence: 0xffff_ffff,
+                witness: vec![],
+            }],
+            output: outputs
+                .into_iter()
+                .map(|output| TxOut {
+                    value: output.amount.unwrap(),
+                    script_pubkey: Script::from(output.script),
+                })
+                .collect(),
+        });
+
+        // Wait for replication
+        for _ in 0..500 {
+            if replica_db.inner().get_raw(&addr).unwrap().is_some() {
+                return;
+            }
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+        panic!("metadata was not replicated");
+    }
+}
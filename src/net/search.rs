@@ -0,0 +1,219 @@
+use actix_web::{web, HttpResponse};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::{
+    bitcoin::Network,
+    db::{
+        pubkeys::normalize_query,
+        search::{Found, Term},
+        AsyncDB, Database,
+    },
+    settings::Settings,
+};
+
+use super::{encode_address, errors::ServerError};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct SearchQuery {
+    kind: Option<String>,
+    header: Option<String>,
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchPage {
+    addresses: Vec<String>,
+    next: Option<String>,
+}
+
+// The cursor is the hex encoded body of the last address on the previous page
+fn decode_cursor(cursor: &Option<String>) -> Result<Option<Vec<u8>>, ServerError> {
+    match cursor {
+        Some(cursor) => Ok(Some(hex::decode(cursor).map_err(|_| ServerError::Cursor)?)),
+        None => Ok(None),
+    }
+}
+
+fn page_limit(limit: Option<usize>) -> usize {
+    limit.unwrap_or(DEFAULT_LIMIT).max(1).min(MAX_LIMIT)
+}
+
+// Encode a page of address bodies for the configured network
+fn search_page(found: Found, network: &Network) -> SearchPage {
+    let addresses = found
+        .addrs
+        .into_iter()
+        .filter_map(|body| encode_address(body, network))
+        .collect();
+    SearchPage {
+        addresses,
+        next: found.next.map(hex::encode),
+    }
+}
+
+pub async fn search<D: Database>(
+    query: web::Query<SearchQuery>,
+    db_data: web::Data<AsyncDB<D>>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, ServerError> {
+    // Collect terms
+    let mut terms = Vec::new();
+    if let Some(kind) = &query.kind {
+        terms.push(Term::Kind(kind.clone()));
+    }
+    if let Some(header) = &query.header {
+        terms.push(Term::header(header).ok_or(ServerError::SearchQuery)?);
+    }
+    if terms.is_empty() {
+        return Err(ServerError::SearchQuery);
+    }
+
+    let after = decode_cursor(&query.cursor)?;
+    let limit = page_limit(query.limit);
+
+    // Search database
+    let found = db_data.search(terms, after, limit).await?;
+
+    // Respond
+    Ok(HttpResponse::Ok().json(search_page(found, &settings.network)))
+}
+
+pub async fn lookup_pubkey<D: Database>(
+    pub_key_str: web::Path<String>,
+    query: web::Query<PageQuery>,
+    db_data: web::Data<AsyncDB<D>>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, ServerError> {
+    // Decode and normalise key
+    let pub_key = normalize_query(&pub_key_str).ok_or(ServerError::PubkeyDecode)?;
+    let after = decode_cursor(&query.cursor)?;
+    let limit = page_limit(query.limit);
+
+    // Search database
+    let found = db_data.lookup_pubkey(pub_key, after, limit).await?;
+
+    // Respond
+    let page = Found::page(found, limit);
+    Ok(HttpResponse::Ok().json(search_page(page, &settings.network)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        crypto::Address, db::MemoryDB, models::address_metadata::AddressMetadata, net::tests::*,
+    };
+    use actix_service::Service;
+    use actix_web::{http::StatusCode, test, App};
+    use prost::Message;
+    use secp256k1::PublicKey;
+    use serde_json::Value;
+
+    #[actix_rt::test]
+    async fn test_search() {
+        // Init routes
+        let key_db = AsyncDB::new(MemoryDB::default(), 1);
+        let mut app = test::init_service(
+            App::new()
+                .data(key_db.clone())
+                .data(Settings::default())
+                .route("/search", web::get().to(search::<MemoryDB>)),
+        )
+        .await;
+
+        // Put metadata
+        for _ in 0..2 {
+            let (address_base58, metadata_raw) = generate_address_metadata();
+            let addr = Address::decode(&address_base58).unwrap();
+            let metadata = AddressMetadata::decode(&metadata_raw[..]).unwrap();
+            key_db.inner().put(&addr, &metadata).unwrap();
+        }
+
+        // First page
+        let req = test::TestRequest::get()
+            .uri("/search?kind=text_utf8&header=Type:EgoBoost&limit=1")
+            .to_request();
+        let resp = app.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let page: Value = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(page["addresses"].as_array().unwrap().len(), 1);
+        let cursor = page["next"].as_str().unwrap().to_string();
+
+        // Second page
+        let req = test::TestRequest::get()
+            .uri(&format!("/search?kind=text_utf8&limit=1&cursor={}", cursor))
+            .to_request();
+        let resp = app.call(req).await.unwrap();
+        let page: Value = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(page["addresses"].as_array().unwrap().len(), 1);
+
+        // No match
+        let req = test::TestRequest::get()
+            .uri("/search?header=Type:avatar")
+            .to_request();
+        let resp = app.call(req).await.unwrap();
+        let page: Value = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert!(page["addresses"].as_array().unwrap().is_empty());
+        assert!(page["next"].is_null());
+
+        // Missing terms
+        let req = test::TestRequest::get().uri("/search").to_request();
+        let resp = app.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_rt::test]
+    async fn test_lookup_pubkey() {
+        // Init routes
+        let key_db = AsyncDB::new(MemoryDB::default(), 1);
+        let mut app = test::init_service(
+            App::new()
+                .data(key_db.clone())
+                .data(Settings::default())
+                .route(
+                    "/lookup/pubkey/{pubkey}",
+                    web::get().to(lookup_pubkey::<MemoryDB>),
+                ),
+        )
+        .await;
+
+        // Put metadata
+        let (address_base58, metadata_raw) = generate_address_metadata();
+        let addr = Address::decode(&address_base58).unwrap();
+        let metadata = AddressMetadata::decode(&metadata_raw[..]).unwrap();
+        key_db.inner().put(&addr, &metadata).unwrap();
+
+        // Lookup by signing key, in either encoding
+        let pub_key = PublicKey::from_slice(&metadata.pub_key).unwrap();
+        let encodings = [
+            pub_key.serialize().to_vec(),
+            pub_key.serialize_uncompressed().to_vec(),
+        ];
+        for raw_key in &encodings {
+            let req = test::TestRequest::get()
+                .uri(&format!("/lookup/pubkey/{}", hex::encode(raw_key)))
+                .to_request();
+            let resp = app.call(req).await.unwrap();
+            assert_eq!(resp.status(), StatusCode::OK);
+            let page: Value = serde_json::from_slice(&test::read_body(resp).await).unwrap();
+            assert_eq!(page["addresses"].as_array().unwrap().len(), 1);
+        }
+
+        // Malformed key
+        let req = test::TestRequest::get()
+            .uri("/lookup/pubkey/xyz")
+            .to_request();
+        let resp = app.call(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,484 @@
+//! Embeddable keyserver, configured by an explicit [`Settings`] rather than the command line.
+
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use actix_cors::Cors;
+use actix_web::{dev::Server, http::header, middleware::Logger, web, App, HttpServer};
+use futures::future::{self, AbortHandle, Future, FutureExt};
+
+use crate::{
+    bitcoin::{breaker::CircuitBreaker, tx_stream, BitcoinClient, WalletState},
+    clock::Clock,
+    crypto::pki::PaymentSigner,
+    db::{self, errors::DBError, AsyncDB, Database, KeyDB},
+    net::{payments::*, *},
+    settings::{KeyListing, Settings},
+};
+
+// Run a background task on the current arbiter, returning a handle to stop it with
+fn spawn_abortable(task: impl Future<Output = ()> + Send + 'static) -> AbortHandle {
+    let (task, handle) = future::abortable(task);
+    actix_rt::Arbiter::current().send(Box::pin(task.map(|_| ())));
+    handle
+}
+
+type OpenDatabase<D> = Box<dyn FnOnce(&Settings, &Clock) -> Result<D, DBError>>;
+
+/// Builder for a keyserver serving the REST API, storing metadata in a RocksDB database unless
+/// given another backend.
+pub struct KeyserverBuilder<D: Database = KeyDB> {
+    settings: Settings,
+    clock: Clock,
+    static_dir: Option<String>,
+    open_db: OpenDatabase<D>,
+    maintenance: fn(&mut web::ServiceConfig),
+}
+
+impl KeyserverBuilder {
+    pub fn new(settings: Settings) -> Self {
+        KeyserverBuilder {
+            settings,
+            clock: Clock::system(),
+            static_dir: Some("./static/".to_string()),
+            open_db: Box::new(|settings: &Settings, clock: &Clock| {
+                Ok(KeyDB::try_new(&settings.db_path)?
+                    .with_search_index(settings.search_index)?
+                    .with_clock(clock.clone()))
+            }),
+            maintenance: admin::rocks_maintenance,
+        }
+    }
+}
+
+impl<D: Database> KeyserverBuilder<D> {
+    /// Store metadata in the given database rather than at `db_path`. The database judges expiry
+    /// by its own clock, and no backend specific maintenance is served.
+    pub fn database<E: Database>(self, database: E) -> KeyserverBuilder<E> {
+        KeyserverBuilder {
+            settings: self.settings,
+            clock: self.clock,
+            static_dir: self.static_dir,
+            open_db: Box::new(move |_: &Settings, _: &Clock| Ok(database)),
+            maintenance: |_| (),
+        }
+    }
+
+    /// Use the given clock in place of the system clock.
+    pub fn clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Serve the web interface from a directory, or not at all.
+    pub fn static_dir(mut self, static_dir: Option<String>) -> Self {
+        self.static_dir = static_dir;
+        self
+    }
+
+    /// Open the database, connect to the node and start serving.
+    ///
+    /// Must be called from within a running actix system.
+    pub fn start(self) -> io::Result<Keyserver> {
+        let KeyserverBuilder {
+            settings,
+            clock,
+            static_dir,
+            open_db,
+            maintenance,
+        } = self;
+
+        // Settings built in code bypass the checks made when loading them
+        settings
+            .validate()
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+
+        // Open DB
+        let key_db = AsyncDB::new(
+            open_db(&settings, &clock)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?,
+            settings.db_concurrency,
+        );
+
+        // Setup expiry sweeper
+        let sweeper = db::sweeper::sweep_expired(
+            key_db.clone(),
+            Duration::from_secs(settings.sweep_interval),
+        );
+        let sweeper = spawn_abortable(sweeper);
+
+        // Init wallet
+        let wallet_state = WalletState::default();
+
+        // Load invoice signer
+        let signer = match (&settings.pki_cert_chain, &settings.pki_key) {
+            (Some(chain_path), Some(key_path)) => Some(Arc::new(
+                PaymentSigner::from_pem_files(chain_path, key_path)
+                    .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?,
+            )),
+            _ => None,
+        };
+
+        // Init Bitcoin client
+        let bitcoin_client = BitcoinClient::new(
+            format!("http://{}:{}", settings.node_ip, settings.rpc_port),
+            settings.rpc_username.clone(),
+            settings.rpc_password.clone(),
+        )
+        .with_breaker(CircuitBreaker::new(
+            settings.breaker_threshold,
+            Duration::from_secs(settings.breaker_cooldown),
+        ));
+
+        // Init ZMQ, reconnecting in the background while the node is unreachable, and settling
+        // invoices paid by wallets broadcasting themselves
+        let last_message = tx_stream::LastMessage::default();
+        let tx_stream = tx_stream::reconnecting_tx_stream(
+            format!("tcp://{}:{}", settings.node_ip, settings.zmq_port),
+            last_message.clone(),
+        );
+        let tx_stream = tx_stream::settle_invoices(tx_stream, wallet_state.clone(), clock.clone());
+        let key_stream = tx_stream::extract_details(
+            tx_stream,
+            settings.network.clone(),
+            format!("http://{}", settings.bind),
+        );
+
+        // Peer client
+        let bans = peer::PeerBans::default();
+        let client = peer::PeerClient::new(bans.clone()).with_clock(clock.clone());
+
+        // Setup peer polling logic
+        let peer_polling = client.peer_polling(key_db.clone(), key_stream);
+        let peer_polling = spawn_abortable(peer_polling);
+
+        // Init REST server
+        let bind = settings.bind.clone();
+        let server = HttpServer::new(move || {
+            let key_db_inner = key_db.clone();
+            let wallet_state_inner = wallet_state.clone();
+            let bitcoin_client_inner = bitcoin_client.clone();
+            let bans_inner = bans.clone();
+            let settings_inner = settings.clone();
+            let static_dir_inner = static_dir.clone();
+            let health_state = health::HealthState {
+                key_db: key_db.clone(),
+                bitcoin_client: bitcoin_client.clone(),
+                last_message: last_message.clone(),
+                network: settings.network.clone(),
+                zmq_staleness: Duration::from_secs(settings.zmq_staleness),
+            };
+
+            // Init payment check
+            let mut check_payment = CheckPayment::new(
+                bitcoin_client.clone(),
+                wallet_state.clone(),
+                settings.network.clone(),
+                settings.secret.clone(),
+            )
+            .with_clock(clock.clone());
+            if let Some(signer) = &signer {
+                check_payment = check_payment.with_signer(signer.clone());
+            }
+
+            // Init CORs
+            let cors = Cors::new()
+                .allowed_methods(vec!["GET", "PUT", "POST"])
+                .allowed_headers(vec![
+                    header::AUTHORIZATION,
+                    header::CONTENT_TYPE,
+                    header::HeaderName::from_static(invoices::CLAIM_HEADER),
+                ])
+                .expose_headers(vec![
+                    header::AUTHORIZATION,
+                    header::ACCEPT,
+                    header::LOCATION,
+                    header::LINK,
+                    header::HeaderName::from_static(invoices::CLAIM_HEADER),
+                ])
+                .finish();
+
+            // Init app
+            App::new()
+                .wrap(Logger::default())
+                .wrap(Logger::new("%a %{User-Agent}i"))
+                .wrap(cors)
+                .data(settings_inner.clone())
+                .data(clock.clone())
+                .service(
+                    // Database metrics
+                    web::resource("/stats/db")
+                        .data(key_db_inner.clone())
+                        .route(web::get().to(db_stats::<D>)),
+                )
+                .configure(|cfg| {
+                    // Admin scope
+                    if let Some(admin_key) = &settings_inner.admin_key {
+                        admin::configure(
+                            cfg,
+                            admin_key.clone(),
+                            settings_inner.key_listing,
+                            key_db_inner.clone(),
+                            wallet_state_inner.clone(),
+                            bans_inner,
+                            maintenance,
+                        );
+                    }
+                })
+                .service(
+                    // Health probes
+                    web::scope("/health")
+                        .data(health_state)
+                        .route("/live", web::get().to(health::live))
+                        .route("/ready", web::get().to(health::ready::<D>)),
+                )
+                .service(
+                    // Prometheus metrics
+                    web::resource("/metrics").route(web::get().to(export_metrics)),
+                )
+                .service(
+                    // Search endpoint
+                    web::resource("/search")
+                        .data(key_db_inner.clone())
+                        .route(web::get().to(search::search::<D>)),
+                )
+                .service(
+                    // Public key lookup endpoint
+                    web::resource("/lookup/pubkey/{pubkey}")
+                        .data(key_db_inner.clone())
+                        .route(web::get().to(search::lookup_pubkey::<D>)),
+                )
+                .service(
+                    // Key scope
+                    web::scope("/keys")
+                        .configure(|cfg| {
+                            // Key listing
+                            if settings_inner.key_listing == KeyListing::Public {
+                                cfg.service(
+                                    web::resource("")
+                                        .data(key_db_inner.clone())
+                                        .route(web::get().to(listing::list_keys::<D>)),
+                                );
+                            }
+                        })
+                        .service(
+                            web::resource("/{addr}")
+                                .data(key_db_inner)
+                                .wrap(check_payment) // Apply payment check to put key
+                                .route(web::get().to(get_key::<D>))
+                                .route(web::put().to(put_key::<D>)),
+                        ),
+                )
+                .service(
+                    // Invoice endpoint
+                    web::resource("/invoices/{id}")
+                        .data(wallet_state_inner.clone())
+                        .route(web::get().to(invoices::get_invoice)),
+                )
+                .service(
+                    // Payment endpoint
+                    web::resource("/payments")
+                        .data((bitcoin_client_inner, wallet_state_inner))
+                        .route(web::post().to(payment_handler)),
+                )
+                .configure(|cfg| {
+                    // Web interface
+                    if let Some(static_dir) = static_dir_inner {
+                        cfg.service(
+                            actix_files::Files::new("/", static_dir).index_file("index.html"),
+                        );
+                    }
+                })
+        })
+        .bind(&bind)?;
+        let addrs = server.addrs();
+
+        Ok(Keyserver {
+            server: server.run(),
+            addrs,
+            tasks: vec![sweeper, peer_polling],
+        })
+    }
+}
+
+/// Handle to a running keyserver.
+pub struct Keyserver {
+    server: Server,
+    addrs: Vec<SocketAddr>,
+    tasks: Vec<AbortHandle>,
+}
+
+impl Keyserver {
+    /// Addresses the REST API is bound to.
+    pub fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    // Stop the expiry sweeper, and the ZMQ stream feeding peer polling
+    fn abort_tasks(&self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+
+    /// Stop serving and the background tasks, optionally waiting for in-flight requests.
+    pub async fn stop(&self, graceful: bool) {
+        self.abort_tasks();
+        self.server.stop(graceful).await
+    }
+
+    /// Serve until the server is stopped, then stop the background tasks.
+    pub async fn wait(self) -> io::Result<()> {
+        let result = self.server.clone().await;
+        self.abort_tasks();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bitcoin::{consensus::encode::serialize, OutPoint, Script, Transaction, TxIn, TxOut};
+    use keyserver_client::{metadata::*, KeyserverClient};
+    use keyserver_core::Network;
+    use secp256k1::rand;
+
+    use super::*;
+    use crate::{bitcoin::mock::MockNode, db::MemoryDB};
+
+    fn test_settings(name: &str) -> Settings {
+        Settings {
+            bind: "127.0.0.1:0".to_string(),
+            db_path: format!("./test_db/{}", name),
+            ..Default::default()
+        }
+    }
+
+    // Port of a URL ending in `:port`
+    fn url_port(url: &str) -> u16 {
+        url.rsplit(':').next().unwrap().parse().unwrap()
+    }
+
+    #[actix_rt::test]
+    async fn test_client_put_get() {
+        let _ = std::fs::remove_dir_all("./test_db/server_client");
+
+        // Start a keyserver backed by a mock node
+        let node = MockNode::start();
+        let server = KeyserverBuilder::new(Settings {
+            rpc_port: url_port(node.rpc_url()),
+            zmq_port: url_port(node.zmq_url()),
+            ..test_settings("server_client")
+        })
+        .static_dir(None)
+        .start()
+        .unwrap();
+        let keyserver_url = format!("http://{}", server.addrs()[0]);
+
+        // Sign metadata
+        let secp = secp256k1::Secp256k1::new();
+        let (secret_key, public_key) = secp.generate_keypair(&mut rand::thread_rng());
+        let addr = key_address(&public_key, Network::Regtest);
+        let metadata = sign_payload(&build_payload(vec![], 3000), &secret_key);
+
+        // Put, paying the invoice outputs exactly
+        let client = KeyserverClient::new();
+        client
+            .put_metadata(&keyserver_url, &addr, &metadata, |outputs| async move {
+                let tx = Transaction {
+                    version: 2,
+                    lock_time: 0,
+                    input: vec![TxIn {
+                        previous_output: OutPoint::null(),
+                        script_sig: Script::new(),
+                        sequence: 0xffff_ffff,
+                        witness: vec![],
+                    }],
+                    output: outputs
+                        .into_iter()
+                        .map(|output| TxOut {
+                            value: output.amount.unwrap_or_default(),
+                            script_pubkey: Script::from(output.script),
+                        })
+                        .collect(),
+                };
+                Ok::<_, String>(vec![serialize(&tx)])
+            })
+            .await
+            .unwrap();
+
+        // The payment was broadcast via the node
+        assert_eq!(node.mempool().len(), 1);
+
+        // Fetch and verify
+        let fetched = client.get_metadata(&keyserver_url, &addr).await.unwrap();
+        assert_eq!(fetched, metadata);
+
+        server.stop(true).await;
+        let _ = std::fs::remove_dir_all("./test_db/server_client");
+    }
+
+    #[actix_rt::test]
+    async fn test_two_keyservers() {
+        let _ = std::fs::remove_dir_all("./test_db/server_first");
+        let _ = std::fs::remove_dir_all("./test_db/server_second");
+
+        // Start two keyservers with different configs in one process
+        let first = KeyserverBuilder::new(test_settings("server_first"))
+            .static_dir(None)
+            .start()
+            .unwrap();
+        let second = KeyserverBuilder::new(Settings {
+            key_listing: KeyListing::Disabled,
+            ..test_settings("server_second")
+        })
+        .static_dir(None)
+        .start()
+        .unwrap();
+        assert_ne!(first.addrs(), second.addrs());
+
+        // Check each serves its own config
+        let client = reqwest::Client::new();
+        let get_status = |server: &Keyserver, path: &str| {
+            let url = format!("http://{}{}", server.addrs()[0], path);
+            let request = client.get(&url).send();
+            async move { request.await.unwrap().status().as_u16() }
+        };
+        assert_eq!(get_status(&first, "/health/live").await, 200);
+        assert_eq!(get_status(&second, "/health/live").await, 200);
+        assert_eq!(get_status(&first, "/keys").await, 200);
+        assert_eq!(get_status(&second, "/keys").await, 404);
+
+        first.stop(true).await;
+        second.stop(true).await;
+        let _ = std::fs::remove_dir_all("./test_db/server_first");
+        let _ = std::fs::remove_dir_all("./test_db/server_second");
+    }
+
+    #[actix_rt::test]
+    async fn test_in_memory_keyserver() {
+        let _ = std::fs::remove_dir_all("./test_db/server_memory");
+
+        // Start a keyserver backed by memory rather than RocksDB
+        let server = KeyserverBuilder::new(Settings {
+            admin_key: Some("admin".to_string()),
+            ..test_settings("server_memory")
+        })
+        .database(MemoryDB::default())
+        .static_dir(None)
+        .start()
+        .unwrap();
+        assert!(!std::path::Path::new("./test_db/server_memory").exists());
+
+        // Generic routes are served, RocksDB maintenance is not
+        let client = reqwest::Client::new();
+        let get_status = |path: &str| {
+            let url = format!("http://{}{}", server.addrs()[0], path);
+            let request = client.get(&url).bearer_auth("admin").send();
+            async move { request.await.unwrap().status().as_u16() }
+        };
+        assert_eq!(get_status("/keys").await, 200);
+        assert_eq!(get_status("/admin/settings").await, 200);
+        assert_eq!(get_status("/admin/db/stats").await, 404);
+
+        server.stop(true).await;
+    }
+}
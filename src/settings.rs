@@ -1,10 +1,24 @@
+use std::path::PathBuf;
+
 use clap::App;
 use config::{Config, ConfigError, File};
-use serde_derive::Deserialize;
+use serde_derive::{Deserialize, Serialize};
 
 use crate::bitcoin::Network;
 
-#[derive(Debug, Deserialize)]
+/// Shortest admin key accepted, as it is the only authentication of the admin API.
+pub const MIN_ADMIN_KEY_LEN: usize = 16;
+
+/// Exposure of the `GET /keys` listing.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyListing {
+    Public,
+    Admin,
+    Disabled,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Settings {
     pub bind: String,
     pub node_ip: String,
@@ -15,33 +29,66 @@ pub struct Settings {
     pub secret: String,
     pub db_path: String,
     pub network: Network,
+    pub sweep_interval: u64,
+    pub db_concurrency: usize,
+    pub search_index: bool,
+    pub key_listing: KeyListing,
+    pub admin_key: Option<String>,
+    pub snapshot_dir: Option<String>,
+    pub zmq_staleness: u64,
+    pub breaker_threshold: u32,
+    pub breaker_cooldown: u64,
+    pub pki_cert_chain: Option<String>,
+    pub pki_key: Option<String>,
+}
+
+fn home_dir() -> Result<PathBuf, ConfigError> {
+    dirs::home_dir().ok_or_else(|| ConfigError::Message("no home directory".to_string()))
+}
+
+fn set_defaults(s: &mut Config) -> Result<(), ConfigError> {
+    s.set_default("bind", "127.0.0.1:8080")?;
+    s.set_default("node_ip", "127.0.0.1")?;
+    s.set_default("rpc_port", "18443")?;
+    s.set_default("rpc_username", "username")?;
+    s.set_default("rpc_password", "password")?;
+    s.set_default("zmq_port", "28332")?;
+    s.set_default("secret", "secret")?;
+    let mut default_db = home_dir()?;
+    default_db.push(".keyserver-rust/db");
+    s.set_default("db_path", default_db.to_str())?;
+    s.set_default("network", "regnet")?;
+    s.set_default("sweep_interval", "60")?;
+    s.set_default("db_concurrency", "16")?;
+    s.set_default("search_index", "false")?;
+    s.set_default("key_listing", "public")?;
+    s.set_default("zmq_staleness", "600")?;
+    s.set_default("breaker_threshold", "3")?;
+    s.set_default("breaker_cooldown", "30")?;
+    Ok(())
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        let mut s = Config::new();
+        set_defaults(&mut s)
+            .and_then(|_| s.try_into())
+            .expect("invalid default settings")
+    }
 }
 
 impl Settings {
+    /// Load settings from the config file and command line, over the defaults.
     pub fn new() -> Result<Self, ConfigError> {
         let mut s = Config::new();
 
         // Set defaults
         let yaml = load_yaml!("cli.yml");
         let matches = App::from_yaml(yaml).get_matches();
-        let home_dir = match dirs::home_dir() {
-            Some(some) => some,
-            None => return Err(ConfigError::Message("no home directory".to_string())),
-        };
-        s.set_default("bind", "127.0.0.1:8080")?;
-        s.set_default("node_ip", "127.0.0.1")?;
-        s.set_default("rpc_port", "18443")?;
-        s.set_default("rpc_username", "username")?;
-        s.set_default("rpc_password", "password")?;
-        s.set_default("zmq_port", "28332")?;
-        s.set_default("secret", "secret")?;
-        let mut default_db = home_dir.clone();
-        default_db.push(".keyserver-rust/db");
-        s.set_default("db_path", default_db.to_str())?;
-        s.set_default("network", "regnet")?;
+        set_defaults(&mut s)?;
 
         // Load config from file
-        let mut default_config = home_dir;
+        let mut default_config = home_dir()?;
         default_config.push(".keyserver-rust/config");
         let default_config_str = default_config.to_str().unwrap();
         let config_path = matches.value_of("config").unwrap_or(default_config_str);
@@ -92,6 +139,86 @@ impl Settings {
             s.set("network", db_path)?;
         }
 
-        s.try_into()
+        // Set the expiry sweep interval
+        if let Ok(sweep_interval) = value_t!(matches, "sweep-interval", i64) {
+            s.set("sweep_interval", sweep_interval)?;
+        }
+
+        // Set the database concurrency limit
+        if let Ok(db_concurrency) = value_t!(matches, "db-concurrency", i64) {
+            s.set("db_concurrency", db_concurrency)?;
+        }
+
+        // Enable the search index from cmd line
+        if matches.is_present("search-index") {
+            s.set("search_index", true)?;
+        }
+
+        // Set the key listing exposure from cmd line
+        if let Some(key_listing) = matches.value_of("key-listing") {
+            s.set("key_listing", key_listing)?;
+        }
+
+        // Set the admin key from cmd line
+        if let Some(admin_key) = matches.value_of("admin-key") {
+            s.set("admin_key", admin_key)?;
+        }
+
+        // Set the admin snapshot directory from cmd line
+        if let Some(snapshot_dir) = matches.value_of("snapshot-dir") {
+            s.set("snapshot_dir", snapshot_dir)?;
+        }
+
+        // Set the ZMQ staleness threshold from cmd line
+        if let Ok(zmq_staleness) = value_t!(matches, "zmq-staleness", i64) {
+            s.set("zmq_staleness", zmq_staleness)?;
+        }
+
+        // Set the circuit breaker threshold from cmd line
+        if let Ok(breaker_threshold) = value_t!(matches, "breaker-threshold", i64) {
+            s.set("breaker_threshold", breaker_threshold)?;
+        }
+
+        // Set the circuit breaker cooldown from cmd line
+        if let Ok(breaker_cooldown) = value_t!(matches, "breaker-cooldown", i64) {
+            s.set("breaker_cooldown", breaker_cooldown)?;
+        }
+
+        // Set the invoice signing certificate chain from cmd line
+        if let Some(pki_cert_chain) = matches.value_of("pki-cert-chain") {
+            s.set("pki_cert_chain", pki_cert_chain)?;
+        }
+
+        // Set the invoice signing key from cmd line
+        if let Some(pki_key) = matches.value_of("pki-key") {
+            s.set("pki_key", pki_key)?;
+        }
+
+        let settings: Settings = s.try_into()?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Reject values which would only fail, or be unsafe, once the server is running.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.sweep_interval == 0 {
+            return Err(ConfigError::Message(
+                "sweep_interval must be at least one second".to_string(),
+            ));
+        }
+        if let Some(admin_key) = &self.admin_key {
+            if admin_key.len() < MIN_ADMIN_KEY_LEN {
+                return Err(ConfigError::Message(format!(
+                    "admin_key must be at least {} characters",
+                    MIN_ADMIN_KEY_LEN
+                )));
+            }
+        }
+        if self.pki_cert_chain.is_some() != self.pki_key.is_some() {
+            return Err(ConfigError::Message(
+                "pki_cert_chain and pki_key must be set together".to_string(),
+            ));
+        }
+        Ok(())
     }
 }